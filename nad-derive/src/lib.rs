@@ -0,0 +1,142 @@
+//! Derive `IntoLua` and `FromLua` for structs
+//!
+//! - struct with named fields is a table keyed by the field names
+//! - tuple struct is an array table, the `n`th field at index `n`
+//! - unit struct is an empty table
+//!
+//! `IntoLuaMulti` and `FromLuaMulti` are derived too, as a single value
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Generics, Index};
+
+#[proc_macro_derive(IntoLua)]
+pub fn derive_into_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_lua(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FromLua)]
+pub fn derive_from_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_lua(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn struct_fields(input: &DeriveInput, derive: &str) -> syn::Result<Fields> {
+    match &input.data {
+        Data::Struct(data) => Ok(data.fields.clone()),
+        _ => Err(syn::Error::new(
+            Span::call_site(),
+            format!("#[derive({})] only supports structs", derive),
+        )),
+    }
+}
+
+/// bound every type parameter with `bound`
+fn add_bound(mut generics: Generics, bound: TokenStream2) -> Generics {
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+    generics
+}
+
+fn expand_into_lua(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = struct_fields(&input, "IntoLua")?;
+    let name = &input.ident;
+    let generics = add_bound(input.generics.clone(), quote!(::nad::IntoLua));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let entries = match &fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|f| {
+                let ident = f.ident.as_ref().unwrap();
+                let key = ident.to_string();
+                quote! {
+                    (
+                        ::nad::Value::String(::nad::LuaString::from(#key)),
+                        ::nad::IntoLua::into_lua(self.#ident)?,
+                    )
+                }
+            })
+            .collect::<Vec<_>>(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| {
+                let index = Index::from(i);
+                let key = i as i64 + 1;
+                quote! {
+                    (
+                        ::nad::Value::Integer(#key),
+                        ::nad::IntoLua::into_lua(self.#index)?,
+                    )
+                }
+            })
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::nad::IntoLua for #name #ty_generics #where_clause {
+            fn into_lua(self) -> ::nad::LuaResult<::nad::Value> {
+                ::nad::__private::new_table(::std::vec![#(#entries),*])
+            }
+        }
+
+        impl #impl_generics ::nad::IntoLuaMulti for #name #ty_generics #where_clause {
+            fn into_lua_multi(self) -> ::nad::LuaResult<::std::vec::Vec<::nad::Value>> {
+                ::nad::__private::into_multi(self)
+            }
+        }
+    })
+}
+
+fn expand_from_lua(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = struct_fields(&input, "FromLua")?;
+    let name = &input.ident;
+    let type_name = name.to_string();
+    let generics = add_bound(input.generics.clone(), quote!(::nad::FromLua));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &fields {
+        Fields::Named(fields) => {
+            let fields = fields.named.iter().map(|f| {
+                let ident = f.ident.as_ref().unwrap();
+                let key = ident.to_string();
+                quote!(#ident: ::nad::__private::get_field(&table, #key)?)
+            });
+            quote!(#name { #(#fields),* })
+        }
+        Fields::Unnamed(fields) => {
+            let fields = (0..fields.unnamed.len()).map(|i| {
+                let index = i as i64 + 1;
+                quote!(::nad::__private::get_index(&table, #index)?)
+            });
+            quote!(#name ( #(#fields),* ))
+        }
+        Fields::Unit => quote!(#name),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::nad::FromLua for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_lua(value: ::nad::Value) -> ::nad::LuaResult<Self> {
+                let table = ::nad::__private::expect_table(value, #type_name)?;
+                ::std::result::Result::Ok(#body)
+            }
+        }
+
+        impl #impl_generics ::nad::FromLuaMulti for #name #ty_generics #where_clause {
+            fn from_lua_multi(
+                values: ::std::vec::Vec<::nad::Value>,
+            ) -> ::nad::LuaResult<Self> {
+                ::nad::__private::from_multi(values)
+            }
+        }
+    })
+}
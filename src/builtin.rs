@@ -1,32 +1,590 @@
 use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
 
+use crate::builtin_io;
+use crate::builtin_json;
+use crate::builtin_math;
+use crate::builtin_os;
+use crate::builtin_package;
+use crate::builtin_utf8;
+use crate::chunk::LUAC_HEADER;
+use crate::error::{LuaError, LuaResult};
 use crate::func::Closure;
-use crate::value::Value;
-use crate::State;
+use crate::gc::Finalizable;
+use crate::state::MAX_STACK;
+use crate::state_map::{map_len, map_raw_set};
+use crate::state_mem::entry_size;
+use crate::value::{LuaString, Value};
+use crate::value_impl::str_to_number;
+use crate::{Reader, State};
 
 macro_rules! add_func {
     ($m:ident, $name:ident) => {
+        add_func!($m, stringify!($name), $name);
+    };
+    ($m:ident, $name:expr, $func:expr) => {
         $m.insert(
-            Value::String(stringify!($name).to_string()),
-            Value::Function(Closure::with_builtin($name, 0)),
+            Value::String($name.into()),
+            Value::Function(std::rc::Rc::new(Closure::with_builtin($func, 0))),
         );
     };
 }
 
-pub fn add_builtin_func(m: &mut HashMap<Value, Value>) {
+pub fn add_builtin_func(m: &mut HashMap<Value, Value>, registry: &mut HashMap<Value, Value>) {
+    add_func!(m, assert);
+    add_func!(m, collectgarbage);
+    add_func!(m, dofile);
+    add_func!(m, getmetatable);
+    add_func!(m, load);
+    add_func!(m, loadfile);
+    add_func!(m, next);
+    add_func!(m, pairs);
+    add_func!(m, pcall);
     add_func!(m, print);
-}
+    add_func!(m, rawequal);
+    add_func!(m, rawget);
+    add_func!(m, rawlen);
+    add_func!(m, rawset);
+    add_func!(m, select);
+    add_func!(m, setmetatable);
+    add_func!(m, tonumber);
+    add_func!(m, tostring);
+    add_func!(m, "type", type_of);
+    add_func!(m, unpack);
+    m.insert(
+        Value::String("_VERSION".into()),
+        Value::String("Lua 5.3".into()),
+    );
+
+    m.insert(
+        Value::String("io".into()),
+        builtin_io::new_io_lib(registry),
+    );
+    m.insert(
+        Value::String("json".into()),
+        builtin_json::new_json_lib(),
+    );
+    m.insert(
+        Value::String("math".into()),
+        builtin_math::new_math_lib(),
+    );
+    m.insert(Value::String("os".into()), builtin_os::new_os_lib());
+    m.insert(
+        Value::String("utf8".into()),
+        builtin_utf8::new_utf8_lib(),
+    );
 
-fn print(state: &mut State) -> usize {
-    println!(
-        "{}",
-        (1..=state.top())
-            .map(|index| {
-                state.get_rk((index - 1) as i32);
-                format!("{}", state.pop_value())
-            })
-            .collect::<Vec<String>>()
-            .join(" ")
+    let package = builtin_package::new_package_lib(registry);
+    m.insert(
+        Value::String("require".into()),
+        builtin_package::new_require(&package),
     );
-    0
+    m.insert(Value::String("package".into()), package);
+
+    // standard libraries are also loaded modules
+    let key = Value::String(builtin_package::LOADED.into());
+    if let Some(Value::Map(loaded)) = registry.get(&key) {
+        let mut loaded = loaded.borrow_mut();
+        for name in ["io", "json", "math", "os", "package", "utf8"] {
+            let name = Value::String(name.into());
+            loaded.insert(name.clone(), m[&name].clone());
+        }
+    }
+}
+
+/// get argument `n` of current builtin call, `nil` if absent
+pub fn arg(state: &State, n: usize) -> Value {
+    if n <= state.top() {
+        state.stack().get(n as i32)
+    } else {
+        Value::Nil
+    }
+}
+
+/// error message of `e` same as C `strerror`
+pub fn strerror(e: &io::Error) -> String {
+    match e.raw_os_error() {
+        Some(errno) => unsafe { CStr::from_ptr(libc::strerror(errno)) }
+            .to_string_lossy()
+            .to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// push `true` on success, otherwise `nil, "name: message", errno`
+pub fn file_result(state: &mut State, res: io::Result<()>, name: Option<&str>) -> LuaResult<usize> {
+    let e = match res {
+        Ok(_) => {
+            state.push_value(Value::Bool(true));
+            return Ok(1);
+        }
+        Err(e) => e,
+    };
+
+    let errno = e.raw_os_error().unwrap_or(0);
+    let msg = strerror(&e);
+    state.push_value(Value::Nil);
+    state.push_value(Value::String(match name {
+        Some(name) => format!("{}: {}", name, msg).into(),
+        None => msg.into(),
+    }));
+    state.push_value(Value::Integer(errno as i64));
+    Ok(3)
+}
+
+fn assert(state: &mut State) -> LuaResult<usize> {
+    if state.check_any(1)?.into_boolean() {
+        return Ok(state.top());
+    }
+    match state.top() {
+        1 => Err(LuaError::new("assertion failed!")),
+        _ => Err(LuaError::Runtime(arg(state, 2))),
+    }
+}
+
+/// name of chunk used in messages of loading errors
+fn chunk_id(chunkname: &str) -> &str {
+    match chunkname.as_bytes().first() {
+        Some(b'=' | b'@') => &chunkname[1..],
+        Some(&c) if c == LUAC_HEADER.signature[0] => "binary string",
+        _ => chunkname,
+    }
+}
+
+/// load chunk in `data` into a function, `mode` controls whether
+/// text or binary chunks are allowed
+fn load_chunk(
+    state: &State,
+    data: &[u8],
+    chunkname: &str,
+    mode: &str,
+    env: Option<Value>,
+) -> Result<Value, String> {
+    let binary = data.first() == Some(&LUAC_HEADER.signature[0]);
+    let kind = if binary { "binary" } else { "text" };
+    if !mode.contains(&kind[..1]) {
+        return Err(format!(
+            "attempt to load a {} chunk (mode is '{}')",
+            kind, mode
+        ));
+    }
+    if binary && !state.binary_chunks_allowed() {
+        return Err(format!(
+            "{}: binary chunks are not allowed in sandbox",
+            chunk_id(chunkname)
+        ));
+    }
+    if !binary {
+        // there is no compiler, only chunks precompiled by `luac` are accepted
+        return Err(format!(
+            "{}: text chunks are not supported",
+            chunk_id(chunkname)
+        ));
+    }
+
+    match Reader::from_str(data).with_name(chunkname).try_into_chunk() {
+        Ok(ch) => Ok(state.main_closure(ch, env)),
+        Err(e) => Err(format!("{}: {}", chunk_id(chunkname), e)),
+    }
+}
+
+/// load chunk from file `fname` or standard input
+pub(in crate) fn load_file(
+    state: &State,
+    fname: Option<&str>,
+    mode: &str,
+    env: Option<Value>,
+) -> Result<Value, String> {
+    let (chunkname, data) = match fname {
+        Some(name) => (
+            format!("@{}", name),
+            state
+                .sandbox_path(name)
+                .and_then(fs::read)
+                .map_err(|e| format!("cannot open {}: {}", name, strerror(&e)))?,
+        ),
+        None => {
+            if state.options.sandbox.is_some() {
+                return Err("cannot read stdin: not allowed in sandbox".to_string());
+            }
+            let mut data = vec![];
+            io::stdin()
+                .read_to_end(&mut data)
+                .map_err(|e| format!("cannot read stdin: {}", strerror(&e)))?;
+            ("=stdin".to_string(), data)
+        }
+    };
+
+    // skip UTF-8 BOM and the first line starting with '#'
+    let mut data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&data);
+    if data.first() == Some(&b'#') {
+        let pos = data.iter().position(|&c| c == b'\n');
+        data = &data[pos.map_or(data.len(), |pos| pos + 1)..];
+    }
+    load_chunk(state, data, &chunkname, mode, env)
+}
+
+/// push the loaded function, otherwise `nil, message`
+fn load_result(state: &mut State, res: Result<Value, String>) -> LuaResult<usize> {
+    match res {
+        Ok(func) => {
+            state.push_value(func);
+            Ok(1)
+        }
+        Err(msg) => {
+            state.push_value(Value::Nil);
+            state.push_value(Value::String(msg.into()));
+            Ok(2)
+        }
+    }
+}
+
+/// call reader function until it returns `nil` or empty string
+fn read_pieces(state: &mut State, reader: &Value) -> LuaResult<Vec<u8>> {
+    let mut data = vec![];
+    loop {
+        state.push_value(reader.clone());
+        state.call(0, 1)?;
+        match state.pop_value() {
+            Value::Nil => break,
+            Value::String(s) if s.is_empty() => break,
+            Value::String(s) => data.extend_from_slice(s.as_bytes()),
+            _ => return Err(LuaError::new("reader function must return a string")),
+        }
+    }
+    Ok(data)
+}
+
+fn dofile(state: &mut State) -> LuaResult<usize> {
+    let fname = match arg(state, 1) {
+        Value::Nil => None,
+        _ => Some(state.check_str(1)?),
+    };
+    let func = load_file(state, fname.as_deref(), "bt", None).map_err(LuaError::new)?;
+
+    let top = state.top();
+    state.push_value(func);
+    state.call(0, -1)?;
+    Ok(state.top() - top)
+}
+
+fn collectgarbage(state: &mut State) -> LuaResult<usize> {
+    let opt = state.opt_str(1, "collect")?;
+    // collections and their parameters are shared with host
+    if state.options.sandbox.is_some() && opt != "count" {
+        let msg = format!("option '{}' is not allowed in sandbox", opt);
+        return Err(state.arg_error(1, &msg));
+    }
+    let res = match opt.as_str() {
+        "collect" => {
+            state.gc_collect()?;
+            Value::Integer(0)
+        }
+        "count" => Value::Float(state.gc_count() as f64 / 1024.0),
+        "step" => Value::Bool(state.gc_step()?),
+        "stop" => {
+            state.gc_stop();
+            Value::Integer(0)
+        }
+        "restart" => {
+            state.gc_restart();
+            Value::Integer(0)
+        }
+        "isrunning" => Value::Bool(state.gc_is_running()),
+        "setpause" => {
+            let pause = state.opt_integer(2, 0)?.clamp(0, u32::MAX as i64) as u32;
+            Value::Integer(state.gc_set_pause(pause) as i64)
+        }
+        _ => return Err(state.arg_error(1, &format!("invalid option '{}'", opt))),
+    };
+    state.push_value(res);
+    Ok(1)
+}
+
+/// metatable of `obj`, or its field `__metatable` if present
+fn getmetatable(state: &mut State) -> LuaResult<usize> {
+    let meta = match state.check_any(1)? {
+        Value::Map(m) => m.borrow().meta.clone(),
+        Value::UserData(ud) => Some(ud.meta.clone()),
+        _ => None,
+    };
+    let meta = meta.map(|meta| {
+        let protected = meta
+            .borrow()
+            .get(&Value::String("__metatable".into()))
+            .cloned();
+        protected.unwrap_or(Value::Map(meta))
+    });
+    state.push_value(meta.unwrap_or(Value::Nil));
+    Ok(1)
+}
+
+fn load(state: &mut State) -> LuaResult<usize> {
+    let mode = state.opt_str(3, "bt")?;
+    let env = (state.top() >= 4).then(|| arg(state, 4));
+    let (data, default_name) = match arg(state, 1) {
+        Value::String(s) => (s.to_vec(), s.to_string_lossy().into_owned()),
+        reader @ Value::Function(_) => match read_pieces(state, &reader) {
+            Ok(data) => (data, "=(load)".to_string()),
+            Err(LuaError::Runtime(msg)) => {
+                state.push_value(Value::Nil);
+                state.push_value(msg);
+                return Ok(2);
+            }
+            Err(e) => return Err(e),
+        },
+        _ => return Err(state.type_error(1, "function")),
+    };
+    let chunkname = state.opt_str(2, &default_name)?;
+
+    let res = load_chunk(state, &data, &chunkname, &mode, env);
+    load_result(state, res)
+}
+
+fn loadfile(state: &mut State) -> LuaResult<usize> {
+    let fname = match arg(state, 1) {
+        Value::Nil => None,
+        _ => Some(state.check_str(1)?),
+    };
+    let mode = state.opt_str(2, "bt")?;
+    let env = (state.top() >= 3).then(|| arg(state, 3));
+
+    let res = load_file(state, fname.as_deref(), &mode, env);
+    load_result(state, res)
+}
+
+fn next(state: &mut State) -> LuaResult<usize> {
+    let m = state.check_table(1)?;
+    let ordered = state.is_deterministic();
+    if ordered {
+        let size = m.borrow_mut().sort_keys();
+        state.alloc(size)?;
+    }
+    let entry = m.borrow().next(&arg(state, 2), ordered)?;
+    match entry {
+        Some((k, v)) => {
+            state.push_value(k);
+            state.push_value(v);
+            Ok(2)
+        }
+        None => {
+            state.push_value(Value::Nil);
+            Ok(1)
+        }
+    }
+}
+
+/// `next, t, nil` to traverse table `t`, or results of its `__pairs`
+fn pairs(state: &mut State) -> LuaResult<usize> {
+    let t = state.check_any(1)?;
+    let rets = match state.metamethod(&t, "__pairs") {
+        Value::Nil => {
+            let next = Value::Function(Rc::new(Closure::with_builtin(next, 0)));
+            vec![next, t]
+        }
+        h => state.call_value(h, vec![t])?,
+    };
+    let mut rets = rets.into_iter();
+    for _ in 0..3 {
+        state.push_value(rets.next().unwrap_or(Value::Nil));
+    }
+    Ok(3)
+}
+
+/// call `f` in protected mode, return `false` and the error if it fails
+fn pcall(state: &mut State) -> LuaResult<usize> {
+    let f = state.check_any(1)?;
+    let args = (2..=state.top()).map(|n| arg(state, n)).collect();
+    let (ok, rets) = match state.call_value(f, args) {
+        Ok(rets) => (true, rets),
+        Err(e @ (LuaError::Exit(_) | LuaError::Interrupted)) => return Err(e),
+        Err(LuaError::Runtime(e)) => (false, vec![e]),
+        Err(e) => (false, vec![Value::String(e.to_string().into())]),
+    };
+    let n = rets.len();
+    state.ensure_stack(n + 1, "too many results")?;
+    state.push_value(Value::Bool(ok));
+    rets.into_iter().for_each(|v| state.push_value(v));
+    Ok(n + 1)
+}
+
+fn print(state: &mut State) -> LuaResult<usize> {
+    let mut line = (1..=state.top())
+        .map(|n| state.display_value(&arg(state, n)).map(LuaString::into_bytes))
+        .collect::<LuaResult<Vec<Vec<u8>>>>()?
+        .join(&b'\t');
+    line.push(b'\n');
+    // strings are printed as they are, even if not valid utf-8
+    let mut stdout = io::stdout();
+    stdout.write_all(&line).and_then(|_| stdout.flush()).ok();
+    Ok(0)
+}
+
+fn rawequal(state: &mut State) -> LuaResult<usize> {
+    let v1 = state.check_any(1)?;
+    let v2 = state.check_any(2)?;
+    state.push_value(Value::Bool(v1 == v2));
+    Ok(1)
+}
+
+fn rawget(state: &mut State) -> LuaResult<usize> {
+    let m = state.check_table(1)?;
+    let key = state.check_any(2)?;
+    let val = m.borrow().get(&key).cloned();
+    state.push_value(val.unwrap_or(Value::Nil));
+    Ok(1)
+}
+
+fn rawlen(state: &mut State) -> LuaResult<usize> {
+    let len = match arg(state, 1) {
+        Value::Map(m) => map_len(&m),
+        Value::String(s) => s.len() as i64,
+        _ => return Err(state.arg_error(1, "table or string expected")),
+    };
+    state.push_value(Value::Integer(len));
+    Ok(1)
+}
+
+fn rawset(state: &mut State) -> LuaResult<usize> {
+    let m = state.check_table(1)?;
+    let key = state.check_any(2)?;
+    let val = state.check_any(3)?;
+    state.alloc(entry_size(&key, &val))?;
+    map_raw_set(&m, key, val)?;
+    state.push_value(Value::Map(m));
+    Ok(1)
+}
+
+fn select(state: &mut State) -> LuaResult<usize> {
+    let n = state.top() as i64;
+    if let Value::String(s) = arg(state, 1) {
+        if s.starts_with(b"#") {
+            state.push_value(Value::Integer(n - 1));
+            return Ok(1);
+        }
+    }
+
+    let i = match state.check_integer(1)? {
+        i if i < 0 => n + i,
+        i => i.min(n),
+    };
+    if i < 1 {
+        return Err(state.arg_error(1, "index out of range"));
+    }
+    // arguments after `i` are already on the top
+    Ok((n - i) as usize)
+}
+
+/// set metatable of table `t`, `nil` removes it. `t` is marked for
+/// finalization if the metatable has field `__gc`
+fn setmetatable(state: &mut State) -> LuaResult<usize> {
+    let m = state.check_table(1)?;
+    let meta = match arg(state, 2) {
+        Value::Nil => None,
+        Value::Map(meta) => Some(meta),
+        _ => return Err(state.type_error(2, "nil or table")),
+    };
+    let t = Value::Map(m.clone());
+    if state.metamethod(&t, "__metatable") != Value::Nil {
+        return Err(LuaError::new("cannot change a protected metatable"));
+    }
+    if m.borrow().frozen {
+        return Err(LuaError::new("attempt to modify a read-only table"));
+    }
+    let has_gc = meta.as_ref().is_some_and(|meta| {
+        let key = Value::String("__gc".into());
+        meta.borrow().contains_key(&key)
+    });
+    if has_gc && m.borrow().gc.is_none() {
+        let f = state.new_finalizer(Finalizable::Table(Rc::downgrade(&m)));
+        m.borrow_mut().gc = Some(f);
+    }
+    m.borrow_mut().meta = meta;
+    state.push_value(t);
+    Ok(1)
+}
+
+/// convert `s` in `base` to integer like `l_str2int` in `lbaselib.c`
+fn str_to_int_base(s: &[u8], base: u32) -> Option<i64> {
+    let s = std::str::from_utf8(s).ok()?;
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\x0b');
+    let (neg, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    if s.is_empty() {
+        return None;
+    }
+
+    let n = s.chars().try_fold(0i64, |n, c| {
+        let d = c.to_digit(36).filter(|&d| d < base)?;
+        Some(n.wrapping_mul(base as i64).wrapping_add(d as i64))
+    })?;
+    Some(if neg { n.wrapping_neg() } else { n })
+}
+
+fn tonumber(state: &mut State) -> LuaResult<usize> {
+    let val = match arg(state, 2) {
+        Value::Nil => match state.check_any(1)? {
+            v @ (Value::Integer(_) | Value::Float(_)) => v,
+            Value::String(s) => str_to_number(&s).unwrap_or(Value::Nil),
+            _ => Value::Nil,
+        },
+        _ => {
+            let base = state.check_integer(2)?;
+            let s = match arg(state, 1) {
+                Value::String(s) => s,
+                _ => return Err(state.type_error(1, "string")),
+            };
+            if !(2..=36).contains(&base) {
+                return Err(state.arg_error(2, "base out of range"));
+            }
+            str_to_int_base(&s, base as u32).map_or(Value::Nil, Value::Integer)
+        }
+    };
+    state.push_value(val);
+    Ok(1)
+}
+
+fn tostring(state: &mut State) -> LuaResult<usize> {
+    let val = state.check_any(1)?;
+    let s = state.display_value(&val)?;
+    state.push_value(Value::String(s));
+    Ok(1)
+}
+
+fn type_of(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_any(1)?.type_name();
+    state.push_value(Value::String(name.into()));
+    Ok(1)
+}
+
+fn unpack(state: &mut State) -> LuaResult<usize> {
+    let list = arg(state, 1);
+    let i = state.opt_integer(2, 1)?;
+    let e = match arg(state, 3) {
+        Value::Nil => {
+            state.len(1)?;
+            state.pop_value().into_integer()?
+        }
+        _ => state.check_integer(3)?,
+    };
+    if i > e {
+        return Ok(0);
+    }
+
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= MAX_STACK as u64 {
+        return Err(LuaError::new("too many results to unpack"));
+    }
+    state.ensure_stack(n as usize + 1, "too many results to unpack")?;
+    for k in i..=e {
+        let val = state.index_value(&list, &Value::Integer(k))?;
+        state.push_value(val);
+    }
+    Ok(n as usize + 1)
 }
@@ -0,0 +1,725 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::rc::Rc;
+use std::str;
+
+use crate::builtin::{arg, file_result, strerror};
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::gc;
+use crate::stack::new_upval;
+use crate::value::{LuaString, Value};
+use crate::value_impl::{fmt_float, str_to_number};
+use crate::State;
+
+/// registry key of the methods shared by all file handles
+pub const FILE_HANDLE: &str = "FILE*";
+
+/// registry keys of default input and output file
+const IO_INPUT: &str = "_IO_input";
+const IO_OUTPUT: &str = "_IO_output";
+
+/// size of read buffer and default size of write buffer
+const BUFFER_SIZE: usize = 8192;
+
+/// maximum length of a numeral read by format `n`
+const MAX_NUMERAL_LEN: usize = 200;
+
+enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(fs::File),
+}
+
+impl Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Stdin => io::stdin().read(buf),
+            Stream::File(f) => f.read(buf),
+            _ => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Stdout => io::stdout().write_all(buf),
+            Stream::Stderr => io::stderr().write_all(buf),
+            Stream::File(f) => f.write_all(buf),
+            Stream::Stdin => Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Stdout => io::stdout().flush(),
+            Stream::Stderr => io::stderr().flush(),
+            Stream::File(f) => f.flush(),
+            Stream::Stdin => Ok(()),
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Stream::File(f) => f.seek(pos),
+            _ => Err(io::Error::from_raw_os_error(libc::ESPIPE)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BufMode {
+    No,
+    Full,
+    Line,
+}
+
+/// file handle with buffering like C `FILE`
+pub struct LuaFile {
+    /// `None` after the file is closed
+    stream: Option<Stream>,
+    rbuf: Vec<u8>,
+    rpos: usize,
+    wbuf: Vec<u8>,
+    mode: BufMode,
+    size: usize,
+    /// creation order, see `gc::next_seq`
+    pub(in crate) seq: u64,
+}
+
+impl LuaFile {
+    fn new(stream: Stream) -> Self {
+        let mode = match stream {
+            Stream::File(_) => BufMode::Full,
+            _ => BufMode::No,
+        };
+        LuaFile {
+            stream: Some(stream),
+            rbuf: vec![],
+            rpos: 0,
+            wbuf: vec![],
+            mode,
+            size: BUFFER_SIZE,
+            seq: gc::next_seq(),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn is_std(&self) -> bool {
+        matches!(
+            self.stream,
+            Some(Stream::Stdin | Stream::Stdout | Stream::Stderr)
+        )
+    }
+
+    fn stream(&mut self) -> io::Result<&mut Stream> {
+        self.stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EBADF))
+    }
+
+    /// write pending output to the underlying stream
+    fn write_out(&mut self) -> io::Result<()> {
+        if !self.wbuf.is_empty() {
+            let data = mem::take(&mut self.wbuf);
+            self.stream()?.write_all(&data)?;
+        }
+        Ok(())
+    }
+
+    /// drop read ahead bytes and move the stream back to the logical position
+    fn sync_read(&mut self) -> io::Result<()> {
+        let unread = self.rbuf.len() - self.rpos;
+        self.rbuf.clear();
+        self.rpos = 0;
+        if unread > 0 {
+            self.stream()?.seek(SeekFrom::Current(-(unread as i64)))?;
+        }
+        Ok(())
+    }
+
+    /// make sure read buffer is not empty, `false` on end of file
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.rpos < self.rbuf.len() {
+            return Ok(true);
+        }
+        self.write_out()?;
+
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EBADF))?;
+        self.rbuf.resize(BUFFER_SIZE, 0);
+        self.rpos = 0;
+        let n = loop {
+            match stream.read(&mut self.rbuf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res,
+            }
+        };
+        self.rbuf.truncate(*n.as_ref().unwrap_or(&0));
+        Ok(n? > 0)
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(match self.fill()? {
+            true => Some(self.rbuf[self.rpos]),
+            false => None,
+        })
+    }
+
+    fn read_line(&mut self, chop: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut line = vec![];
+        while self.fill()? {
+            let buf = &self.rbuf[self.rpos..];
+            match buf.iter().position(|&c| c == b'\n') {
+                Some(pos) => {
+                    line.extend_from_slice(&buf[..pos]);
+                    if !chop {
+                        line.push(b'\n');
+                    }
+                    self.rpos += pos + 1;
+                    return Ok(Some(line));
+                }
+                None => {
+                    line.extend_from_slice(buf);
+                    self.rpos = self.rbuf.len();
+                }
+            }
+        }
+        Ok((!line.is_empty()).then_some(line))
+    }
+
+    fn read_chars(&mut self, n: usize) -> io::Result<Option<Vec<u8>>> {
+        let mut data = vec![];
+        while data.len() < n && self.fill()? {
+            let len = cmp::min(n - data.len(), self.rbuf.len() - self.rpos);
+            data.extend_from_slice(&self.rbuf[self.rpos..self.rpos + len]);
+            self.rpos += len;
+        }
+        Ok((!data.is_empty()).then_some(data))
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        while self.fill()? {
+            data.extend_from_slice(&self.rbuf[self.rpos..]);
+            self.rpos = self.rbuf.len();
+        }
+        Ok(data)
+    }
+
+    /// accept current char into `rn` if it is in `set`
+    fn test2(&mut self, rn: &mut Vec<u8>, set: &[u8]) -> io::Result<bool> {
+        match self.peek()? {
+            Some(c) if set.contains(&c) && rn.len() <= MAX_NUMERAL_LEN => {
+                rn.push(c);
+                self.rpos += 1;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn read_digits(&mut self, rn: &mut Vec<u8>, hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(c) = self.peek()? {
+            let digit = if hex {
+                c.is_ascii_hexdigit()
+            } else {
+                c.is_ascii_digit()
+            };
+            if !digit || !self.test2(rn, &[c])? {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// read the longest prefix that may be a numeral, same as `read_number` in `liolib.c`
+    fn read_number(&mut self) -> io::Result<Option<Value>> {
+        while let Some(c) = self.peek()? {
+            if !c.is_ascii_whitespace() && c != 0x0b {
+                break;
+            }
+            self.rpos += 1;
+        }
+
+        let mut rn = vec![];
+        let mut count = 0;
+        let mut hex = false;
+        self.test2(&mut rn, b"-+")?;
+        if self.test2(&mut rn, b"0")? {
+            hex = self.test2(&mut rn, b"xX")?;
+            count = (!hex) as usize;
+        }
+        count += self.read_digits(&mut rn, hex)?;
+        if self.test2(&mut rn, b".")? {
+            count += self.read_digits(&mut rn, hex)?;
+        }
+        if count > 0 && self.test2(&mut rn, if hex { b"pP" } else { b"eE" })? {
+            self.test2(&mut rn, b"-+")?;
+            self.read_digits(&mut rn, false)?;
+        }
+
+        if rn.len() > MAX_NUMERAL_LEN {
+            return Ok(None);
+        }
+        Ok(str_to_number(&rn))
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.sync_read()?;
+        self.wbuf.extend_from_slice(data);
+        let need_flush = match self.mode {
+            BufMode::No => true,
+            BufMode::Line => data.contains(&b'\n'),
+            BufMode::Full => self.wbuf.len() >= self.size,
+        };
+        if need_flush {
+            self.write_out()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.stream()?.flush()
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.write_out()?;
+        let unread = (self.rbuf.len() - self.rpos) as i64;
+        self.rbuf.clear();
+        self.rpos = 0;
+        let pos = match pos {
+            SeekFrom::Current(offset) => SeekFrom::Current(offset - unread),
+            pos => pos,
+        };
+        self.stream()?.seek(pos)
+    }
+
+    fn set_vbuf(&mut self, mode: BufMode, size: usize) -> io::Result<()> {
+        self.write_out()?;
+        self.mode = mode;
+        self.size = size;
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let res = self.flush();
+        self.stream = None;
+        res
+    }
+}
+
+impl Drop for LuaFile {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            let _ = self.flush();
+        }
+    }
+}
+
+pub fn new_io_lib(registry: &mut HashMap<Value, Value>) -> Value {
+    let mut methods = HashMap::new();
+    add_func!(methods, "close", f_close);
+    add_func!(methods, "flush", f_flush);
+    add_func!(methods, "lines", f_lines);
+    add_func!(methods, "read", f_read);
+    add_func!(methods, "seek", f_seek);
+    add_func!(methods, "setvbuf", f_setvbuf);
+    add_func!(methods, "write", f_write);
+    registry.insert(
+        Value::String(FILE_HANDLE.into()),
+        Value::new_map(methods),
+    );
+
+    let stdin = new_file(Stream::Stdin);
+    let stdout = new_file(Stream::Stdout);
+    let stderr = new_file(Stream::Stderr);
+    registry.insert(Value::String(IO_INPUT.into()), stdin.clone());
+    registry.insert(Value::String(IO_OUTPUT.into()), stdout.clone());
+
+    let mut m = HashMap::new();
+    add_func!(m, close);
+    add_func!(m, flush);
+    add_func!(m, input);
+    add_func!(m, lines);
+    add_func!(m, open);
+    add_func!(m, output);
+    add_func!(m, read);
+    add_func!(m, "type", io_type);
+    add_func!(m, write);
+    m.insert(Value::String("stdin".into()), stdin);
+    m.insert(Value::String("stdout".into()), stdout);
+    m.insert(Value::String("stderr".into()), stderr);
+    Value::new_map(m)
+}
+
+fn new_file(stream: Stream) -> Value {
+    Value::File(Rc::new(RefCell::new(LuaFile::new(stream))))
+}
+
+/// get the opened file at argument `n`
+fn to_file(state: &State, n: usize) -> LuaResult<Rc<RefCell<LuaFile>>> {
+    match arg(state, n) {
+        Value::File(f) if f.borrow().is_closed() => {
+            Err(LuaError::new("attempt to use a closed file"))
+        }
+        Value::File(f) => Ok(f),
+        _ => Err(state.type_error(n, FILE_HANDLE)),
+    }
+}
+
+/// get default input or output file
+fn io_file(state: &State, key: &str) -> LuaResult<Rc<RefCell<LuaFile>>> {
+    match state.registry_get(key) {
+        Value::File(f) if !f.borrow().is_closed() => Ok(f),
+        _ => Err(LuaError::new(format!(
+            "standard {} file is closed",
+            &key[4..]
+        ))),
+    }
+}
+
+/// check mode matches `[rwa]%+?b*`
+fn check_mode(mode: &str) -> bool {
+    match mode.strip_prefix(['r', 'w', 'a']) {
+        Some(rest) => {
+            let rest = rest.strip_prefix('+').unwrap_or(rest);
+            rest.chars().all(|c| c == 'b')
+        }
+        None => false,
+    }
+}
+
+/// open file `name`, it must be under the file root in sandbox
+fn open_file(state: &State, name: &str, mode: &str) -> io::Result<fs::File> {
+    let update = mode.contains('+');
+    let mut opts = OpenOptions::new();
+    match mode.as_bytes()[0] {
+        b'r' => opts.read(true).write(update),
+        b'w' => opts.write(true).create(true).truncate(true).read(update),
+        _ => opts.append(true).create(true).read(update),
+    };
+    opts.open(state.sandbox_path(name)?)
+}
+
+/// open file or raise an error
+fn open_check(state: &State, name: &str, mode: &str) -> LuaResult<Value> {
+    match open_file(state, name, mode) {
+        Ok(f) => Ok(new_file(Stream::File(f))),
+        Err(e) => Err(LuaError::new(format!(
+            "cannot open file '{}' ({})",
+            name,
+            strerror(&e)
+        ))),
+    }
+}
+
+fn aux_close(state: &mut State, file: Rc<RefCell<LuaFile>>) -> LuaResult<usize> {
+    if file.borrow().is_std() {
+        state.push_value(Value::Nil);
+        state.push_value(Value::String("cannot close standard file".into()));
+        return Ok(2);
+    }
+    let res = file.borrow_mut().close();
+    file_result(state, res, None)
+}
+
+fn bytes_value(data: Vec<u8>) -> Value {
+    Value::String(data.into())
+}
+
+/// read values by `formats` whose first argument index is `first`
+fn g_read(
+    state: &mut State,
+    file: &Rc<RefCell<LuaFile>>,
+    formats: &[Value],
+    first: usize,
+) -> LuaResult<usize> {
+    let mut file = file.borrow_mut();
+    let mut results = vec![];
+    if formats.is_empty() {
+        match file.read_line(true) {
+            Ok(line) => results.push(line.map_or(Value::Nil, bytes_value)),
+            Err(e) => return file_result(state, Err(e), None),
+        }
+    }
+
+    for (i, format) in formats.iter().enumerate() {
+        let n = first + i;
+        let item = match format {
+            Value::Integer(_) | Value::Float(_) => {
+                let l = check_count(state, format, n)?;
+                if l == 0 {
+                    file.fill()
+                        .map(|ok| ok.then(|| Value::String(LuaString::default())))
+                } else {
+                    file.read_chars(l as usize)
+                        .map(|data| data.map(bytes_value))
+                }
+            }
+            Value::String(p) => match p.strip_prefix(b"*").unwrap_or(p).first() {
+                Some(b'n') => file.read_number(),
+                Some(b'l') => file.read_line(true).map(|data| data.map(bytes_value)),
+                Some(b'L') => file.read_line(false).map(|data| data.map(bytes_value)),
+                Some(b'a') => file.read_all().map(|data| Some(bytes_value(data))),
+                _ => return Err(state.arg_error(n, "invalid format")),
+            },
+            _ => {
+                let msg = format!("number expected, got {}", format.type_name());
+                return Err(state.arg_error(n, &msg));
+            }
+        };
+
+        match item {
+            Ok(Some(v)) => results.push(v),
+            Ok(None) => {
+                results.push(Value::Nil);
+                break;
+            }
+            Err(e) => return file_result(state, Err(e), None),
+        }
+    }
+
+    let n = results.len();
+    state.ensure_stack(n, "too many arguments")?;
+    results.into_iter().for_each(|v| state.push_value(v));
+    Ok(n)
+}
+
+fn check_count(state: &State, format: &Value, n: usize) -> LuaResult<i64> {
+    format
+        .clone()
+        .into_integer()
+        .map_err(|_| state.arg_error(n, "number has no integer representation"))
+}
+
+/// write arguments from `first` to top into `file`, return the file
+fn g_write(state: &mut State, file: Rc<RefCell<LuaFile>>, first: usize) -> LuaResult<usize> {
+    for n in first..=state.top() {
+        let data = match arg(state, n) {
+            Value::Integer(i) => i.to_string().into(),
+            Value::Float(f) => fmt_float(f).into(),
+            Value::String(s) => s,
+            _ => return Err(state.type_error(n, "string")),
+        };
+        if let Err(e) = file.borrow_mut().write(data.as_bytes()) {
+            return file_result(state, Err(e), None);
+        }
+    }
+    state.push_value(Value::File(file));
+    Ok(1)
+}
+
+/// set default input or output file, return current one
+fn g_iofile(state: &mut State, key: &str, mode: &str) -> LuaResult<usize> {
+    match arg(state, 1) {
+        Value::Nil => {}
+        Value::String(_) | Value::Integer(_) | Value::Float(_) => {
+            let name = state.check_str(1)?;
+            let file = open_check(state, &name, mode)?;
+            state.registry_set(key, file);
+        }
+        _ => {
+            let file = to_file(state, 1)?;
+            state.registry_set(key, Value::File(file));
+        }
+    }
+    state.push_value(state.registry_get(key));
+    Ok(1)
+}
+
+/// create iterator reading `file` with formats from argument `first`
+fn aux_lines(state: &mut State, file: Value, first: usize, to_close: bool) -> LuaResult<usize> {
+    let formats: Vec<Value> = (first..=state.top()).map(|n| arg(state, n)).collect();
+    let mut iter = Closure::with_builtin(io_readline, formats.len() + 2);
+    iter.upval[0] = new_upval(file);
+    iter.upval[1] = new_upval(Value::Bool(to_close));
+    for (uv, format) in iter.upval[2..].iter_mut().zip(formats) {
+        *uv = new_upval(format);
+    }
+    state.push_value(Value::Function(Rc::new(iter)));
+    Ok(1)
+}
+
+fn io_readline(state: &mut State) -> LuaResult<usize> {
+    let file = match state.uv_get_index(0) {
+        Value::File(f) if !f.borrow().is_closed() => f,
+        _ => return Err(LuaError::new("file is already closed")),
+    };
+    let to_close = state.uv_get_index(1).into_boolean();
+    let nuv = state.stack().upval_count();
+    let formats: Vec<Value> = (2..nuv).map(|i| state.uv_get_index(i as i32)).collect();
+
+    let top = state.top();
+    let n = g_read(state, &file, &formats, 2)?;
+    if arg(state, top + 1).into_boolean() {
+        return Ok(n);
+    }
+    if n > 1 {
+        // error message of a failed read
+        return Err(LuaError::Runtime(arg(state, top + 2)));
+    }
+    if to_close {
+        let _ = file.borrow_mut().close();
+    }
+    Ok(0)
+}
+
+fn close(state: &mut State) -> LuaResult<usize> {
+    let file = match state.top() {
+        0 => io_file(state, IO_OUTPUT)?,
+        _ => to_file(state, 1)?,
+    };
+    aux_close(state, file)
+}
+
+fn flush(state: &mut State) -> LuaResult<usize> {
+    let res = io_file(state, IO_OUTPUT)?.borrow_mut().flush();
+    file_result(state, res, None)
+}
+
+fn input(state: &mut State) -> LuaResult<usize> {
+    g_iofile(state, IO_INPUT, "r")
+}
+
+fn lines(state: &mut State) -> LuaResult<usize> {
+    match arg(state, 1) {
+        Value::Nil => {
+            let file = io_file(state, IO_INPUT)?;
+            aux_lines(state, Value::File(file), 2, false)
+        }
+        _ => {
+            let name = state.check_str(1)?;
+            let file = open_check(state, &name, "r")?;
+            aux_lines(state, file, 2, true)
+        }
+    }
+}
+
+fn open(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    let mode = state.opt_str(2, "r")?;
+    if !check_mode(&mode) {
+        return Err(state.arg_error(2, "invalid mode"));
+    }
+
+    match open_file(state, &name, &mode) {
+        Ok(f) => {
+            state.push_value(new_file(Stream::File(f)));
+            Ok(1)
+        }
+        Err(e) => file_result(state, Err(e), Some(&name)),
+    }
+}
+
+fn output(state: &mut State) -> LuaResult<usize> {
+    g_iofile(state, IO_OUTPUT, "w")
+}
+
+fn read(state: &mut State) -> LuaResult<usize> {
+    let file = io_file(state, IO_INPUT)?;
+    let formats: Vec<Value> = (1..=state.top()).map(|n| arg(state, n)).collect();
+    g_read(state, &file, &formats, 1)
+}
+
+fn io_type(state: &mut State) -> LuaResult<usize> {
+    if state.top() == 0 {
+        return Err(state.arg_error(1, "value expected"));
+    }
+    state.push_value(match arg(state, 1) {
+        Value::File(f) if f.borrow().is_closed() => Value::String("closed file".into()),
+        Value::File(_) => Value::String("file".into()),
+        _ => Value::Nil,
+    });
+    Ok(1)
+}
+
+fn write(state: &mut State) -> LuaResult<usize> {
+    let file = io_file(state, IO_OUTPUT)?;
+    g_write(state, file, 1)
+}
+
+fn f_close(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    aux_close(state, file)
+}
+
+fn f_flush(state: &mut State) -> LuaResult<usize> {
+    let res = to_file(state, 1)?.borrow_mut().flush();
+    file_result(state, res, None)
+}
+
+fn f_lines(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    aux_lines(state, Value::File(file), 2, false)
+}
+
+fn f_read(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    let formats: Vec<Value> = (2..=state.top()).map(|n| arg(state, n)).collect();
+    g_read(state, &file, &formats, 2)
+}
+
+fn f_seek(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    let whence = state.opt_str(2, "cur")?;
+    let offset = match arg(state, 3) {
+        Value::Nil => 0,
+        _ => state.check_integer(3)?,
+    };
+
+    let pos = match whence.as_str() {
+        "set" if offset < 0 => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        "set" => Ok(SeekFrom::Start(offset as u64)),
+        "cur" => Ok(SeekFrom::Current(offset)),
+        "end" => Ok(SeekFrom::End(offset)),
+        _ => {
+            let msg = format!("invalid option '{}'", whence);
+            return Err(state.arg_error(2, &msg));
+        }
+    };
+    let res = pos.and_then(|pos| file.borrow_mut().seek(pos));
+    match res {
+        Ok(pos) => {
+            state.push_value(Value::Integer(pos as i64));
+            Ok(1)
+        }
+        Err(e) => file_result(state, Err(e), None),
+    }
+}
+
+fn f_setvbuf(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    let mode = match state.check_str(2)?.as_str() {
+        "no" => BufMode::No,
+        "full" => BufMode::Full,
+        "line" => BufMode::Line,
+        other => {
+            let msg = format!("invalid option '{}'", other);
+            return Err(state.arg_error(2, &msg));
+        }
+    };
+    let size = match arg(state, 3) {
+        Value::Nil => BUFFER_SIZE,
+        _ => state.check_integer(3)? as usize,
+    };
+    let res = file.borrow_mut().set_vbuf(mode, size);
+    file_result(state, res, None)
+}
+
+fn f_write(state: &mut State) -> LuaResult<usize> {
+    let file = to_file(state, 1)?;
+    g_write(state, file, 2)
+}
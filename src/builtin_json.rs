@@ -0,0 +1,544 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::builtin::arg;
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::gc;
+use crate::stack::new_upval;
+use crate::state_map::map_raw_set;
+use crate::state_mem::{entry_size, table_size};
+use crate::value::{LuaString, Map, Table, Value};
+use crate::State;
+
+/// maximum nesting depth of arrays and objects
+const MAX_DEPTH: usize = 1000;
+
+/// largest integer `indent`, in spaces
+const MAX_INDENT: i64 = 10;
+
+/// `json.null` is an empty table shared by encoder and decoder as upvalue,
+/// it stands for `null` inside arrays and objects where `nil` can not be stored
+pub fn new_json_lib() -> Value {
+    let null = Value::new_map(HashMap::new());
+    let mut m = HashMap::new();
+    for (name, f) in [
+        ("decode", decode as fn(&mut State) -> LuaResult<usize>),
+        ("encode", encode),
+    ] {
+        let mut func = Closure::with_builtin(f, 1);
+        func.upval[0] = new_upval(null.clone());
+        m.insert(
+            Value::String(name.into()),
+            gc::new_function(func),
+        );
+    }
+    m.insert(Value::String("null".into()), null);
+    Value::new_map(m)
+}
+
+/// length of `m` if its keys are exactly `1..=n`
+fn array_len(m: &Map) -> Option<i64> {
+    let m = m.borrow();
+    let n = m.len() as i64;
+    let is_index = |k: Value| matches!(k, Value::Integer(i) if i >= 1 && i <= n);
+    match n > 0 && m.keys().all(is_index) {
+        true => Some(n),
+        false => None,
+    }
+}
+
+struct Encoder<'a> {
+    state: &'a mut State,
+    null: Map,
+    indent: Option<Vec<u8>>,
+    sort_keys: bool,
+    /// tables on the path from the root value
+    visiting: Vec<*const RefCell<Table>>,
+    out: Vec<u8>,
+    /// length of `out` counted as allocated
+    counted: usize,
+}
+
+impl Encoder<'_> {
+    /// line break and indentation of `level` if pretty printing
+    fn newline(&mut self, level: usize) {
+        if let Some(indent) = &self.indent {
+            self.out.push(b'\n');
+            for _ in 0..level {
+                self.out.extend_from_slice(indent);
+            }
+        }
+    }
+
+    /// json text is utf-8, other strings can not be encoded
+    fn string(&mut self, s: &LuaString) -> LuaResult<()> {
+        if s.to_str().is_err() {
+            return Err(LuaError::new("cannot encode string which is not valid UTF-8"));
+        }
+        self.out.push(b'"');
+        for &c in s.iter() {
+            match c {
+                b'"' => self.out.extend_from_slice(b"\\\""),
+                b'\\' => self.out.extend_from_slice(b"\\\\"),
+                b'\n' => self.out.extend_from_slice(b"\\n"),
+                b'\r' => self.out.extend_from_slice(b"\\r"),
+                b'\t' => self.out.extend_from_slice(b"\\t"),
+                0x08 => self.out.extend_from_slice(b"\\b"),
+                0x0C => self.out.extend_from_slice(b"\\f"),
+                c if c < 0x20 => self
+                    .out
+                    .extend_from_slice(format!("\\u{:04x}", c).as_bytes()),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push(b'"');
+        Ok(())
+    }
+
+    fn value(&mut self, val: &Value) -> LuaResult<()> {
+        match val {
+            Value::Nil => self.out.extend_from_slice(b"null"),
+            Value::Bool(b) => self.out.extend_from_slice(b.to_string().as_bytes()),
+            Value::Integer(i) => self.out.extend_from_slice(i.to_string().as_bytes()),
+            Value::Float(f) if f.is_finite() => {
+                // shortest representation which reads back the same float
+                self.out.extend_from_slice(format!("{:?}", f).as_bytes())
+            }
+            Value::Float(_) => return Err(LuaError::new("cannot encode non-finite number")),
+            Value::String(s) => self.string(s)?,
+            Value::Map(m) if Rc::ptr_eq(m, &self.null) => self.out.extend_from_slice(b"null"),
+            Value::Map(m) => self.table(m)?,
+            v => {
+                let msg = format!("cannot encode a {} value", v.type_name());
+                return Err(LuaError::new(msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// count output written since last time, error if it exceeds the
+    /// memory limit
+    fn count(&mut self) -> LuaResult<()> {
+        let n = self.out.len() - self.counted;
+        self.counted = self.out.len();
+        self.state.alloc(n)
+    }
+
+    fn table(&mut self, m: &Map) -> LuaResult<()> {
+        self.state.check_interrupt()?;
+        self.count()?;
+        if self.visiting.contains(&Rc::as_ptr(m)) {
+            return Err(LuaError::new("cannot encode a recursive table"));
+        }
+        if self.visiting.len() >= MAX_DEPTH {
+            return Err(LuaError::new("too many nested tables"));
+        }
+
+        self.visiting.push(Rc::as_ptr(m));
+        match array_len(m) {
+            Some(n) => self.array(m, n)?,
+            None => self.object(m)?,
+        }
+        self.visiting.pop();
+        Ok(())
+    }
+
+    fn array(&mut self, m: &Map, n: i64) -> LuaResult<()> {
+        self.out.push(b'[');
+        for i in 1..=n {
+            if i > 1 {
+                self.out.push(b',');
+            }
+            self.newline(self.visiting.len());
+            let val = m.borrow()[&Value::Integer(i)].clone();
+            self.value(&val)?;
+        }
+        self.close(b']');
+        Ok(())
+    }
+
+    /// only string keys can be encoded, empty table is an object
+    fn object(&mut self, m: &Map) -> LuaResult<()> {
+        let mut entries = Vec::with_capacity(m.borrow().len());
+        for (k, v) in m.borrow().iter() {
+            match k {
+                Value::String(k) => entries.push((k.clone(), v.clone())),
+                k => {
+                    let msg = format!("cannot encode table key of type {}", k.type_name());
+                    return Err(LuaError::new(msg));
+                }
+            }
+        }
+        if self.sort_keys {
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        self.out.push(b'{');
+        let empty = entries.is_empty();
+        for (i, (k, v)) in entries.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(b',');
+            }
+            self.newline(self.visiting.len());
+            self.string(&k)?;
+            self.out.push(b':');
+            if self.indent.is_some() {
+                self.out.push(b' ');
+            }
+            self.value(&v)?;
+        }
+        match empty {
+            true => self.out.push(b'}'),
+            false => self.close(b'}'),
+        }
+        Ok(())
+    }
+
+    /// closing bracket at the indentation level of the opening one
+    fn close(&mut self, c: u8) {
+        self.newline(self.visiting.len() - 1);
+        self.out.push(c);
+    }
+}
+
+/// `json.encode(value [, {indent = n or s, sort_keys = bool}])`
+fn encode(state: &mut State) -> LuaResult<usize> {
+    let val = state.check_any(1)?;
+    let mut indent = None;
+    let mut sort_keys = false;
+    if let Value::Map(opts) = arg(state, 2) {
+        let opts = opts.borrow();
+        let field = |k: &str| opts.get(&Value::String(k.into())).cloned();
+        indent = match field("indent") {
+            None | Some(Value::Nil) | Some(Value::Bool(false)) => None,
+            Some(Value::Integer(n)) if (0..=MAX_INDENT).contains(&n) => {
+                Some(vec![b' '; n as usize])
+            }
+            Some(Value::Integer(_)) => {
+                let msg = format!("'indent' must be between 0 and {}", MAX_INDENT);
+                return Err(LuaError::new(msg));
+            }
+            Some(Value::String(s)) => Some(s.into_bytes()),
+            Some(_) => {
+                return Err(LuaError::new(
+                    "'indent' must be a non-negative integer or string",
+                ))
+            }
+        };
+        sort_keys = field("sort_keys").is_some_and(|v| v.into_boolean());
+    } else if !matches!(arg(state, 2), Value::Nil) {
+        return Err(state.type_error(2, "table"));
+    }
+
+    // objects are encoded in the same order in deterministic mode
+    sort_keys |= state.is_deterministic();
+
+    let null = match state.uv_get_index(0) {
+        Value::Map(m) => m,
+        _ => unreachable!(),
+    };
+    let mut encoder = Encoder {
+        state,
+        null,
+        indent,
+        sort_keys,
+        visiting: vec![],
+        out: vec![],
+        counted: 0,
+    };
+    encoder.value(&val)?;
+    let out = encoder.out;
+    state.push_value(Value::String(out.into()));
+    Ok(1)
+}
+
+struct Decoder<'a> {
+    /// tables and strings created are counted as allocated by it
+    state: &'a mut State,
+    null: Value,
+    s: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn error(&self, msg: &str) -> LuaError {
+        LuaError::new(format!(
+            "json decode error at position {}: {}",
+            self.pos + 1,
+            msg
+        ))
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.s.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.s.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> LuaResult<()> {
+        match self.peek() {
+            Some(cc) if cc == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", c as char))),
+        }
+    }
+
+    fn literal(&mut self, word: &str, val: Value) -> LuaResult<Value> {
+        match self.s[self.pos..].starts_with(word.as_bytes()) {
+            true => {
+                self.pos += word.len();
+                Ok(val)
+            }
+            false => Err(self.error("invalid literal")),
+        }
+    }
+
+    fn value(&mut self) -> LuaResult<Value> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            _ => self.scalar(),
+        }
+    }
+
+    /// value other than arrays and objects, kept out of `value` so frames
+    /// of nested values are small
+    fn scalar(&mut self) -> LuaResult<Value> {
+        match self.peek() {
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", self.null.clone()),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// decode array or object into new table, which is kept on the stack
+    /// while it is filled so memory checks see it
+    fn nested(&mut self, f: fn(&mut Self, &Map) -> LuaResult<()>) -> LuaResult<Value> {
+        self.enter()?;
+        let m = gc::new_table(HashMap::new());
+        self.state.push_value(Value::Map(m.clone()));
+        f(self, &m)?;
+        self.state.pop(1);
+        self.depth -= 1;
+        Ok(Value::Map(m))
+    }
+
+    /// start array or object, its table is counted as allocated here so
+    /// frames of nested values are small
+    fn enter(&mut self) -> LuaResult<()> {
+        self.state.check_interrupt()?;
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("too many nested levels"));
+        }
+        self.depth += 1;
+        self.state.alloc(table_size(0))
+    }
+
+    /// set entry of table created by decoder, counted as allocated
+    fn set(&mut self, m: &Map, key: Value, val: Value) -> LuaResult<()> {
+        self.state.alloc(entry_size(&key, &val))?;
+        map_raw_set(m, key, val)
+    }
+
+    fn array(&mut self, m: &Map) -> LuaResult<()> {
+        self.expect(b'[')?;
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        for i in 1.. {
+            let val = self.value()?;
+            self.set(m, Value::Integer(i), val)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, m: &Map) -> LuaResult<()> {
+        self.expect(b'{')?;
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            let val = self.value()?;
+            self.set(m, Value::String(key), val)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+        Ok(())
+    }
+
+    fn hex4(&mut self) -> LuaResult<u32> {
+        let digits = self.s.get(self.pos..self.pos + 4);
+        let code = digits
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    /// `\uXXXX` escape with surrogate pairs, the `\u` is consumed
+    fn unicode(&mut self) -> LuaResult<char> {
+        let hi = self.hex4()?;
+        let code = match hi {
+            0xD800..=0xDBFF => {
+                if !self.s[self.pos..].starts_with(b"\\u") {
+                    return Err(self.error("missing low surrogate"));
+                }
+                self.pos += 2;
+                match self.hex4()? {
+                    lo @ 0xDC00..=0xDFFF => 0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00),
+                    _ => return Err(self.error("invalid low surrogate")),
+                }
+            }
+            0xDC00..=0xDFFF => return Err(self.error("unexpected low surrogate")),
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn string(&mut self) -> LuaResult<LuaString> {
+        self.pos += 1;
+        let mut buff = vec![];
+        loop {
+            let c = match self.s.get(self.pos) {
+                Some(c) => *c,
+                None => return Err(self.error("unfinished string")),
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = self.s.get(self.pos).copied();
+                    self.pos += 1;
+                    match e {
+                        Some(b'"') => buff.push(b'"'),
+                        Some(b'\\') => buff.push(b'\\'),
+                        Some(b'/') => buff.push(b'/'),
+                        Some(b'b') => buff.push(0x08),
+                        Some(b'f') => buff.push(0x0C),
+                        Some(b'n') => buff.push(b'\n'),
+                        Some(b'r') => buff.push(b'\r'),
+                        Some(b't') => buff.push(b'\t'),
+                        Some(b'u') => {
+                            let mut utf8 = [0; 4];
+                            let c = self.unicode()?;
+                            buff.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c if c < 0x20 => return Err(self.error("control character in string")),
+                c => buff.push(c),
+            }
+        }
+        self.state.alloc(buff.capacity())?;
+        Ok(buff.into())
+    }
+
+    /// integers in `i64` range are kept as integer, others are float
+    fn number(&mut self) -> LuaResult<Value> {
+        let start = self.pos;
+        let digits = |d: &mut Self| {
+            let begin = d.pos;
+            while d.s.get(d.pos).is_some_and(|c| c.is_ascii_digit()) {
+                d.pos += 1;
+            }
+            d.pos > begin
+        };
+
+        if self.s[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        // no leading zeros
+        if self.s.get(self.pos) == Some(&b'0') {
+            self.pos += 1;
+            if self.s.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("invalid number"));
+            }
+        } else if !digits(self) {
+            return Err(self.error("invalid number"));
+        }
+        let mut is_float = false;
+        if self.s.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            is_float = true;
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.s.get(self.pos) {
+            self.pos += 1;
+            is_float = true;
+            if let Some(b'+' | b'-') = self.s.get(self.pos) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+
+        let text = std::str::from_utf8(&self.s[start..self.pos]).unwrap();
+        if !is_float {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(Value::Integer(i));
+            }
+        }
+        text.parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
+
+/// `json.decode(s)`, `null` is decoded as `json.null`
+fn decode(state: &mut State) -> LuaResult<usize> {
+    // json text is utf-8
+    let s = state.check_str(1)?;
+    let mut decoder = Decoder {
+        null: state.uv_get_index(0),
+        state,
+        s: s.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let val = decoder.value()?;
+    if decoder.peek().is_some() {
+        return Err(decoder.error("trailing characters"));
+    }
+    decoder.state.push_value(val);
+    Ok(1)
+}
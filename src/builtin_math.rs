@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::builtin::arg;
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::value::Value;
+use crate::State;
+
+pub fn new_math_lib() -> Value {
+    let mut m = HashMap::new();
+    add_func!(m, random);
+    add_func!(m, randomseed);
+    Value::new_map(m)
+}
+
+/// pseudo-random generator `xoshiro256**`, same as Lua 5.4
+pub(in crate) struct Random {
+    s: [u64; 4],
+}
+
+impl Random {
+    pub(in crate) fn new(seed: u64) -> Self {
+        let mut rng = Random {
+            s: [seed, 0xff, 0, 0],
+        };
+        // discard initial values to spread the seed
+        (0..16).for_each(|_| {
+            rng.next_u64();
+        });
+        rng
+    }
+
+    /// seeded by the current time
+    pub(in crate) fn from_time() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        Self::new(now.map_or(0, |d| d.as_nanos() as u64))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let res = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        res
+    }
+
+    /// random integer in `[0, n]`
+    fn project(&mut self, ran: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        // smallest `2^b - 1` not less than `n`
+        let mut lim = n;
+        for shift in [1, 2, 4, 8, 16, 32] {
+            lim |= lim >> shift;
+        }
+        let mut ran = ran & lim;
+        while ran > n {
+            ran = self.next_u64() & lim;
+        }
+        ran
+    }
+}
+
+/// `math.random([m [, n]])`
+fn random(state: &mut State) -> LuaResult<usize> {
+    let ran = state.rng.next_u64();
+    let (low, up) = match state.top() {
+        0 => {
+            // float in `[0, 1)` with the higher 53 bits
+            state.push_value(Value::Float((ran >> 11) as f64 * (0.5f64).powi(53)));
+            return Ok(1);
+        }
+        1 => match state.check_integer(1)? {
+            // integer with all bits random
+            0 => {
+                state.push_value(Value::Integer(ran as i64));
+                return Ok(1);
+            }
+            up => (1, up),
+        },
+        2 => (state.check_integer(1)?, state.check_integer(2)?),
+        _ => return Err(LuaError::new("wrong number of arguments")),
+    };
+    if low > up {
+        return Err(state.arg_error(1, "interval is empty"));
+    }
+    let n = state.rng.project(ran, (up as u64).wrapping_sub(low as u64));
+    state.push_value(Value::Integer(n.wrapping_add(low as u64) as i64));
+    Ok(1)
+}
+
+/// `math.randomseed(x)`
+fn randomseed(state: &mut State) -> LuaResult<usize> {
+    let seed = match arg(state, 1) {
+        Value::Integer(i) => i as u64,
+        _ => state.check_number(1)?.to_bits(),
+    };
+    state.rng = Random::new(seed);
+    Ok(0)
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+use std::ptr;
+
+use crate::builtin::{arg, file_result};
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::state_map::map_raw_set;
+use crate::value::{Table, Value};
+use crate::State;
+
+/// conversion specifiers accepted by `os.date`, grouped by length
+/// same as `L_STRFTIMEC99` in `loslib.c`
+const STRFTIME_OPTIONS: [&str; 2] = [
+    "aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%",
+    "EcECExEXEyEYOdOeOHOIOmOMOSOuOUOVOwOWOy",
+];
+
+/// maximum value for date fields, avoid overflow of `c_int`
+const MAX_DATE_FIELD: i64 = (i32::MAX / 2) as i64;
+
+/// maximum size of single `strftime` item
+const TIME_FORMAT_SIZE: usize = 250;
+
+pub fn new_os_lib() -> Value {
+    let mut m = HashMap::new();
+    add_func!(m, clock);
+    add_func!(m, date);
+    add_func!(m, difftime);
+    add_func!(m, exit);
+    add_func!(m, getenv);
+    add_func!(m, remove);
+    add_func!(m, rename);
+    add_func!(m, time);
+    add_func!(m, tmpname);
+    Value::new_map(m)
+}
+
+/// C functions see the string until the first `\0`
+fn c_string(s: &str) -> CString {
+    CString::new(s.split('\0').next().unwrap_or("")).unwrap()
+}
+
+/// current time, the virtual clock in deterministic mode
+fn now(state: &State) -> libc::time_t {
+    match state.is_deterministic() {
+        true => state.clock.time as libc::time_t,
+        false => unsafe { libc::time(ptr::null_mut()) },
+    }
+}
+
+fn date_field(m: &Table, key: &str, d: i32, delta: i64) -> LuaResult<i32> {
+    match m.get(&Value::String(key.into())) {
+        None | Some(Value::Nil) if d < 0 => Err(LuaError::new(format!(
+            "field '{}' missing in date table",
+            key
+        ))),
+        None | Some(Value::Nil) => Ok(d),
+        Some(v) => match v.clone().into_integer() {
+            Ok(n) if (-MAX_DATE_FIELD..=MAX_DATE_FIELD).contains(&n) => Ok((n - delta) as i32),
+            Ok(_) => Err(LuaError::new(format!("field '{}' is out-of-bound", key))),
+            Err(_) => Err(LuaError::new(format!("field '{}' is not an integer", key))),
+        },
+    }
+}
+
+/// fields of date table, same as `setallfields` in `loslib.c`
+fn date_fields(stm: &libc::tm) -> Vec<(Value, Value)> {
+    let mut fields: Vec<(Value, Value)> = [
+        ("sec", stm.tm_sec),
+        ("min", stm.tm_min),
+        ("hour", stm.tm_hour),
+        ("day", stm.tm_mday),
+        ("month", stm.tm_mon + 1),
+        ("year", stm.tm_year + 1900),
+        ("wday", stm.tm_wday + 1),
+        ("yday", stm.tm_yday + 1),
+    ]
+    .iter()
+    .map(|&(key, val)| (Value::String(key.into()), Value::Integer(val as i64)))
+    .collect();
+    if stm.tm_isdst >= 0 {
+        fields.push((
+            Value::String("isdst".into()),
+            Value::Bool(stm.tm_isdst != 0),
+        ));
+    }
+    fields
+}
+
+fn date_table(stm: &libc::tm) -> Value {
+    Value::new_map(date_fields(stm).into_iter().collect())
+}
+
+/// find the longest valid conversion specifier at the beginning of `conv`
+fn check_option<'a>(state: &State, conv: &'a str) -> LuaResult<&'a str> {
+    for (index, options) in STRFTIME_OPTIONS.iter().enumerate() {
+        let len = index + 1;
+        if let Some(spec) = conv.get(..len) {
+            let mut iter = (0..options.len()).step_by(len);
+            if iter.any(|i| &options[i..i + len] == spec) {
+                return Ok(spec);
+            }
+        }
+    }
+
+    let msg = format!("invalid conversion specifier '%{}'", conv);
+    Err(state.arg_error(1, &msg))
+}
+
+fn strftime(spec: &str, stm: &libc::tm) -> String {
+    let mut buff = [0u8; TIME_FORMAT_SIZE];
+    let format = c_string(&format!("%{}", spec));
+    let len = unsafe {
+        libc::strftime(
+            buff.as_mut_ptr() as *mut libc::c_char,
+            TIME_FORMAT_SIZE,
+            format.as_ptr(),
+            stm,
+        )
+    };
+    String::from_utf8_lossy(&buff[..len]).to_string()
+}
+
+fn clock(state: &mut State) -> LuaResult<usize> {
+    if state.is_deterministic() {
+        state.push_value(Value::Float(state.clock.cpu));
+        return Ok(1);
+    }
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+    let secs = ts.tv_sec as f64 + ts.tv_nsec as f64 / 1e9;
+    state.push_value(Value::Float(secs));
+    Ok(1)
+}
+
+fn date(state: &mut State) -> LuaResult<usize> {
+    let format = state.opt_str(1, "%c")?;
+    let t = match arg(state, 2) {
+        Value::Nil => now(state),
+        _ => state.check_integer(2)? as libc::time_t,
+    };
+
+    let mut stm: libc::tm = unsafe { mem::zeroed() };
+    let (format, res) = match format.strip_prefix('!') {
+        Some(format) => (format, unsafe { libc::gmtime_r(&t, &mut stm) }),
+        // local time is UTC in deterministic mode
+        None if state.is_deterministic() => {
+            (format.as_str(), unsafe { libc::gmtime_r(&t, &mut stm) })
+        }
+        None => (format.as_str(), unsafe { libc::localtime_r(&t, &mut stm) }),
+    };
+    if res.is_null() {
+        return Err(LuaError::new(
+            "time result cannot be represented in this installation",
+        ));
+    }
+
+    if format == "*t" {
+        state.push_value(date_table(&stm));
+        return Ok(1);
+    }
+
+    let mut result = String::new();
+    let mut rest = format;
+    while let Some(pos) = rest.find('%') {
+        result.push_str(&rest[..pos]);
+        let spec = check_option(state, &rest[pos + 1..])?;
+        result.push_str(&strftime(spec, &stm));
+        rest = &rest[pos + 1 + spec.len()..];
+    }
+    result.push_str(rest);
+
+    state.push_value(Value::String(result.into()));
+    Ok(1)
+}
+
+fn difftime(state: &mut State) -> LuaResult<usize> {
+    let t1 = state.check_integer(1)?;
+    let t2 = state.opt_integer(2, 0)?;
+    state.push_value(Value::Float(t1 as f64 - t2 as f64));
+    Ok(1)
+}
+
+fn exit(state: &mut State) -> LuaResult<usize> {
+    let code = match arg(state, 1) {
+        Value::Bool(ok) => (!ok) as i32,
+        Value::Nil => 0,
+        _ => state.check_integer(1)? as i32,
+    };
+    Err(LuaError::Exit(code))
+}
+
+fn getenv(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    state.push_value(match env::var_os(name) {
+        Some(val) => Value::String(val.into_vec().into()),
+        None => Value::Nil,
+    });
+    Ok(1)
+}
+
+/// convert return value of C functions into `io::Result`
+fn c_result(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn remove(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    let res = state.sandbox_path(&name).and_then(|path| {
+        let path = c_string(&path.to_string_lossy());
+        c_result(unsafe { libc::remove(path.as_ptr()) })
+    });
+    file_result(state, res, Some(&name))
+}
+
+fn rename(state: &mut State) -> LuaResult<usize> {
+    let from = state.check_str(1)?;
+    let to = state.check_str(2)?;
+    let res = state.sandbox_path(&from).and_then(|from| {
+        let from = c_string(&from.to_string_lossy());
+        let to = c_string(&state.sandbox_path(&to)?.to_string_lossy());
+        c_result(unsafe { libc::rename(from.as_ptr(), to.as_ptr()) })
+    });
+    file_result(state, res, None)
+}
+
+fn time(state: &mut State) -> LuaResult<usize> {
+    let t = match arg(state, 1) {
+        Value::Nil => now(state),
+        Value::Map(map) => {
+            let m = map.borrow();
+            let mut ts: libc::tm = unsafe { mem::zeroed() };
+            ts.tm_sec = date_field(&m, "sec", 0, 0)?;
+            ts.tm_min = date_field(&m, "min", 0, 0)?;
+            ts.tm_hour = date_field(&m, "hour", 12, 0)?;
+            ts.tm_mday = date_field(&m, "day", -1, 0)?;
+            ts.tm_mon = date_field(&m, "month", -1, 1)?;
+            ts.tm_year = date_field(&m, "year", -1, 1900)?;
+            ts.tm_isdst = match m.get(&Value::String("isdst".into())) {
+                None | Some(Value::Nil) => -1,
+                Some(v) => v.clone().into_boolean() as i32,
+            };
+            drop(m);
+            let t = match state.is_deterministic() {
+                true => unsafe { libc::timegm(&mut ts) },
+                false => unsafe { libc::mktime(&mut ts) },
+            };
+            // update fields with normalized values
+            for (key, val) in date_fields(&ts) {
+                map_raw_set(&map, key, val)?;
+            }
+            t
+        }
+        _ => return Err(state.type_error(1, "table")),
+    };
+
+    if t == -1 {
+        return Err(LuaError::new(
+            "time result cannot be represented in this installation",
+        ));
+    }
+    state.push_value(Value::Integer(t as i64));
+    Ok(1)
+}
+
+fn tmpname(state: &mut State) -> LuaResult<usize> {
+    let mut template = *b"/tmp/lua_XXXXXX\0";
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if fd == -1 {
+        return Err(LuaError::new("unable to generate a unique filename"));
+    }
+
+    unsafe { libc::close(fd) };
+    state.push_value(Value::String(template[..template.len() - 1].into()));
+    Ok(1)
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+
+use crate::builtin::load_file;
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::gc;
+use crate::stack::new_upval;
+use crate::value::{Map, Value};
+use crate::value_conv::IntoLua;
+use crate::State;
+
+/// registry key of `package.loaded`
+pub const LOADED: &str = "_LOADED";
+
+/// registry key of `package.preload`
+pub const PRELOAD: &str = "_PRELOAD";
+
+/// default `package.path`, only chunks precompiled by `luac` can be loaded
+const PATH_DEFAULT: &str = "/usr/local/share/lua/5.3/?.luac;/usr/local/share/lua/5.3/?/init.luac;\
+                            /usr/local/lib/lua/5.3/?.luac;/usr/local/lib/lua/5.3/?/init.luac;\
+                            ./?.luac;./?/init.luac";
+
+/// `package.config`: directory separator, template separator, substitution mark,
+/// executable directory mark and ignore mark
+const CONFIG: &str = "/\n;\n?\n!\n-\n";
+
+/// create closure of builtin `f` whose upvalue is `package`
+fn with_package(f: fn(&mut State) -> LuaResult<usize>, package: &Value) -> Value {
+    let mut func = Closure::with_builtin(f, 1);
+    func.upval[0] = new_upval(package.clone());
+    gc::new_function(func)
+}
+
+/// `package.path` from environment `LUA_PATH_5_3` or `LUA_PATH`,
+/// `;;` is replaced by the default path
+fn init_path() -> String {
+    let path = match env::var("LUA_PATH_5_3").or_else(|_| env::var("LUA_PATH")) {
+        Ok(path) => path,
+        Err(_) => return PATH_DEFAULT.to_string(),
+    };
+    let path = path.replacen(";;", &format!(";{};", PATH_DEFAULT), 1);
+    path.trim_matches(';').to_string()
+}
+
+pub fn new_package_lib(registry: &mut HashMap<Value, Value>) -> Value {
+    let loaded = Value::new_map(HashMap::new());
+    let preload = Value::new_map(HashMap::new());
+    registry.insert(Value::String(LOADED.into()), loaded.clone());
+    registry.insert(Value::String(PRELOAD.into()), preload.clone());
+
+    let mut m = HashMap::new();
+    add_func!(m, searchpath);
+    let set = |m: &mut HashMap<Value, Value>, k: &str, v: Value| {
+        m.insert(Value::String(k.into()), v);
+    };
+    set(&mut m, "config", Value::String(CONFIG.into()));
+    set(&mut m, "loaded", loaded);
+    set(&mut m, "path", Value::String(init_path().into()));
+    set(&mut m, "preload", preload);
+    let package = Value::new_map(m);
+
+    let searchers = [searcher_preload, searcher_lua]
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (Value::Integer(i as i64 + 1), with_package(*f, &package)))
+        .collect();
+    if let Value::Map(m) = &package {
+        let mut m = m.borrow_mut();
+        m.insert(
+            Value::String("searchers".into()),
+            Value::new_map(searchers),
+        );
+    }
+    package
+}
+
+/// global function `require` which shares upvalue `package` with searchers
+pub fn new_require(package: &Value) -> Value {
+    with_package(require, package)
+}
+
+/// get field `k` of table `package` in upvalue
+fn package_field(state: &mut State, k: &str) -> Value {
+    match state.uv_get_index(0) {
+        Value::Map(m) => {
+            let key = Value::String(k.into());
+            m.borrow().get(&key).cloned().unwrap_or(Value::Nil)
+        }
+        _ => Value::Nil,
+    }
+}
+
+fn loaded_map(state: &State) -> LuaResult<Map> {
+    match state.registry_get(LOADED) {
+        Value::Map(m) => Ok(m),
+        _ => Err(LuaError::new("'package.loaded' must be a table")),
+    }
+}
+
+fn require(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    let key = Value::String(name.as_str().into());
+    let loaded = loaded_map(state)?;
+    let val = loaded.borrow().get(&key).cloned();
+    if let Some(val) = val.filter(|v| v.clone().into_boolean()) {
+        state.push_value(val);
+        return Ok(1);
+    }
+
+    let (loader, extra) = find_loader(state, &name)?;
+    let res = state.call_value(loader, vec![key.clone(), extra])?;
+    if let Some(val) = res.into_iter().next().filter(|v| *v != Value::Nil) {
+        loaded.borrow_mut().insert(key.clone(), val);
+    }
+    // use `true` if loader did not set any value
+    let val = loaded.borrow().get(&key).cloned();
+    let val = val.unwrap_or_else(|| {
+        loaded.borrow_mut().insert(key, Value::Bool(true));
+        Value::Bool(true)
+    });
+    state.push_value(val);
+    Ok(1)
+}
+
+/// try each of `package.searchers`, return the loader and its extra value
+fn find_loader(state: &mut State, name: &str) -> LuaResult<(Value, Value)> {
+    let searchers = match package_field(state, "searchers") {
+        Value::Map(m) => m,
+        _ => return Err(LuaError::new("'package.searchers' must be a table")),
+    };
+
+    let mut msg = String::new();
+    for i in 1.. {
+        let searcher = searchers.borrow().get(&Value::Integer(i)).cloned();
+        let searcher = match searcher {
+            Some(f) => f,
+            None => break,
+        };
+        let mut res = state
+            .call_value(searcher, vec![Value::String(name.into())])?
+            .into_iter();
+        match res.next() {
+            Some(f @ Value::Function(_)) => return Ok((f, res.next().unwrap_or(Value::Nil))),
+            Some(Value::String(s)) => msg.push_str(&s.to_string_lossy()),
+            _ => {}
+        }
+    }
+    Err(LuaError::new(format!(
+        "module '{}' not found:{}",
+        name, msg
+    )))
+}
+
+fn searcher_preload(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_string(1)?;
+    let loader = match state.registry_get(PRELOAD) {
+        Value::Map(m) => m.borrow().get(&Value::String(name.clone())).cloned(),
+        _ => return Err(LuaError::new("'package.preload' must be a table")),
+    };
+    match loader {
+        Some(loader) => state.push_value(loader),
+        None => state.push_value(Value::String(
+            format!("\n\tno field package.preload['{}']", name).into(),
+        )),
+    }
+    Ok(1)
+}
+
+fn searcher_lua(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    let path = match package_field(state, "path") {
+        Value::String(path) => path.into_string().map_err(|_| {
+            LuaError::new("'package.path' must be a valid UTF-8 string")
+        })?,
+        _ => return Err(LuaError::new("'package.path' must be a string")),
+    };
+    let filename = match search_path(state, &name, &path, ".", "/") {
+        Ok(filename) => filename,
+        Err(msg) => {
+            state.push_value(Value::String(msg.into()));
+            return Ok(1);
+        }
+    };
+
+    match load_file(state, Some(&filename), "b", None) {
+        Ok(func) => {
+            state.push_value(func);
+            state.push_value(Value::String(filename.into()));
+            Ok(2)
+        }
+        Err(msg) => Err(LuaError::new(format!(
+            "error loading module '{}' from file '{}':\n\t{}",
+            name, filename, msg
+        ))),
+    }
+}
+
+/// find the first readable file in `path` with `name`, return the tried files if not found
+fn search_path(
+    state: &State,
+    name: &str,
+    path: &str,
+    sep: &str,
+    rep: &str,
+) -> Result<String, String> {
+    let name = match sep.is_empty() {
+        true => name.to_string(),
+        false => name.replace(sep, rep),
+    };
+    let mut msg = String::new();
+    for template in path.split(';').filter(|t| !t.is_empty()) {
+        let filename = template.replace('?', &name);
+        if state.sandbox_path(&filename).and_then(File::open).is_ok() {
+            return Ok(filename);
+        }
+        msg.push_str(&format!("\n\tno file '{}'", filename));
+    }
+    Err(msg)
+}
+
+fn searchpath(state: &mut State) -> LuaResult<usize> {
+    let name = state.check_str(1)?;
+    let path = state.check_str(2)?;
+    let sep = state.opt_str(3, ".")?;
+    let rep = state.opt_str(4, "/")?;
+    match search_path(state, &name, &path, &sep, &rep) {
+        Ok(filename) => {
+            state.push_value(Value::String(filename.into()));
+            Ok(1)
+        }
+        Err(msg) => {
+            state.push_value(Value::Nil);
+            state.push_value(Value::String(msg.into()));
+            Ok(2)
+        }
+    }
+}
+
+impl State {
+    /// register native module `name`, `loader` is called with the module name
+    /// by the first `require(name)` and its result is kept in `package.loaded`
+    pub fn preload<R, F>(&mut self, name: &str, loader: F) -> LuaResult<()>
+    where
+        R: IntoLua,
+        F: Fn(&mut State, String) -> LuaResult<R> + 'static,
+    {
+        let f = self.create_function(move |state, (name, _): (String, Value)| {
+            loader(state, name).map(|m| (m,))
+        });
+        match self.registry_get(PRELOAD) {
+            Value::Map(m) => {
+                m.borrow_mut().insert(Value::String(name.into()), f);
+                Ok(())
+            }
+            _ => Err(LuaError::new("'package.preload' must be a table")),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::builtin::arg;
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::value::Value;
+use crate::State;
+
+/// maximum code point, same as `MAXUNICODE` in `lutf8lib.c`
+const MAX_UNICODE: u32 = 0x10FFFF;
+
+/// pattern matching exactly one UTF-8 byte sequence
+const CHAR_PATTERN: &[u8] = b"[\0-\x7F\xC2-\xF4][\x80-\xBF]*";
+
+pub fn new_utf8_lib() -> Value {
+    let mut m = HashMap::new();
+    add_func!(m, "char", utf8_char);
+    add_func!(m, codepoint);
+    add_func!(m, codes);
+    add_func!(m, len);
+    add_func!(m, offset);
+    m.insert(
+        Value::String("charpattern".into()),
+        Value::String(CHAR_PATTERN.into()),
+    );
+    Value::new_map(m)
+}
+
+/// translate a relative string position, negative means back from end
+fn pos_relat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
+
+fn is_cont(s: &[u8], i: usize) -> bool {
+    s.get(i).is_some_and(|c| c & 0xC0 == 0x80)
+}
+
+/// decode one UTF-8 sequence at the beginning of `s`, return code and its length
+fn decode(s: &[u8]) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 4] = [0xFF, 0x7F, 0x7FF, 0xFFFF];
+
+    let mut c = *s.first()? as u32;
+    if c < 0x80 {
+        return Some((c, 1));
+    }
+
+    let mut res = 0;
+    let mut count = 0;
+    while c & 0x40 != 0 {
+        count += 1;
+        let cc = s.get(count).copied().unwrap_or(0) as u32;
+        if cc & 0xC0 != 0x80 {
+            return None;
+        }
+        res = (res << 6) | (cc & 0x3F);
+        c <<= 1;
+    }
+    if count > 3 {
+        return None;
+    }
+    res |= (c & 0x7F) << (count * 5);
+    if res > MAX_UNICODE || res <= LIMITS[count] {
+        return None;
+    }
+    Some((res, count + 1))
+}
+
+/// surrogates are rejected, they have no UTF-8 encoding
+fn utf8_char(state: &mut State) -> LuaResult<usize> {
+    let mut buff = String::new();
+    for n in 1..=state.top() {
+        let code = state.check_integer(n)?;
+        let c = match (0..=MAX_UNICODE as i64).contains(&code) {
+            true => char::from_u32(code as u32),
+            false => None,
+        };
+        match c {
+            Some(c) => buff.push(c),
+            None => return Err(state.arg_error(n, "value out of range")),
+        }
+    }
+    state.push_value(Value::String(buff.into()));
+    Ok(1)
+}
+
+fn codepoint(state: &mut State) -> LuaResult<usize> {
+    let s = state.check_string(1)?;
+    let s = s.as_bytes();
+    let posi = pos_relat(state.opt_integer(2, 1)?, s.len());
+    let pose = pos_relat(state.opt_integer(3, posi)?, s.len());
+    if posi < 1 {
+        return Err(state.arg_error(2, "out of range"));
+    }
+    if pose > s.len() as i64 {
+        return Err(state.arg_error(3, "out of range"));
+    }
+    if posi > pose {
+        return Ok(0);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(LuaError::new("string slice too long"));
+    }
+
+    let mut codes = vec![];
+    let mut pos = posi as usize - 1;
+    while pos < pose as usize {
+        let (code, len) = decode(&s[pos..]).ok_or_else(|| LuaError::new("invalid UTF-8 code"))?;
+        codes.push(Value::Integer(code as i64));
+        pos += len;
+    }
+
+    let n = codes.len();
+    state.ensure_stack(n, "string slice too long")?;
+    codes.into_iter().for_each(|v| state.push_value(v));
+    Ok(n)
+}
+
+fn len(state: &mut State) -> LuaResult<usize> {
+    let s = state.check_string(1)?;
+    let s = s.as_bytes();
+    let mut posi = pos_relat(state.opt_integer(2, 1)?, s.len());
+    let mut posj = pos_relat(state.opt_integer(3, -1)?, s.len());
+    if posi < 1 || posi - 1 > s.len() as i64 {
+        return Err(state.arg_error(2, "initial position out of string"));
+    }
+    posi -= 1;
+    posj -= 1;
+    if posj >= s.len() as i64 {
+        return Err(state.arg_error(3, "final position out of string"));
+    }
+
+    let mut n = 0;
+    while posi <= posj {
+        match decode(&s[posi as usize..]) {
+            Some((_, len)) => posi += len as i64,
+            None => {
+                // position of the invalid byte
+                state.push_value(Value::Nil);
+                state.push_value(Value::Integer(posi + 1));
+                return Ok(2);
+            }
+        }
+        n += 1;
+    }
+    state.push_value(Value::Integer(n));
+    Ok(1)
+}
+
+fn offset(state: &mut State) -> LuaResult<usize> {
+    let s = state.check_string(1)?;
+    let s = s.as_bytes();
+    let mut n = state.check_integer(2)?;
+    let default = if n >= 0 { 1 } else { s.len() as i64 + 1 };
+    let posi = pos_relat(state.opt_integer(3, default)?, s.len());
+    if posi < 1 || posi - 1 > s.len() as i64 {
+        return Err(state.arg_error(3, "position out of range"));
+    }
+
+    let mut posi = posi as usize - 1;
+    if n == 0 {
+        // find beginning of current byte sequence
+        while posi > 0 && is_cont(s, posi) {
+            posi -= 1;
+        }
+    } else if is_cont(s, posi) {
+        return Err(LuaError::new("initial position is a continuation byte"));
+    } else if n < 0 {
+        while n < 0 && posi > 0 {
+            posi -= 1;
+            while posi > 0 && is_cont(s, posi) {
+                posi -= 1;
+            }
+            n += 1;
+        }
+    } else {
+        // do not move for the first character
+        n -= 1;
+        while n > 0 && posi < s.len() {
+            posi += 1;
+            while is_cont(s, posi) {
+                posi += 1;
+            }
+            n -= 1;
+        }
+    }
+
+    state.push_value(match n {
+        0 => Value::Integer(posi as i64 + 1),
+        _ => Value::Nil,
+    });
+    Ok(1)
+}
+
+fn iter_codes(state: &mut State) -> LuaResult<usize> {
+    let s = state.check_string(1)?;
+    let s = s.as_bytes();
+    let mut n = arg(state, 2).into_integer().unwrap_or(0) - 1;
+    if n < 0 {
+        n = 0;
+    } else if n < s.len() as i64 {
+        // skip current byte and its continuations
+        n += 1;
+        while is_cont(s, n as usize) {
+            n += 1;
+        }
+    }
+    if n >= s.len() as i64 {
+        return Ok(0);
+    }
+
+    match decode(&s[n as usize..]) {
+        Some((code, len)) if !is_cont(s, n as usize + len) => {
+            state.push_value(Value::Integer(n + 1));
+            state.push_value(Value::Integer(code as i64));
+            Ok(2)
+        }
+        _ => Err(LuaError::new("invalid UTF-8 code")),
+    }
+}
+
+fn codes(state: &mut State) -> LuaResult<usize> {
+    let s = state.check_string(1)?;
+    state.push_value(Value::Function(Rc::new(Closure::with_builtin(
+        iter_codes, 0,
+    ))));
+    state.push_value(Value::String(s));
+    state.push_value(Value::Integer(0));
+    Ok(3)
+}
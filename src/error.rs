@@ -0,0 +1,65 @@
+use std::fmt;
+
+use crate::value::{IntoError, LuaString, Value};
+
+pub type LuaResult<T> = Result<T, LuaError>;
+
+pub enum LuaError {
+    /// error raised by script or runtime, carry the error value
+    Runtime(Value),
+    /// script ask to terminate the host with exit code by `os.exit`
+    Exit(i32),
+    /// script is aborted by host, it can not be caught by `pcall`
+    Interrupted,
+    /// value can not be converted to the rust type by `FromLua`
+    FromLua {
+        from: &'static str,
+        to: &'static str,
+        message: Option<String>,
+    },
+}
+
+impl LuaError {
+    pub fn new<S: Into<LuaString>>(msg: S) -> Self {
+        LuaError::Runtime(Value::String(msg.into()))
+    }
+
+    pub(in crate) fn from_lua(
+        from: &'static str,
+        to: &'static str,
+        message: Option<String>,
+    ) -> Self {
+        LuaError::FromLua { from, to, message }
+    }
+}
+
+impl From<IntoError> for LuaError {
+    fn from(e: IntoError) -> Self {
+        LuaError::new(e.to_string())
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaError::Runtime(v) => write!(f, "{}", v),
+            LuaError::Exit(code) => write!(f, "exit with code {}", code),
+            LuaError::Interrupted => write!(f, "interrupted"),
+            LuaError::FromLua { from, to, message } => {
+                write!(f, "error converting Lua {} to {}", from, to)?;
+                match message {
+                    Some(msg) => write!(f, " ({})", msg),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for LuaError {}
+
+impl fmt::Debug for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
@@ -1,17 +1,24 @@
-use std::cell::RefCell;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+use crate::error::LuaResult;
+use crate::gc;
 use crate::prototype::Prototype;
-use crate::value::{MutValue, Value};
+use crate::stack::{new_upval, UpvalRef};
+use crate::value::Value;
 use crate::State;
 
-pub type BuiltinFunc = fn(&mut State) -> usize;
+pub type BuiltinFunc = fn(&mut State) -> LuaResult<usize>;
+
+/// host function which may capture its environment, called like `BuiltinFunc`
+pub type RustFunc = Rc<dyn Fn(&mut State) -> LuaResult<usize>>;
 
 #[derive(Clone)]
 pub struct Closure {
     pub proto: Func,
-    pub upval: Vec<MutValue>,
+    pub upval: Vec<UpvalRef>,
+    /// creation order, see `gc::next_seq`
+    pub(in crate) seq: u64,
 }
 
 impl Hash for Closure {
@@ -24,37 +31,48 @@ impl Hash for Closure {
 pub enum Func {
     Proto(Rc<Prototype>),
     Builtin(BuiltinFunc),
+    Rust(RustFunc),
 }
 
 impl Hash for Func {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Func::Proto(p) => p.hash(state),
-            Func::Builtin(_) => "rsfunc".hash(state),
+            Func::Builtin(_) | Func::Rust(_) => "rsfunc".hash(state),
         }
     }
 }
 
 impl Closure {
     pub fn with_proto(proto: Rc<Prototype>) -> Self {
-        let mut upval = vec![];
-        for _ in 0..(proto.upvalue.len()) {
-            upval.push(Rc::from(RefCell::new(Value::Nil)));
-        }
+        let upval = (0..proto.upvalue.len())
+            .map(|_| new_upval(Value::Nil))
+            .collect();
+        Self::with_upvals(proto, upval)
+    }
+
+    /// closure of `proto` capturing `upval`
+    pub fn with_upvals(proto: Rc<Prototype>, upval: Vec<UpvalRef>) -> Self {
         Closure {
             upval,
             proto: Func::Proto(proto),
+            seq: gc::next_seq(),
         }
     }
 
     pub fn with_builtin(f: BuiltinFunc, n: usize) -> Self {
-        let mut upval = vec![];
-        for _ in 0..n {
-            upval.push(Rc::from(RefCell::new(Value::Nil)));
-        }
         Closure {
-            upval,
+            upval: (0..n).map(|_| new_upval(Value::Nil)).collect(),
             proto: Func::Builtin(f),
+            seq: gc::next_seq(),
+        }
+    }
+
+    pub fn with_rust(f: RustFunc) -> Self {
+        Closure {
+            upval: vec![],
+            proto: Func::Rust(f),
+            seq: gc::next_seq(),
         }
     }
 }
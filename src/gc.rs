@@ -0,0 +1,548 @@
+//! tracing collector for cycles of tables, closures and upvalues
+//!
+//! objects are reference counted so acyclic garbage is freed at once, the
+//! collector only has to find cycles which are not reachable anymore.
+//! tables and closures with upvalues are tracked when created, a collection
+//! counts the references among tracked objects, whatever holds more than
+//! that is referenced from outside: the registry, the call chain of a state
+//! or a handle of host. objects are reachable if they can be traced from
+//! those roots, the others are cleared to break their cycles.
+//!
+//! weak references of tables with `__mode` are not traced, their entries are
+//! removed once the weak key or value is unreachable. values of weak keys
+//! are ephemerons, they are only reachable if their keys are.
+//!
+//! objects marked for finalization by their state are resurrected when found
+//! unreachable, they are queued with everything they reference and freed
+//! after their `__gc` metamethod if they are unreachable again.
+//!
+//! the heap is shared by all states of a thread, a collection started by any
+//! of them traces every tracked object. whether and when collections start
+//! automatically is set for each state, memory is counted from its roots.
+
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::error::LuaResult;
+use crate::func::Closure;
+use crate::stack::{Upval, UpvalRef};
+use crate::userdata::LuaUserData;
+use crate::value::{Map, Table, Value};
+use crate::State;
+
+/// collect if tracked objects grow more than this since the last collection
+const MIN_THRESHOLD: usize = 1024;
+
+/// weak handles of tracked objects, dead ones are removed by collections
+struct Heap {
+    tables: Vec<Weak<RefCell<Table>>>,
+    closures: Vec<Weak<Closure>>,
+    /// tracked objects alive after the last collection
+    live: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const { RefCell::new(Heap {
+        tables: vec![],
+        closures: vec![],
+        live: 0,
+    }) };
+}
+
+/// parameters of collections started automatically by a state
+pub(in crate) struct GcParams {
+    running: bool,
+    /// percent of live objects to wait for before the next collection
+    pause: u32,
+}
+
+impl Default for GcParams {
+    fn default() -> Self {
+        GcParams {
+            running: true,
+            pause: 200,
+        }
+    }
+}
+
+thread_local! {
+    static SEQ: Cell<u64> = const { Cell::new(0) };
+}
+
+/// number of objects created before, deterministic traversal orders
+/// reference keys by it
+pub(in crate) fn next_seq() -> u64 {
+    SEQ.with(|seq| {
+        seq.set(seq.get() + 1);
+        seq.get()
+    })
+}
+
+/// objects waiting for their `__gc` metamethod and the order they were marked
+pub(in crate) type GcQueue = Rc<RefCell<Vec<(u64, Value)>>>;
+
+/// pending `__gc` of an object, it is queued once the object is unreachable
+pub(in crate) struct Finalizer {
+    queue: Weak<RefCell<Vec<(u64, Value)>>>,
+    order: u64,
+}
+
+impl Finalizer {
+    pub(in crate) fn new(queue: &GcQueue, order: u64) -> Self {
+        Finalizer {
+            queue: Rc::downgrade(queue),
+            order,
+        }
+    }
+
+    /// queue `obj` to be finalized, unless its state is closed
+    pub(in crate) fn push(self, obj: Value) {
+        if let Some(queue) = self.queue.upgrade() {
+            queue.borrow_mut().push((self.order, obj));
+        }
+    }
+}
+
+/// object marked for finalization
+pub(in crate) enum Finalizable {
+    Table(Weak<RefCell<Table>>),
+    UserData(Weak<LuaUserData>),
+}
+
+impl Finalizable {
+    pub(in crate) fn is_alive(&self) -> bool {
+        match self {
+            Finalizable::Table(m) => m.strong_count() > 0,
+            Finalizable::UserData(ud) => ud.strong_count() > 0,
+        }
+    }
+
+    /// take the pending finalizer of the object if it is alive
+    pub(in crate) fn take(&self) -> Option<(Finalizer, Value)> {
+        match self {
+            Finalizable::Table(m) => {
+                let m = m.upgrade()?;
+                let f = m.try_borrow_mut().ok()?.gc.take()?;
+                Some((f, Value::Map(m)))
+            }
+            Finalizable::UserData(ud) => {
+                let ud = ud.upgrade()?;
+                let f = ud.take_finalizer()?;
+                Some((f, Value::UserData(ud)))
+            }
+        }
+    }
+}
+
+/// create a table tracked by collector
+pub fn new_table(m: HashMap<Value, Value>) -> Map {
+    track_table(Table::new(m))
+}
+
+/// move table to heap tracked by collector
+pub fn track_table(t: Table) -> Map {
+    let m = Rc::new(RefCell::new(t));
+    HEAP.with(|heap| heap.borrow_mut().tables.push(Rc::downgrade(&m)));
+    m
+}
+
+/// create a function, closures with upvalues are tracked by collector
+pub fn new_function(c: Closure) -> Value {
+    let c = Rc::new(c);
+    if !c.upval.is_empty() {
+        HEAP.with(|heap| heap.borrow_mut().closures.push(Rc::downgrade(&c)));
+    }
+    Value::Function(c)
+}
+
+/// objects traced by a collection
+enum Node {
+    Table(Map),
+    Closure(Rc<Closure>),
+    Upvalue(UpvalRef),
+    UserData(Rc<LuaUserData>),
+}
+
+impl Node {
+    fn from_value(v: &Value) -> Option<Node> {
+        match v {
+            Value::Map(m) => Some(Node::Table(m.clone())),
+            Value::Function(c) => Some(Node::Closure(c.clone())),
+            Value::UserData(ud) => Some(Node::UserData(ud.clone())),
+            _ => None,
+        }
+    }
+
+    fn ptr(&self) -> *const () {
+        match self {
+            Node::Table(m) => Rc::as_ptr(m) as *const (),
+            Node::Closure(c) => Rc::as_ptr(c) as *const (),
+            Node::Upvalue(uv) => Rc::as_ptr(uv) as *const (),
+            Node::UserData(ud) => Rc::as_ptr(ud) as *const (),
+        }
+    }
+
+    /// references held outside of the collector
+    fn strong_count(&self) -> usize {
+        let count = match self {
+            Node::Table(m) => Rc::strong_count(m),
+            Node::Closure(c) => Rc::strong_count(c),
+            Node::Upvalue(uv) => Rc::strong_count(uv),
+            Node::UserData(ud) => Rc::strong_count(ud),
+        };
+        count - 1
+    }
+}
+
+/// references from a traced object to others
+#[derive(Default)]
+struct Edges {
+    /// the object is in use and can not be traced, it is kept as a root
+    opaque: bool,
+    strong: Vec<usize>,
+    /// references which do not keep objects alive
+    weak: Vec<usize>,
+    /// entries of table with weak keys, the value is alive if the key is
+    ephemerons: Vec<(usize, usize)>,
+}
+
+/// whether keys and values of `t` are weak by `__mode` of its metatable
+pub fn weak_mode(t: &Table) -> (bool, bool) {
+    let mode = t.meta.as_ref().and_then(|meta| {
+        let meta = meta.try_borrow().ok()?;
+        meta.get(&Value::String("__mode".into())).cloned()
+    });
+    match mode {
+        Some(Value::String(mode)) => (mode.contains(&b'k'), mode.contains(&b'v')),
+        _ => (false, false),
+    }
+}
+
+/// tracked objects and those they reference
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    index: HashMap<*const (), usize>,
+    edges: Vec<Edges>,
+}
+
+impl Graph {
+    fn add(&mut self, node: Node) -> usize {
+        match self.index.entry(node.ptr()) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => {
+                e.insert(self.nodes.len());
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn value(&mut self, v: &Value) -> Option<usize> {
+        Node::from_value(v).map(|node| self.add(node))
+    }
+
+    fn is_reachable(&self, reachable: &[bool], v: &Value) -> bool {
+        match Node::from_value(v) {
+            Some(node) => reachable[self.index[&node.ptr()]],
+            None => true,
+        }
+    }
+
+    fn trace(&mut self, i: usize) -> Edges {
+        let mut edges = Edges::default();
+        match &self.nodes[i] {
+            Node::Table(m) => {
+                let m = m.clone();
+                let t = match m.try_borrow() {
+                    Ok(t) => t,
+                    Err(_) => {
+                        edges.opaque = true;
+                        return edges;
+                    }
+                };
+                if let Some(meta) = &t.meta {
+                    edges.strong.push(self.add(Node::Table(meta.clone())));
+                }
+                // keys kept for traversals do not keep objects alive
+                if let Some(keys) = &t.sorted {
+                    edges.weak.extend(keys.iter().filter_map(|k| self.value(k)));
+                }
+                // object keys are in the hash part, held by its nodes and its index
+                let keys = |k: Option<usize>| k.into_iter().chain(k);
+                for k in t.dead_keys() {
+                    edges.weak.extend(keys(self.value(k)));
+                }
+
+                let (weak_k, weak_v) = weak_mode(&t);
+                for (k, v) in t.iter() {
+                    let (k, v) = (self.value(&k), self.value(v));
+                    match (weak_k, weak_v) {
+                        (false, false) => edges.strong.extend(keys(k).chain(v)),
+                        (false, true) => {
+                            edges.strong.extend(keys(k));
+                            edges.weak.extend(v);
+                        }
+                        (true, false) => match (k, v) {
+                            (Some(k), Some(v)) => {
+                                edges.weak.extend(keys(Some(k)));
+                                edges.ephemerons.push((k, v));
+                            }
+                            (k, v) => {
+                                edges.weak.extend(keys(k));
+                                edges.strong.extend(v);
+                            }
+                        },
+                        (true, true) => edges.weak.extend(keys(k).chain(v)),
+                    }
+                }
+            }
+            Node::Closure(c) => {
+                let upval = c.upval.clone();
+                edges.strong = upval
+                    .into_iter()
+                    .map(|uv| self.add(Node::Upvalue(uv)))
+                    .collect();
+            }
+            // value of open upvalue is on the stack which is a root
+            Node::Upvalue(uv) => match uv.clone().try_borrow().as_deref() {
+                Ok(Upval::Closed(v)) => edges.strong.extend(self.value(v)),
+                Ok(Upval::Open(_)) => {}
+                Err(_) => edges.opaque = true,
+            },
+            // content of userdata is opaque, values held by it are roots
+            Node::UserData(_) => {}
+        }
+        edges
+    }
+
+    /// tracked objects still alive and everything they reference
+    fn build() -> Graph {
+        let mut graph = Graph::default();
+        let (tables, closures) = HEAP.with(|heap| {
+            let heap = heap.borrow();
+            let tables: Vec<_> = heap.tables.iter().filter_map(Weak::upgrade).collect();
+            let closures: Vec<_> = heap.closures.iter().filter_map(Weak::upgrade).collect();
+            (tables, closures)
+        });
+        tables.into_iter().for_each(|m| {
+            graph.add(Node::Table(m));
+        });
+        closures.into_iter().for_each(|c| {
+            graph.add(Node::Closure(c));
+        });
+
+        let mut i = 0;
+        while i < graph.nodes.len() {
+            let edges = graph.trace(i);
+            graph.edges.push(edges);
+            i += 1;
+        }
+        graph
+    }
+
+    /// objects referenced from outside of the collector
+    fn roots(&self) -> Vec<usize> {
+        let mut external: Vec<usize> = self.nodes.iter().map(Node::strong_count).collect();
+        for edges in self.edges.iter() {
+            let values = edges.ephemerons.iter().map(|(_, v)| v);
+            for &i in edges.strong.iter().chain(edges.weak.iter()).chain(values) {
+                external[i] -= 1;
+            }
+        }
+        (0..self.nodes.len())
+            .filter(|&i| external[i] > 0 || self.edges[i].opaque)
+            .collect()
+    }
+
+    /// mark objects reachable from `pending`
+    fn mark(&self, reachable: &mut [bool], mut pending: Vec<usize>) {
+        loop {
+            while let Some(i) = pending.pop() {
+                if mem::replace(&mut reachable[i], true) {
+                    continue;
+                }
+                pending.extend(self.edges[i].strong.iter().filter(|&&c| !reachable[c]));
+            }
+            // values of ephemerons are reachable once their keys are
+            for (t, edges) in self.edges.iter().enumerate() {
+                if !reachable[t] {
+                    continue;
+                }
+                let entries = edges.ephemerons.iter();
+                pending.extend(
+                    entries
+                        .filter(|&&(k, v)| reachable[k] && !reachable[v])
+                        .map(|&(_, v)| v),
+                );
+            }
+            if pending.is_empty() {
+                break;
+            }
+        }
+    }
+
+    /// unreachable objects which are marked for finalization
+    fn finalizable(&self, reachable: &[bool]) -> Vec<usize> {
+        let pending = |node: &Node| match node {
+            Node::Table(m) => m.try_borrow().is_ok_and(|t| t.gc.is_some()),
+            Node::UserData(ud) => ud.has_finalizer(),
+            Node::Closure(_) | Node::Upvalue(_) => false,
+        };
+        (0..self.nodes.len())
+            .filter(|&i| !reachable[i] && pending(&self.nodes[i]))
+            .collect()
+    }
+}
+
+/// collect all unreachable objects, return the number of them
+pub fn collect() -> usize {
+    let mut graph = Graph::build();
+    let mut reachable = vec![false; graph.nodes.len()];
+    graph.mark(&mut reachable, graph.roots());
+
+    // resurrect objects to be finalized and those they reference,
+    // they are removed from weak values but not from weak keys
+    let strict = reachable.clone();
+    let finalize = graph.finalizable(&reachable);
+    graph.mark(&mut reachable, finalize.clone());
+
+    // values are dropped after all cycles are broken
+    let mut garbage_tables = vec![];
+    let mut garbage = vec![];
+    let mut freed = 0;
+    for (i, node) in graph.nodes.iter().enumerate() {
+        match node {
+            Node::Table(m) if reachable[i] => {
+                if let Ok(mut t) = m.try_borrow_mut() {
+                    garbage.extend(clear_weak(&graph, &strict, &reachable, &mut t));
+                }
+            }
+            _ if reachable[i] => {}
+            Node::Table(m) => {
+                if let Ok(mut t) = m.try_borrow_mut() {
+                    garbage_tables.push(mem::take(&mut *t));
+                }
+            }
+            Node::Upvalue(uv) => {
+                if let Ok(mut uv) = uv.try_borrow_mut() {
+                    if let Upval::Closed(v) = mem::replace(&mut *uv, Upval::Closed(Value::Nil)) {
+                        garbage.push(v);
+                    }
+                }
+            }
+            Node::Closure(_) | Node::UserData(_) => {}
+        }
+        if !reachable[i] {
+            freed += 1;
+        }
+    }
+
+    for i in finalize {
+        let obj = match &graph.nodes[i] {
+            Node::Table(m) => Finalizable::Table(Rc::downgrade(m)),
+            Node::UserData(ud) => Finalizable::UserData(Rc::downgrade(ud)),
+            Node::Closure(_) | Node::Upvalue(_) => continue,
+        };
+        if let Some((f, obj)) = obj.take() {
+            f.push(obj);
+        }
+    }
+
+    let live = graph.nodes.len() - freed;
+    graph.nodes.clear();
+    drop(garbage_tables);
+    drop(garbage);
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tables.retain(|m| m.strong_count() > 0);
+        heap.closures.retain(|c| c.strong_count() > 0);
+        heap.live = live;
+    });
+    freed
+}
+
+/// remove entries of weak table `t` whose weak key is unreachable or weak
+/// value is unreachable without resurrection, return the removed keys and values
+fn clear_weak(graph: &Graph, strict: &[bool], reachable: &[bool], t: &mut Table) -> Vec<Value> {
+    let mut removed = vec![];
+    // keys kept for traversals are weak references
+    let sorted = t.sorted.as_ref();
+    if sorted.is_some_and(|keys| keys.iter().any(|k| !graph.is_reachable(reachable, k))) {
+        removed.extend(t.sorted.take().unwrap_or_default());
+    }
+
+    let (weak_k, weak_v) = weak_mode(t);
+    if weak_k || weak_v {
+        let dead: Vec<Value> = t
+            .iter()
+            .filter(|(k, v)| {
+                (weak_k && !graph.is_reachable(reachable, k))
+                    || (weak_v && !graph.is_reachable(strict, v))
+            })
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in dead {
+            if let Some(v) = t.remove(&k) {
+                removed.push(k);
+                removed.push(v);
+            }
+        }
+    }
+    // keys of removed entries are weak references too
+    removed.extend(t.remove_dead_keys(|k| !graph.is_reachable(reachable, k)));
+    removed
+}
+
+/// whether tracked objects have grown enough for `params` to run a collection
+pub(in crate) fn should_collect(params: &GcParams) -> bool {
+    if !params.running {
+        return false;
+    }
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        let threshold = (heap.live * params.pause as usize / 100).max(MIN_THRESHOLD);
+        heap.tables.len() + heap.closures.len() >= threshold
+    })
+}
+
+impl State {
+    /// run a full collection and the finalizers of unreachable objects,
+    /// return the number of unreachable objects freed
+    pub fn gc_collect(&mut self) -> LuaResult<usize> {
+        let freed = collect();
+        self.run_finalizers()?;
+        Ok(freed)
+    }
+
+    /// estimated bytes of objects reachable from this state
+    pub fn gc_count(&self) -> usize {
+        self.measure()
+    }
+
+    /// collections are always full, so a step finishes a cycle and returns `true`
+    pub fn gc_step(&mut self) -> LuaResult<bool> {
+        self.gc_collect().map(|_| true)
+    }
+
+    /// stop collections started automatically by this state
+    pub fn gc_stop(&mut self) {
+        self.gc.running = false;
+    }
+
+    pub fn gc_restart(&mut self) {
+        self.gc.running = true;
+    }
+
+    pub fn gc_is_running(&self) -> bool {
+        self.gc.running
+    }
+
+    /// set pause in percent, return the previous one
+    pub fn gc_set_pause(&mut self, pause: u32) -> u32 {
+        mem::replace(&mut self.gc.pause, pause)
+    }
+}
@@ -2,6 +2,7 @@ use std::fmt;
 use std::fmt::Formatter;
 use std::ops::BitAnd;
 
+use crate::error::LuaResult;
 use crate::opcode::{ArgType, Code, Mode, ALL, RET};
 use crate::State;
 
@@ -20,8 +21,8 @@ const MAX_BX: i32 = (1 << 18) - 1;
 const MAX_SBX: i32 = MAX_BX >> 1;
 
 impl Instruction {
-    pub fn exec(&self, state: &mut State) {
-        (&ALL[(self.0 & 0x3F) as usize].exec)(*self, state)
+    pub fn exec(&self, state: &mut State) -> LuaResult<()> {
+        (ALL[(self.0 & 0x3F) as usize].exec)(*self, state)
     }
 
     pub fn opcode(self) -> &'static Code {
@@ -1,23 +1,66 @@
+// `Value` is used as table key while tables themselves are interior mutable
+#![allow(clippy::mutable_key_type)]
+
+#[macro_use]
 mod builtin;
+mod builtin_io;
+mod builtin_json;
+mod builtin_math;
+mod builtin_os;
+mod builtin_package;
+mod builtin_utf8;
 mod chunk;
+mod error;
 mod func;
+mod gc;
 mod instruction;
 mod opcode;
 mod prototype;
 mod reader;
 mod stack;
+mod userdata;
 
 mod value;
 mod value_cmp;
+mod value_conv;
 mod value_impl;
 mod value_ops;
+#[cfg(feature = "serde")]
+mod value_serde;
+mod value_string;
+mod value_table;
 
 mod state;
+mod state_api;
+mod state_aux;
 mod state_call;
+mod state_clock;
+mod state_func;
+mod state_hook;
 mod state_map;
+mod state_mem;
+mod state_meta;
 mod state_option;
+mod state_ref;
+mod state_sandbox;
 mod state_uv;
 
+pub use error::{LuaError, LuaResult};
 pub use reader::Reader;
 pub use state::State;
-pub use state_option::Options;
+pub use state_api::LuaType;
+pub use state_func::{Function, Scope};
+pub use state_hook::InterruptHandle;
+pub use state_option::{Deterministic, Options, Sandbox};
+pub use state_ref::{RegistryKey, NOREF, REFNIL};
+pub use userdata::{LuaUserData, UserData, UserDataMethods};
+pub use value::Value;
+pub use value_conv::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Variadic};
+pub use value_ops::ArithOp;
+pub use value_string::LuaString;
+
+#[cfg(feature = "derive")]
+pub use nad_derive::{FromLua, IntoLua};
+
+#[doc(hidden)]
+pub use value_conv::__private;
@@ -1,15 +1,16 @@
 use ansi_term::Color::{Green, Red};
 use std::env::args;
 use std::fmt::Debug;
+use std::process::ExitCode;
 
 use nad::State;
-use nad::{Options, Reader};
+use nad::{LuaError, Options, Reader};
 
-fn main() {
+fn main() -> ExitCode {
     let args = args();
     if args.len() < 2 {
-        println!("{}: {}", Red.paint("error"), "no input file");
-        return;
+        println!("{}: no input file", Red.paint("error"));
+        return ExitCode::SUCCESS;
     }
 
     let mut ops = Option::default();
@@ -22,7 +23,7 @@ fn main() {
         };
     }
 
-    ops.run();
+    ops.run()
 }
 
 #[derive(Default, Debug)]
@@ -42,7 +43,7 @@ impl Option {
         self.path.iter().for_each(f)
     }
 
-    fn run(&self) {
+    fn run(&self) -> ExitCode {
         if self.dump {
             self.iter_file(|path| {
                 println!("{}", Green.bold().paint(path));
@@ -51,13 +52,22 @@ impl Option {
         }
 
         if self.exec || !self.dump {
-            self.iter_file(|path| {
-                State::from_file(path)
-                    .with_option(Options {
-                        show_ins: self.debug,
-                    })
+            for path in &self.path {
+                let res = State::from_file(path)
+                    .with_option(Options::new(self.debug))
                     .call(0, 0);
-            })
+
+                match res {
+                    Ok(_) => {}
+                    Err(LuaError::Exit(code)) => return ExitCode::from(code as u8),
+                    Err(e) => {
+                        println!("{}: {}", Red.paint("error"), e);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
         }
+
+        ExitCode::SUCCESS
     }
 }
@@ -1,8 +1,12 @@
+use crate::error::{LuaError, LuaResult};
 use crate::instruction::Instruction;
 use crate::state::State;
+use crate::state_mem::{closure_size, table_size};
 use crate::value::Value;
+use crate::value_ops::ArithOp;
 use crate::value_impl::fb2int;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Eq, PartialEq)]
 pub enum Mode {
     IABC,  // [  B:9  ][  C:9  ][ A:8  ][OP:6]
@@ -22,61 +26,50 @@ pub enum ArgType {
 pub struct Code {
     #[allow(dead_code)]
     test_flag: u8,
-    #[allow(dead_code)]
-    seta_flag: u8,
+    pub seta_flag: u8,
     pub argb_mode: ArgType,
     pub argc_mode: ArgType,
     pub op_mode: Mode,
     pub name: &'static str,
-    pub exec: fn(Instruction, &mut State),
+    pub exec: fn(Instruction, &mut State) -> LuaResult<()>,
 }
 
 macro_rules! math1 {
-    ($op:tt) => {
-        |ins: Instruction, state: &mut State| {
+    ($op:ident) => {
+        |ins: Instruction, state: &mut State| -> LuaResult<()> {
             let (a, b, _) = ins.abc();
             state.push_index(b + 1);
-            let val = state.pop_value();
-            match $op val {
-                Ok(res) => {
-                    state.push_value(res);
-                    state.replace(a + 1);
-                },
-                Err(e) => panic!("{}", e),
-            }
+            state.arith(ArithOp::$op)?;
+            state.replace(a + 1);
+            Ok(())
         }
     };
 }
 
 macro_rules! math2 {
-    ($op:tt) => {
-        |ins: Instruction, state: &mut State| {
+    ($op:ident) => {
+        |ins: Instruction, state: &mut State| -> LuaResult<()> {
             let (a, b, c) = ins.abc();
             state.get_rk(b);
             state.get_rk(c);
-            let vb = state.pop_value();
-            let va = state.pop_value();
-            match va $op vb {
-                Ok(res) => {
-                    state.push_value(res);
-                    state.replace(a + 1);
-                },
-                Err(e) => panic!("{}", e),
-            }
+            state.arith(ArithOp::$op)?;
+            state.replace(a + 1);
+            Ok(())
         }
     };
 }
 
 macro_rules! cmp {
     ($op:tt) => {
-        |ins: Instruction, state: &mut State| {
+        |ins: Instruction, state: &mut State| -> LuaResult<()> {
             let (a, b, c) = ins.abc();
             state.get_rk(b);
             state.get_rk(c);
-            if state.compare(-2, -1, stringify!($op)) != (a != 0) {
+            if state.compare(-2, -1, stringify!($op))? != (a != 0) {
                 state.add_pc(1);
             }
             state.pop(2);
+            Ok(())
         }
     };
 }
@@ -99,7 +92,7 @@ macro_rules! code {
 pub const RET: u32 = 38;
 
 /// copy from [luago-book](https://github.com/zxh0/luago-book/blob/master/code/go/ch03/src/luago/vm/opcodes.go)
-pub const ALL: &'static [Code] = &[
+pub const ALL: &[Code] = &[
     /*    T  A  B  C  mode         name    */
     code!(0, 1, R, N, IABC /* */, "MOVE    ", move_), // R(A) := R(B)
     code!(0, 1, K, N, IABx /* */, "LOADK   ", load_const), // R(A) := Kst(Bx)
@@ -114,20 +107,20 @@ pub const ALL: &'static [Code] = &[
     code!(0, 0, K, K, IABC /* */, "SETTABLE", set_table), // R(A)[RK(B)] := RK(C)
     code!(0, 1, U, U, IABC /* */, "NEWTABLE", new_table), // R(A) := {} (size = B,C)
     code!(0, 1, R, K, IABC /* */, "SELF    ", self_), // R(A+1) := R(B); R(A) := R(B)[RK(C)]
-    code!(0, 1, K, K, IABC /* */, "ADD     ", math2!(+)), // R(A) := RK(B) + RK(C)
-    code!(0, 1, K, K, IABC /* */, "SUB     ", math2!(-)), // R(A) := RK(B) - RK(C)
-    code!(0, 1, K, K, IABC /* */, "MUL     ", math2!(*)), // R(A) := RK(B) * RK(C)
-    code!(0, 1, K, K, IABC /* */, "MOD     ", math2!(%)), // R(A) := RK(B) % RK(C)
-    code!(0, 1, K, K, IABC /* */, "POW     ", math2!(*)), // R(A) := RK(B) ^ RK(C) TODO: implement pow
-    code!(0, 1, K, K, IABC /* */, "DIV     ", math2!(/)), // R(A) := RK(B) / RK(C)
-    code!(0, 1, K, K, IABC /* */, "IDIV    ", math2!(/)), // R(A) := RK(B) // RK(C)
-    code!(0, 1, K, K, IABC /* */, "BAND    ", math2!(&)), // R(A) := RK(B) & RK(C)
-    code!(0, 1, K, K, IABC /* */, "BOR     ", math2!(|)), // R(A) := RK(B) | RK(C)
-    code!(0, 1, K, K, IABC /* */, "BXOR    ", math2!(^)), // R(A) := RK(B) ~ RK(C)
-    code!(0, 1, K, K, IABC /* */, "SHL     ", math2!(<<)), // R(A) := RK(B) << RK(C)
-    code!(0, 1, K, K, IABC /* */, "SHR     ", math2!(>>)), // R(A) := RK(B) >> RK(C)
-    code!(0, 1, R, N, IABC /* */, "UNM     ", math1!(-)), // R(A) := -R(B)
-    code!(0, 1, R, N, IABC /* */, "BNOT    ", math1!(!)), // R(A) := ~R(B)
+    code!(0, 1, K, K, IABC /* */, "ADD     ", math2!(Add)), // R(A) := RK(B) + RK(C)
+    code!(0, 1, K, K, IABC /* */, "SUB     ", math2!(Sub)), // R(A) := RK(B) - RK(C)
+    code!(0, 1, K, K, IABC /* */, "MUL     ", math2!(Mul)), // R(A) := RK(B) * RK(C)
+    code!(0, 1, K, K, IABC /* */, "MOD     ", math2!(Mod)), // R(A) := RK(B) % RK(C)
+    code!(0, 1, K, K, IABC /* */, "POW     ", math2!(Pow)), // R(A) := RK(B) ^ RK(C)
+    code!(0, 1, K, K, IABC /* */, "DIV     ", math2!(Div)), // R(A) := RK(B) / RK(C)
+    code!(0, 1, K, K, IABC /* */, "IDIV    ", math2!(IDiv)), // R(A) := RK(B) // RK(C)
+    code!(0, 1, K, K, IABC /* */, "BAND    ", math2!(BAnd)), // R(A) := RK(B) & RK(C)
+    code!(0, 1, K, K, IABC /* */, "BOR     ", math2!(BOr)), // R(A) := RK(B) | RK(C)
+    code!(0, 1, K, K, IABC /* */, "BXOR    ", math2!(BXor)), // R(A) := RK(B) ~ RK(C)
+    code!(0, 1, K, K, IABC /* */, "SHL     ", math2!(Shl)), // R(A) := RK(B) << RK(C)
+    code!(0, 1, K, K, IABC /* */, "SHR     ", math2!(Shr)), // R(A) := RK(B) >> RK(C)
+    code!(0, 1, R, N, IABC /* */, "UNM     ", math1!(Unm)), // R(A) := -R(B)
+    code!(0, 1, R, N, IABC /* */, "BNOT    ", math1!(BNot)), // R(A) := ~R(B)
     code!(0, 1, R, N, IABC /* */, "NOT     ", not),       // R(A) := not R(B)
     code!(0, 1, R, N, IABC /* */, "LEN     ", len),       // R(A) := length of R(B)
     code!(0, 1, R, R, IABC /* */, "CONCAT  ", concat),    // R(A) := R(B).. ... ..R(C)
@@ -138,76 +131,84 @@ pub const ALL: &'static [Code] = &[
     code!(1, 0, N, U, IABC /* */, "TEST    ", test),        // if not (R(A) <=> C) then pc++
     code!(1, 1, R, U, IABC /* */, "TESTSET ", test_set), // if (R(B) <=> C) then R(A) := R(B) else pc++
     code!(0, 1, U, U, IABC /* */, "CALL    ", call), // R(A), ... ,R(A+C-2) := R(A)(R(A+1), ... ,R(A+B-1))
-    code!(0, 1, U, U, IABC /* */, "TAILCALL", unimplement), // return R(A)(R(A+1), ... ,R(A+B-1))
+    code!(0, 1, U, U, IABC /* */, "TAILCALL", tail_call), // return R(A)(R(A+1), ... ,R(A+B-1))
     code!(0, 0, U, N, IABC /* */, "RETURN  ", return_), // return R(A), ... ,R(A+B-2)
     code!(0, 1, R, N, IAsBx /**/, "FORLOOP ", for_loop), // R(A)+=R(A+2); if R(A) <?= R(A+1) then { pc+=sBx; R(A+3)=R(A) }
     code!(0, 1, R, N, IAsBx /**/, "FORPREP ", for_prep), // R(A)-=R(A+2); pc+=sBx
-    code!(0, 0, N, U, IABC /* */, "TFORCALL", unimplement), // R(A+3), ... ,R(A+2+C) := R(A)(R(A+1), R(A+2));
-    code!(0, 1, R, N, IAsBx /**/, "TFORLOOP", unimplement), // if R(A+1) ~= nil then { R(A)=R(A+1); pc += sBx }
+    code!(0, 0, N, U, IABC /* */, "TFORCALL", tfor_call), // R(A+3), ... ,R(A+2+C) := R(A)(R(A+1), R(A+2));
+    code!(0, 1, R, N, IAsBx /**/, "TFORLOOP", tfor_loop), // if R(A+1) ~= nil then { R(A)=R(A+1); pc += sBx }
     code!(0, 0, U, U, IABC /* */, "SETLIST ", set_list), // R(A)[(C-1)*FPF+i] := R(A+i), 1 <= i <= B
     code!(0, 1, U, N, IABx /* */, "CLOSURE ", closure),  // R(A) := closure(KPROTO[Bx])
     code!(0, 1, U, N, IABC /* */, "VARARG  ", vararg),   // R(A), R(A+1), ..., R(A+B-2) = vararg
-    code!(0, 0, U, U, IAx /*  */, "EXTRAARG", unimplement), // extra (larger) argument for previous opcode
+    code!(0, 0, U, U, IAx /*  */, "EXTRAARG", extra_arg), // extra (larger) argument for previous opcode
 ];
 
-fn unimplement(_: Instruction, _: &mut State) {
-    unimplemented!();
+/// `EXTRAARG` is only read by the opcode before it
+fn extra_arg(_: Instruction, _: &mut State) -> LuaResult<()> {
+    Err(LuaError::new("unexpected opcode EXTRAARG"))
 }
 
-fn move_(ins: Instruction, state: &mut State) {
+fn move_(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     state.copy(b + 1, a + 1);
+    Ok(())
 }
 
-fn jmp(ins: Instruction, state: &mut State) {
+fn jmp(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, sbx) = ins.asbx();
     state.add_pc(sbx);
     if a != 0 {
         state.close_upval(a);
     }
+    Ok(())
 }
 
-fn load_nil(ins: Instruction, state: &mut State) {
+fn load_nil(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     let (start, end) = (a + 1, a + 1 + b);
     state.push_value(Value::Nil);
     (start..=end).for_each(|index| state.copy(-1, index));
     state.pop(1);
+    Ok(())
 }
 
-fn load_bool(ins: Instruction, state: &mut State) {
+fn load_bool(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     state.push_value(Value::Bool(b != 0));
     state.replace(a + 1);
     if c != 0 {
         state.add_pc(1)
     }
+    Ok(())
 }
 
 /// load constant index from current instruction
-fn load_const(ins: Instruction, state: &mut State) {
+fn load_const(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, bx) = ins.abx();
     assert!(bx >= 0);
     state.get_const(bx as usize);
     state.replace(a + 1);
+    Ok(())
 }
 
 /// load constant index from next instruction(`EXTRAARG`)
-fn load_constx(ins: Instruction, state: &mut State) {
+fn load_constx(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, _) = ins.abx();
     let ax = state.fetch().ax();
     assert!(ax >= 0);
     state.get_const(ax as usize);
     state.replace(a + 1);
+    Ok(())
 }
 
-fn len(ins: Instruction, state: &mut State) {
+fn len(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
-    state.len(b + 1);
+    state.len(b + 1)?;
     state.replace(a + 1);
+    Ok(())
 }
 
-fn concat(ins: Instruction, state: &mut State) {
+fn concat(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     let (a, b, c) = (a + 1, b + 1, c + 1);
 
@@ -215,36 +216,40 @@ fn concat(ins: Instruction, state: &mut State) {
     let size = (c - b + 1) as usize;
     state.check_stack(size);
     (b..=c).for_each(|i| state.push_index(i));
-    state.concat(size);
-    state.replace(a + 1);
+    state.concat(size)?;
+    state.replace(a);
+    Ok(())
 }
 
-fn not(ins: Instruction, state: &mut State) {
+fn not(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     state.push_value(Value::Bool(!state.to_boolean(b + 1)));
     state.replace(a + 1);
+    Ok(())
 }
 
-fn test_set(ins: Instruction, state: &mut State) {
+fn test_set(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     if state.to_boolean(b + 1) == (c != 0) {
         state.copy(b + 1, a + 1);
     } else {
         state.add_pc(1);
     }
+    Ok(())
 }
 
-fn test(ins: Instruction, state: &mut State) {
+fn test(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, _, c) = ins.abc();
     if state.to_boolean(a + 1) != (c != 0) {
         state.add_pc(1);
     }
+    Ok(())
 }
 
 /// for index, step, limit do ...
 /// 1. makes index = index - step
 /// 2. add pc to the loop body
-fn for_prep(ins: Instruction, state: &mut State) {
+fn for_prep(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, sbx) = ins.asbx();
     let a = a + 1;
 
@@ -253,17 +258,18 @@ fn for_prep(ins: Instruction, state: &mut State) {
 
     let vb = state.pop_value();
     let va = state.pop_value();
-    state.push_value((va - vb).unwrap());
+    state.push_value((va - vb)?);
     state.replace(a);
 
     state.add_pc(sbx);
+    Ok(())
 }
 
 /// for index, step, limit do ...
 /// 1. makes index = index + step
 /// 2. check index <= limit
 /// 2. add pc to the loop body
-fn for_loop(ins: Instruction, state: &mut State) {
+fn for_loop(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, sbx) = ins.asbx();
     let a = a + 1;
 
@@ -272,41 +278,46 @@ fn for_loop(ins: Instruction, state: &mut State) {
 
     let vb = state.pop_value();
     let va = state.pop_value();
-    state.push_value((va + vb).unwrap());
+    state.push_value((va + vb)?);
     state.replace(a);
 
     let postive_step = state.to_number(a + 2) > 0.0;
-    if (postive_step && state.compare(a, a + 1, "<="))
-        || (!postive_step && state.compare(a + 1, a, "<="))
+    if (postive_step && state.compare(a, a + 1, "<=")?)
+        || (!postive_step && state.compare(a + 1, a, "<=")?)
     {
         state.add_pc(sbx);
         state.copy(a, a + 3);
     }
+    Ok(())
 }
 
-fn new_table(ins: Instruction, state: &mut State) {
+fn new_table(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
-    let size = if b > 0 { fb2int(b) } else { fb2int(c) };
-    state.map_new(size as usize);
+    let (narr, nrec) = (fb2int(b) as usize, fb2int(c) as usize);
+    state.alloc(table_size(narr + nrec))?;
+    state.create_table(narr, nrec);
     state.replace(a + 1);
+    Ok(())
 }
 
-fn get_table(ins: Instruction, state: &mut State) {
+fn get_table(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     state.get_rk(c);
-    state.map_get_top(b + 1);
+    state.map_get_top(b + 1)?;
     state.replace(a + 1);
+    Ok(())
 }
 
-fn set_table(ins: Instruction, state: &mut State) {
+fn set_table(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     state.get_rk(b);
     state.get_rk(c);
-    state.map_set_top(a + 1);
+    state.map_set_top(a + 1)?;
+    Ok(())
 }
 
 const LIST_BATCH_NUM: i64 = 50;
-fn set_list(ins: Instruction, state: &mut State) {
+fn set_list(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, mut b, c) = ins.abc();
     let a = a + 1;
 
@@ -319,27 +330,36 @@ fn set_list(ins: Instruction, state: &mut State) {
     state.check_stack(1);
     let num = if c > 0 { c - 1 } else { state.fetch().ax() } as i64;
     let mut index = num * LIST_BATCH_NUM;
-    (1..=b).for_each(|n| {
+    let mut last = index + b as i64;
+    if b_zero {
+        last += state.top() as i64 - state.reg_count() as i64;
+    }
+    state.map_reserve(a, last as usize);
+    for n in 1..=b {
         index += 1;
         state.push_index(a + n);
-        state.map_set_idx(a, index);
-    });
+        state.map_set_idx(a, index)?;
+    }
 
     if b_zero {
-        (state.reg_count() + 1..=state.top() as i32).for_each(|index2| {
+        for index2 in state.reg_count() + 1..=state.top() as i32 {
             index += 1;
             state.push_index(index2);
-            state.map_set_idx(a, index);
-        });
+            state.map_set_idx(a, index)?;
+        }
         let c = state.reg_count();
         state.set_top(c);
     }
+    Ok(())
 }
 
-fn closure(ins: Instruction, state: &mut State) {
+fn closure(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, bx) = ins.abx();
+    let n = state.stack().frame().func.protos[bx as usize].upvalue.len();
+    state.alloc(closure_size(n))?;
     state.load_proto(bx as usize);
     state.replace(a + 1);
+    Ok(())
 }
 
 /// when call `a(1, 2, b())`  
@@ -368,30 +388,70 @@ fn push_func_and_args(a: i32, b: i32, state: &mut State) -> usize {
 }
 
 fn pop_return_value(a: i32, c: i32, state: &mut State) {
-    if c == 1 {
-        return;
-    } else if c > 1 {
+    if c > 1 {
         let mut index = a + c - 2;
         while index >= a {
             state.replace(index);
             index -= 1;
         }
-    } else {
+    } else if c == 0 {
         // leave the return value on the stack
         state.check_stack(1);
         state.push_value(Value::Integer(a as i64));
     }
 }
 
-fn call(ins: Instruction, state: &mut State) {
+fn call(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     let a = a + 1;
     let narg = push_func_and_args(a, b, state);
-    state.call(narg, c - 1);
-    pop_return_value(a, c, state);
+    if !state.precall(narg, c - 1)? {
+        pop_return_value(a, c, state);
+    }
+    Ok(())
+}
+
+/// for k, v in iter, state, ctrl do ...
+/// 1. call `iter(state, ctrl)`
+/// 2. set return values to loop variables
+fn tfor_call(ins: Instruction, state: &mut State) -> LuaResult<()> {
+    let (a, _, c) = ins.abc();
+    let a = a + 1;
+
+    state.check_stack(3);
+    (a..a + 3).for_each(|index| state.push_index(index));
+    if !state.precall(2, c)? {
+        pop_return_value(a + 3, c + 1, state);
+    }
+    Ok(())
 }
 
-fn return_(ins: Instruction, state: &mut State) {
+/// set results of lua function called by the last `CALL` or `TFORCALL`
+/// to registers, once the function returns
+pub fn finish_call(state: &mut State) {
+    let ins = state.stack().frame().func.code[state.pc() - 1];
+    let (a, _, c) = ins.abc();
+    match ins.opcode().name {
+        "TFORCALL" => pop_return_value(a + 4, c + 1, state),
+        _ => pop_return_value(a + 1, c, state),
+    }
+}
+
+/// for k, v in iter, state, ctrl do ...
+/// 1. exit loop if first loop variable is nil
+/// 2. otherwise save it as control variable and jump back to the loop body
+fn tfor_loop(ins: Instruction, state: &mut State) -> LuaResult<()> {
+    let (a, sbx) = ins.asbx();
+    let a = a + 1;
+
+    if state.stack().get(a + 1) != Value::Nil {
+        state.copy(a + 1, a);
+        state.add_pc(sbx);
+    }
+    Ok(())
+}
+
+fn return_(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     let a = a + 1;
     if b == 0 {
@@ -400,63 +460,76 @@ fn return_(ins: Instruction, state: &mut State) {
         state.check_stack((b - 1) as usize);
         (a..=(a + b - 2)).for_each(|index| state.push_index(index))
     }
+    Ok(())
 }
 
-fn vararg(ins: Instruction, state: &mut State) {
+fn vararg(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     if b != 1 {
         state.load_vararg(b - 1);
         pop_return_value(a + 1, b, state);
     }
+    Ok(())
 }
 
-#[allow(dead_code)]
-fn tail_call(_: Instruction, _: &mut State) {
-    unimplemented!()
+/// `return f(args)`, results are returned by the `RETURN A 0` after it
+fn tail_call(ins: Instruction, state: &mut State) -> LuaResult<()> {
+    let (a, b, _) = ins.abc();
+    let a = a + 1;
+    let narg = push_func_and_args(a, b, state);
+    if !state.tail_call(narg)? {
+        pop_return_value(a, 0, state);
+    }
+    Ok(())
 }
 
-fn self_(ins: Instruction, state: &mut State) {
+fn self_(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     let a = a + 1;
     let b = b + 1;
 
     state.copy(b, a + 1);
     state.get_rk(c);
-    state.map_get_top(b);
+    state.map_get_top(b)?;
     state.replace(a);
+    Ok(())
 }
 
-fn get_upval(ins: Instruction, state: &mut State) {
+fn get_upval(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     let a = a + 1;
     let b = b + 1; // uv index
 
     state.uv_get(b, a);
+    Ok(())
 }
 
-fn set_upval(ins: Instruction, state: &mut State) {
+fn set_upval(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, _) = ins.abc();
     let a = a + 1;
     let b = b + 1; // uv index
 
     state.uv_set(a, b);
+    Ok(())
 }
 
-fn get_uv_map(ins: Instruction, state: &mut State) {
+fn get_uv_map(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     let a = a + 1;
     let b = b + 1;
 
     state.get_rk(c);
-    state.uv_map_get(b);
+    state.uv_map_get(b)?;
     state.replace(a);
+    Ok(())
 }
 
-fn set_uv_map(ins: Instruction, state: &mut State) {
+fn set_uv_map(ins: Instruction, state: &mut State) -> LuaResult<()> {
     let (a, b, c) = ins.abc();
     let a = a + 1;
 
     state.get_rk(b);
     state.get_rk(c);
-    state.uv_map_set(a);
+    state.uv_map_set(a)?;
+    Ok(())
 }
@@ -97,6 +97,104 @@ impl Prototype {
         }
     }
 
+    /// name of the `n`th active local variable at `pc`, `n` starts from 1
+    pub fn local_name(&self, mut n: usize, pc: usize) -> Option<&str> {
+        let vars = self.local_vars.iter();
+        for var in vars.take_while(|var| var.pc_start as usize <= pc) {
+            if pc < var.pc_end as usize {
+                n -= 1;
+                if n == 0 {
+                    return Some(&var.name);
+                }
+            }
+        }
+        None
+    }
+
+    /// kind and name of the value in register `reg` at `pc`,
+    /// like `getobjname` of lua, kind is one of `local`, `global`,
+    /// `field`, `upvalue`, `constant` and `method`
+    pub fn obj_name(&self, pc: usize, reg: i32) -> Option<(&'static str, String)> {
+        if let Some(name) = self.local_name(reg as usize + 1, pc) {
+            return Some(("local", name.to_string()));
+        }
+
+        let setpc = self.find_set_reg(pc, reg)?;
+        let ins = self.code[setpc];
+        let (a, b, c) = ins.abc();
+        let is_env = |name: Option<&str>| match name {
+            Some("_ENV") => "global",
+            _ => "field",
+        };
+        match ins.opcode().name.trim_end() {
+            "MOVE" if b < a => self.obj_name(setpc, b),
+            "GETTABUP" => {
+                let env = self.upvalue_name.get(b as usize).map(String::as_str);
+                Some((is_env(env), self.rk_name(c)))
+            }
+            "GETTABLE" => Some((
+                is_env(self.local_name(b as usize + 1, setpc)),
+                self.rk_name(c),
+            )),
+            "GETUPVAL" => Some(("upvalue", self.upvalue_name.get(b as usize)?.clone())),
+            "LOADK" | "LOADKX" => {
+                let index = match ins.opcode().name.trim_end() {
+                    "LOADK" => ins.abx().1,
+                    _ => self.code.get(setpc + 1)?.ax(),
+                };
+                match self.constants.get(index as usize)? {
+                    Value::String(s) => Some(("constant", s.to_string())),
+                    _ => None,
+                }
+            }
+            "SELF" => Some(("method", self.rk_name(c))),
+            _ => None,
+        }
+    }
+
+    /// name of constant string `RK(c)`, `?` if not a constant string
+    fn rk_name(&self, c: i32) -> String {
+        match c > 0xFF {
+            true => match self.constants.get((c & 0xFF) as usize) {
+                Some(Value::String(s)) => s.to_string(),
+                _ => "?".to_string(),
+            },
+            false => "?".to_string(),
+        }
+    }
+
+    /// the last instruction before `lastpc` which changes register `reg`,
+    /// `None` if it is unknown because of jumps
+    fn find_set_reg(&self, lastpc: usize, reg: i32) -> Option<usize> {
+        let mut setreg = None;
+        let mut jmp_target = 0;
+        for (pc, ins) in self.code.iter().enumerate().take(lastpc) {
+            let (a, b, _) = ins.abc();
+            let change = match ins.opcode().name.trim_end() {
+                "LOADNIL" => a <= reg && reg <= a + b,
+                "TFORCALL" => reg >= a + 2,
+                "CALL" | "TAILCALL" => reg >= a,
+                "JMP" => {
+                    // jump forward and not beyond `lastpc`
+                    let dest = pc as i32 + 1 + ins.asbx().1;
+                    if (pc as i32) < dest && dest <= lastpc as i32 && dest > jmp_target {
+                        jmp_target = dest;
+                    }
+                    false
+                }
+                _ => ins.opcode().seta_flag == 1 && reg == a,
+            };
+            if change {
+                // the instruction may be skipped by a jump
+                setreg = match (pc as i32) < jmp_target {
+                    true => None,
+                    false => Some(pc),
+                };
+            }
+        }
+        setreg
+    }
+
     pub fn dump(&self) {
         self.print_header();
         self.print_code();
@@ -8,7 +8,7 @@ use std::rc::Rc;
 use crate::chunk::{Chunk, Header};
 use crate::instruction::Instruction;
 use crate::prototype::Prototype;
-use crate::value::{LocalValue, Upvalue, Value};
+use crate::value::{LocalValue, LuaString, Upvalue, Value};
 
 pub struct Reader<T: std::io::Read> {
     r: T,
@@ -27,6 +27,7 @@ impl Reader<io::BufReader<File>> {
 }
 
 impl<'a> Reader<io::BufReader<&'a [u8]>> {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str<S: AsRef<[u8]> + ?Sized>(s: &'a S) -> Reader<io::BufReader<&'a [u8]>> {
         Reader {
             r: io::BufReader::new(s.as_ref()),
@@ -36,198 +37,253 @@ impl<'a> Reader<io::BufReader<&'a [u8]>> {
 }
 
 impl<T: std::io::Read> Reader<T> {
-    fn read_byte(&mut self) -> u8 {
+    /// set name of the chunk, used as source of stripped functions
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.file_name = name.to_string();
+        self
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
         let mut data: [u8; 1] = [0];
-        self.r.read_exact(&mut data).unwrap();
-        u8::from_le_bytes(data)
+        self.r.read_exact(&mut data)?;
+        Ok(u8::from_le_bytes(data))
     }
 
-    pub fn read_bytes<const N: usize>(&mut self) -> [u8; N] {
+    pub fn read_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
         let mut data: [u8; N] = [0; N];
-        self.r.read_exact(&mut data).unwrap();
-        data
+        self.r.read_exact(&mut data)?;
+        Ok(data)
     }
 
-    pub fn read_uint32(&mut self) -> u32 {
-        let mut data: [u8; 4] = [0; 4];
-        self.r.read_exact(&mut data).unwrap();
-        u32::from_le_bytes(data)
+    pub fn read_uint32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes()?))
     }
 
-    pub fn read_uint64(&mut self) -> u64 {
-        let mut data: [u8; 8] = [0; 8];
-        self.r.read_exact(&mut data).unwrap();
-        u64::from_le_bytes(data)
+    pub fn read_uint64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes()?))
     }
 
-    pub fn read_luaint(&mut self) -> i64 {
-        let mut data: [u8; 8] = [0; 8];
-        self.r.read_exact(&mut data).unwrap();
-        i64::from_le_bytes(data)
+    pub fn read_luaint(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.read_bytes()?))
     }
 
-    pub fn read_luanum(&mut self) -> f64 {
-        let mut data: [u8; 8] = [0; 8];
-        self.r.read_exact(&mut data).unwrap();
-        f64::from_le_bytes(data)
+    pub fn read_luanum(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.read_bytes()?))
     }
 
-    pub fn read_string(&mut self) -> String {
-        let mut size = self.read_byte() as u64;
+    /// string constant, bytes as they are in the chunk
+    pub fn read_lstring(&mut self) -> io::Result<LuaString> {
+        let mut size = self.read_byte()? as u64;
         if size == 0 {
-            return String::from("");
+            return Ok(LuaString::default());
         }
 
         if size == 0xFF {
-            size = self.read_uint64()
+            size = self.read_uint64()?
         }
 
         let mut buffer = Vec::new();
-        self.r
-            .by_ref()
-            .take(size - 1)
-            .read_to_end(&mut buffer)
-            .unwrap();
-        unsafe { String::from_utf8_unchecked(buffer) }
+        self.r.by_ref().take(size - 1).read_to_end(&mut buffer)?;
+        if buffer.len() as u64 != size - 1 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buffer.into())
+    }
+
+    /// names of source and variables, only used in messages
+    pub fn read_string(&mut self) -> io::Result<String> {
+        Ok(self.read_lstring()?.to_string_lossy().into_owned())
     }
 
     pub fn check_header(&mut self) -> Header {
+        self.try_check_header().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_check_header(&mut self) -> io::Result<Header> {
         use crate::chunk::LUAC_HEADER;
         let h = LUAC_HEADER;
 
-        assert_eq!(self.read_bytes(), h.signature, "not a precompiled chunk!");
-        assert_eq!(self.read_byte(), h.version, "version mismatch");
-        assert_eq!(self.read_byte(), h.format, "format mismatch");
-        assert_eq!(self.read_bytes(), h.luac_data, "corrupted!");
-        assert_eq!(self.read_byte(), h.cint_size, "int size mismatch");
-        assert_eq!(self.read_byte(), h.sizet_size, "size_t size mismatch");
-        assert_eq!(self.read_byte(), h.ins_size, "instruction size mismatch");
-        assert_eq!(self.read_byte(), h.luaint_size, "lua integer size mismatch");
-        assert_eq!(self.read_byte(), h.luanum_size, "lua number size mismatch");
-        assert_eq!(self.read_luaint(), h.luac_int, "endianness mismatch");
-        assert_eq!(self.read_luanum(), h.luac_num, "float format mismatch");
+        fn check<T: PartialEq>(v: T, expected: T, msg: &str) -> io::Result<()> {
+            match v == expected {
+                true => Ok(()),
+                false => Err(io::Error::new(io::ErrorKind::InvalidData, msg)),
+            }
+        }
 
-        h.clone()
+        check(self.read_bytes()?, h.signature, "not a precompiled chunk")?;
+        check(
+            self.read_byte()?,
+            h.version,
+            "version mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.format,
+            "format mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_bytes()?,
+            h.luac_data,
+            "corrupted precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.cint_size,
+            "int size mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.sizet_size,
+            "size_t size mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.ins_size,
+            "Instruction size mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.luaint_size,
+            "lua_Integer size mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_byte()?,
+            h.luanum_size,
+            "lua_Number size mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_luaint()?,
+            h.luac_int,
+            "endianness mismatch in precompiled chunk",
+        )?;
+        check(
+            self.read_luanum()?,
+            h.luac_num,
+            "float format mismatch in precompiled chunk",
+        )?;
+
+        Ok(h)
     }
 
-    fn read_code(&mut self) -> Vec<Instruction> {
-        let count = self.read_uint32();
-        let mut code = Vec::with_capacity(count as usize);
+    /// read a list prefixed with its length
+    fn read_vec<V, F>(&mut self, mut f: F) -> io::Result<Vec<V>>
+    where
+        F: FnMut(&mut Self) -> io::Result<V>,
+    {
+        let count = self.read_uint32()?;
+        // do not trust the length of a corrupted chunk
+        let mut list = Vec::with_capacity((count as usize).min(1024));
         for _ in 0..count {
-            code.push(Instruction(self.read_uint32()))
+            list.push(f(self)?)
         }
-        code
+        Ok(list)
     }
 
-    fn read_constants(&mut self) -> Vec<Value> {
-        let count = self.read_uint32();
-        let mut consts = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            consts.push(self.read_constant())
-        }
-        consts
+    fn read_code(&mut self) -> io::Result<Vec<Instruction>> {
+        self.read_vec(|r| Ok(Instruction(r.read_uint32()?)))
     }
 
-    fn read_constant(&mut self) -> Value {
+    fn read_constants(&mut self) -> io::Result<Vec<Value>> {
+        self.read_vec(Self::read_constant)
+    }
+
+    fn read_constant(&mut self) -> io::Result<Value> {
         use crate::value;
-        match self.read_byte() {
+        Ok(match self.read_byte()? {
             value::CONST_TAG_NIL => Value::Nil,
-            value::CONST_TAG_BOOL => Value::Bool(self.read_byte() != 0),
-            value::CONST_TAG_INT => Value::Integer(self.read_luaint()),
-            value::CONST_TAG_NUM => Value::Float(self.read_luanum()),
-            value::CONST_TAG_SHORT_STR => Value::String(self.read_string()),
-            value::CONST_TAG_LONG_STR => Value::String(self.read_string()),
-            _ => panic!("corrupted"),
-        }
+            value::CONST_TAG_BOOL => Value::Bool(self.read_byte()? != 0),
+            value::CONST_TAG_INT => Value::Integer(self.read_luaint()?),
+            value::CONST_TAG_NUM => Value::Float(self.read_luanum()?),
+            value::CONST_TAG_SHORT_STR => Value::String(self.read_lstring()?),
+            value::CONST_TAG_LONG_STR => Value::String(self.read_lstring()?),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "corrupted precompiled chunk",
+                ))
+            }
+        })
     }
 
-    fn read_upvalues(&mut self) -> Vec<Upvalue> {
-        let count = self.read_uint32();
-        let mut upvalues = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            upvalues.push(Upvalue {
-                in_stack: self.read_byte(),
-                idx: self.read_byte(),
+    fn read_upvalues(&mut self) -> io::Result<Vec<Upvalue>> {
+        self.read_vec(|r| {
+            Ok(Upvalue {
+                in_stack: r.read_byte()?,
+                idx: r.read_byte()?,
             })
-        }
-        upvalues
+        })
     }
 
-    fn read_protos(&mut self, parent_source: &str) -> Vec<Rc<Prototype>> {
-        let count = self.read_uint32();
-        let mut protos = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            protos.push(Rc::new(self.read_prototype(parent_source)))
-        }
-        protos
+    fn read_protos(&mut self, parent_source: &str) -> io::Result<Vec<Rc<Prototype>>> {
+        self.read_vec(|r| Ok(Rc::new(r.read_prototype(parent_source)?)))
     }
 
-    fn read_code_line(&mut self) -> Vec<u32> {
-        let count = self.read_uint32();
-        let mut code_line = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            code_line.push(self.read_uint32())
-        }
-        code_line
+    fn read_code_line(&mut self) -> io::Result<Vec<u32>> {
+        self.read_vec(Self::read_uint32)
     }
 
-    fn read_local_vars(&mut self) -> Vec<LocalValue> {
-        let count = self.read_uint32();
-        let mut vars = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            vars.push(LocalValue {
-                name: self.read_string(),
-                pc_start: self.read_uint32(),
-                pc_end: self.read_uint32(),
+    fn read_local_vars(&mut self) -> io::Result<Vec<LocalValue>> {
+        self.read_vec(|r| {
+            Ok(LocalValue {
+                name: r.read_string()?,
+                pc_start: r.read_uint32()?,
+                pc_end: r.read_uint32()?,
             })
-        }
-        vars
+        })
     }
 
-    fn read_upvalue_name(&mut self) -> Vec<String> {
-        let count = self.read_uint32();
-        let mut names = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            names.push(self.read_string())
-        }
-        names
+    fn read_upvalue_name(&mut self) -> io::Result<Vec<String>> {
+        self.read_vec(Self::read_string)
     }
 
-    fn read_prototype(&mut self, parent_source: &str) -> Prototype {
-        let mut source = self.read_string();
+    fn read_prototype(&mut self, parent_source: &str) -> io::Result<Prototype> {
+        let mut source = self.read_string()?;
         if source.is_empty() {
             source = parent_source.to_string();
         }
 
-        Prototype {
+        Ok(Prototype {
             source: String::from(source.as_str()),
-            def_start_line: self.read_uint32(),
-            def_last_line: self.read_uint32(),
-            num_params: self.read_byte(),
-            is_vararg: self.read_byte(),
-            max_stack_size: self.read_byte(),
-            code: self.read_code(),
-            constants: self.read_constants(),
-            upvalue: self.read_upvalues(),
-            protos: self.read_protos(source.as_str()),
-            code_line: self.read_code_line(),
-            local_vars: self.read_local_vars(),
-            upvalue_name: self.read_upvalue_name(),
-        }
+            def_start_line: self.read_uint32()?,
+            def_last_line: self.read_uint32()?,
+            num_params: self.read_byte()?,
+            is_vararg: self.read_byte()?,
+            max_stack_size: self.read_byte()?,
+            code: self.read_code()?,
+            constants: self.read_constants()?,
+            upvalue: self.read_upvalues()?,
+            protos: self.read_protos(source.as_str())?,
+            code_line: self.read_code_line()?,
+            local_vars: self.read_local_vars()?,
+            upvalue_name: self.read_upvalue_name()?,
+        })
     }
 
     pub fn prototype(&mut self) -> Prototype {
         self.check_header();
-        self.read_byte();
-        self.read_prototype(self.file_name.clone().as_str())
+        self.read_byte().unwrap();
+        let name = self.file_name.clone();
+        self.read_prototype(&name).unwrap()
     }
 
-    pub fn into_chunk(mut self) -> Chunk {
-        Chunk {
-            header: self.check_header(),
-            upvalue_size: self.read_byte(),
-            prototype: self.read_prototype(self.file_name.clone().as_str()),
-        }
+    pub fn into_chunk(self) -> Chunk {
+        self.try_into_chunk().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// read the whole chunk, fail on truncated or corrupted input
+    pub fn try_into_chunk(mut self) -> io::Result<Chunk> {
+        let name = self.file_name.clone();
+        let mut read = || {
+            Ok(Chunk {
+                header: self.try_check_header()?,
+                upvalue_size: self.read_byte()?,
+                prototype: self.read_prototype(&name)?,
+            })
+        };
+        read().map_err(|e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => io::Error::new(e.kind(), "truncated precompiled chunk"),
+            _ => e,
+        })
     }
 
     pub fn dump_proto(&mut self) {
@@ -242,8 +298,9 @@ mod tests {
     #[test]
     fn read_byte() {
         let mut r = Reader::from_str("123");
-        assert_eq!(r.read_byte(), '1' as u8);
-        assert_eq!(r.read_byte(), '2' as u8);
-        assert_eq!(r.read_byte(), '3' as u8);
+        assert_eq!(r.read_byte().unwrap(), b'1');
+        assert_eq!(r.read_byte().unwrap(), b'2');
+        assert_eq!(r.read_byte().unwrap(), b'3');
+        assert!(r.read_byte().is_err());
     }
 }
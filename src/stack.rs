@@ -1,87 +1,252 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::mem;
 use std::rc::Rc;
 
+use crate::func::Closure;
 use crate::instruction::Instruction;
 use crate::prototype::Prototype;
-use crate::value::MutValue;
 use crate::value::Value;
 
-pub struct Stack {
+/// variable captured by closures, it refers to a slot of the stack while
+/// the frame declaring it is running and holds the value once it is closed
+pub enum Upval {
+    Open(usize),
+    Closed(Value),
+}
+
+pub type UpvalRef = Rc<RefCell<Upval>>;
+
+/// new closed upvalue holding `v`
+pub fn new_upval(v: Value) -> UpvalRef {
+    Rc::new(RefCell::new(Upval::Closed(v)))
+}
+
+/// frame of a call, its slots start at `base` of the stack
+pub struct CallInfo {
+    /// slot of the called function, results are moved here when it returns
+    pub callee: usize,
+    pub base: usize,
     pc: usize,
-    pub top: usize,
-    pub slots: Vec<MutValue>,
-    pub varargs: Rc<Vec<Value>>,
     pub func: Rc<Prototype>,
-    pub upvals: Vec<MutValue>,
-    pub openuv: HashMap<i32, MutValue>,
+    /// the running closure, `None` for the frame of host
+    pub closure: Option<Rc<Closure>>,
+    /// extra arguments of vararg function, they are kept right below `base`
+    pub nvarargs: usize,
+    /// results wanted by the caller, all of them if negative
+    pub nret: i32,
+}
+
+impl CallInfo {
+    pub fn new(base: usize, func: Rc<Prototype>, closure: Option<Rc<Closure>>) -> Self {
+        CallInfo {
+            callee: base.saturating_sub(1),
+            base,
+            pc: 0,
+            func,
+            closure,
+            nvarargs: 0,
+            nret: -1,
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+}
+
+/// values of all frames in one vector, the running frame is on top and
+/// indices of the methods are relative to its base
+pub struct Stack {
+    values: Vec<Value>,
+    frames: Vec<CallInfo>,
+    /// upvalues not closed yet by the slot they refer to
+    open: BTreeMap<usize, UpvalRef>,
+    /// shared by frames of builtin functions
+    empty: Rc<Prototype>,
 }
 
 impl Stack {
     pub fn new(size: usize) -> Stack {
+        let empty = Rc::new(Prototype::empty());
         Stack {
-            top: 0,
-            pc: 0,
-            func: Rc::new(Prototype::empty()),
-            varargs: Rc::new(vec![]),
-            upvals: vec![],
-            openuv: HashMap::new(),
-            slots: (0..size)
-                .into_iter()
-                .map(|_| Rc::new(RefCell::from(Value::Nil)))
-                .collect(),
+            values: Vec::with_capacity(size),
+            frames: vec![CallInfo::new(0, empty.clone(), None)],
+            open: BTreeMap::new(),
+            empty,
         }
     }
 
+    pub fn frame(&self) -> &CallInfo {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallInfo {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// frames from the running one to the bottom
+    pub fn frames(&self) -> impl Iterator<Item = &CallInfo> {
+        self.frames.iter().rev()
+    }
+
+    /// number of slots in use by all frames
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// all values of the stack
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn open_upvals(&self) -> impl Iterator<Item = &UpvalRef> {
+        self.open.values()
+    }
+
+    pub fn base(&self) -> usize {
+        self.frame().base
+    }
+
+    pub fn top(&self) -> usize {
+        self.values.len() - self.base()
+    }
+
     pub fn pc(&self) -> usize {
-        self.pc
+        self.frame().pc
     }
 
     pub fn add_pc(&mut self, n: i32) {
-        assert!(self.pc as i32 + n >= 0);
-        self.pc = (self.pc as i32 + n) as usize;
+        let frame = self.frame_mut();
+        assert!(frame.pc as i32 + n >= 0);
+        frame.pc = (frame.pc as i32 + n) as usize;
     }
 
     pub fn fetch(&mut self) -> Instruction {
-        self.func.code[self.pc]
+        let frame = self.frame_mut();
+        let ins = frame.func.code[frame.pc];
+        frame.pc += 1;
+        ins
     }
 
+    /// reserve room for `n` more values, the stack grows by itself anyway
     pub fn check(&mut self, n: usize) {
-        let free = self.slots.len() - self.top;
-        for _ in free..n {
-            self.slots.push(Rc::from(RefCell::from(Value::Nil)))
+        self.values.reserve(n);
+    }
+
+    /// start frame of function at index `func` of current frame, the values
+    /// above it are the arguments
+    pub fn enter(
+        &mut self,
+        func: i32,
+        proto: Option<Rc<Prototype>>,
+        closure: Rc<Closure>,
+        nret: i32,
+    ) {
+        let base = self.base() + self.abx_index(func);
+        let proto = proto.unwrap_or_else(|| self.empty.clone());
+        let mut frame = CallInfo::new(base, proto, Some(closure));
+        frame.nret = nret;
+        self.frames.push(frame);
+    }
+
+    /// move the fixed parameters of vararg function above its arguments,
+    /// the extra arguments are left below the new base
+    pub fn adjust_varargs(&mut self, nparams: usize) {
+        let narg = self.top();
+        let base = self.values.len();
+        for i in 0..nparams {
+            let old = self.base() + i;
+            let v = match i < narg {
+                true => mem::replace(&mut self.values[old], Value::Nil),
+                false => Value::Nil,
+            };
+            self.values.push(v);
         }
+        let frame = self.frame_mut();
+        frame.nvarargs = narg.saturating_sub(nparams);
+        frame.base = base;
     }
 
-    pub fn push(&mut self, v: Value) {
-        assert!(self.slots.len() > self.top);
-        self.slots[self.top] = Rc::from(RefCell::from(v));
-        self.top += 1;
+    /// end current frame, its last `n` values are moved to where its function
+    /// was, the results are adjusted to `nret` of the frame if it is not negative
+    pub fn leave(&mut self, n: usize) {
+        let frame = self.frames.pop().unwrap();
+        let nret = frame.nret;
+        let func = frame.callee;
+        if !self.open.is_empty() {
+            self.close_upvals(frame.base);
+        }
+        let start = self.values.len() - n;
+        self.values.drain(func..start);
+        if nret >= 0 {
+            self.values.resize(func + nret as usize, Value::Nil);
+        }
+    }
+
+    /// end current frame for a tail call, the function and its `narg`
+    /// arguments on top are moved to where its function was, return `nret`
+    /// of the frame
+    pub fn leave_for_tail(&mut self, narg: usize) -> i32 {
+        let frame = self.frames.pop().unwrap();
+        if !self.open.is_empty() {
+            self.close_upvals(frame.base);
+        }
+        let start = self.values.len() - (narg + 1);
+        self.values.drain(frame.callee..start);
+        frame.nret
     }
 
-    pub fn pushn(&mut self, vs: &Vec<Value>, n: i32) {
-        let n = if n < 0 { vs.len() } else { n as usize };
-        (0..n).for_each(|index| self.push(vs.get(index).unwrap_or(&Value::Nil).clone()))
+    /// end current frame after error, all its values are removed
+    pub fn unwind(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        let func = frame.callee;
+        self.close_upvals(func);
+        self.values.truncate(func);
+    }
+
+    /// push `n` extra arguments of current frame, all of them if `n` is negative
+    pub fn push_varargs(&mut self, n: i32) {
+        let frame = self.frame();
+        let (start, count) = (frame.base - frame.nvarargs, frame.nvarargs);
+        let n = if n < 0 { count } else { n as usize };
+        for i in 0..n {
+            let v = match i < count {
+                true => self.values[start + i].clone(),
+                false => Value::Nil,
+            };
+            self.values.push(v);
+        }
+    }
+
+    pub fn push(&mut self, v: Value) {
+        self.values.push(v);
     }
 
     pub fn pop(&mut self) -> Value {
-        assert!(self.top > 0);
-        self.top -= 1;
-        let val = self.slots.remove(self.top);
-        self.slots
-            .insert(self.top, Rc::from(RefCell::from(Value::Nil)));
-        // TODO: how to get value directly
-        val.replace(Value::Nil)
+        assert!(self.top() > 0);
+        self.values.pop().unwrap()
     }
 
     pub fn popn(&mut self, n: usize) -> Vec<Value> {
-        let mut val = vec![Value::Nil; n];
-        (0..n).for_each(|index| val[n - 1 - index] = self.pop());
-        val
+        assert!(self.top() >= n);
+        self.values.split_off(self.values.len() - n)
+    }
+
+    /// set top of current frame, new slots are `nil`
+    pub fn set_top(&mut self, top: usize) {
+        let base = self.base();
+        self.values.resize(base + top, Value::Nil);
     }
 
+    /// swap values at 0-based positions `a` and `b` of current frame
     pub fn swap(&mut self, a: usize, b: usize) {
-        self.slots.swap(a, b);
+        let base = self.base();
+        self.values.swap(base + a, base + b);
     }
 
     pub fn reverse(&mut self, mut low: usize, mut high: usize) {
@@ -96,39 +261,71 @@ impl Stack {
         if index >= 0 {
             index as usize
         } else {
-            let index = index + (self.top as i32) + 1;
+            let index = index + (self.top() as i32) + 1;
             assert!(index >= 0, "illegal negative index");
             index as usize
         }
     }
 
-    pub fn is_valid(&self, index: i32) -> bool {
-        let index = if index < 0 {
-            index + (self.top as i32) + 1
-        } else {
-            index
-        };
-
-        index >= 0 && index <= self.top as i32
-    }
-
-    // TODO: return Ref<T> and get ref by &*
+    /// value at `index`, `nil` if it is above top
     pub fn get(&self, index: i32) -> Value {
         let index = self.abx_index(index);
         assert!(index > 0);
-
-        self.slots
-            .get(index - 1)
-            .unwrap_or(&Rc::new(RefCell::new(Value::Nil)))
-            .borrow_mut()
-            .clone()
+        match self.values.get(self.base() + index - 1) {
+            Some(v) if index <= self.top() => v.clone(),
+            _ => Value::Nil,
+        }
     }
 
     pub fn set(&mut self, index: i32, v: Value) {
         let index = self.abx_index(index);
-        assert!(0 < index && index <= self.top);
-        let source = self.slots[index - 1].clone();
-        *source.borrow_mut() = v;
+        assert!(0 < index && index <= self.top());
+        let base = self.base();
+        self.values[base + index - 1] = v;
+    }
+
+    /// upvalue `n` of running closure
+    pub fn upval(&self, n: usize) -> &UpvalRef {
+        &self.frame().closure.as_ref().unwrap().upval[n]
+    }
+
+    /// number of upvalues of running closure
+    pub fn upval_count(&self) -> usize {
+        self.frame().closure.as_ref().map_or(0, |c| c.upval.len())
+    }
+
+    pub fn upval_get(&self, n: usize) -> Value {
+        match &*self.upval(n).borrow() {
+            Upval::Open(i) => self.values[*i].clone(),
+            Upval::Closed(v) => v.clone(),
+        }
+    }
+
+    pub fn upval_set(&mut self, n: usize, v: Value) {
+        let uv = self.upval(n).clone();
+        let mut uv = uv.borrow_mut();
+        match &mut *uv {
+            Upval::Open(i) => self.values[*i] = v,
+            Upval::Closed(old) => *old = v,
+        }
+    }
+
+    /// upvalue referring to register `reg` of current frame, it is shared
+    /// by closures capturing the same variable
+    pub fn find_upval(&mut self, reg: usize) -> UpvalRef {
+        let index = self.base() + reg;
+        self.open
+            .entry(index)
+            .or_insert_with(|| Rc::new(RefCell::new(Upval::Open(index))))
+            .clone()
+    }
+
+    /// close upvalues referring to slots from `level`, they take the values
+    pub fn close_upvals(&mut self, level: usize) {
+        for (index, uv) in self.open.split_off(&level) {
+            let v = self.values.get(index).cloned().unwrap_or(Value::Nil);
+            *uv.borrow_mut() = Upval::Closed(v);
+        }
     }
 }
 
@@ -140,30 +337,31 @@ mod tests {
     #[test]
     fn test_stack() {
         let mut s = Stack::new(2);
-        assert!(!s.is_valid(1));
-        s.push(Value::String("123".to_string()));
-        assert!(s.is_valid(1));
-        assert_eq!(s.pop(), Value::String("123".to_string()));
-        assert!(!s.is_valid(1));
+        assert_eq!(s.top(), 0);
+        s.push(Value::String("123".into()));
+        assert_eq!(s.top(), 1);
+        assert_eq!(s.pop(), Value::String("123".into()));
+        assert_eq!(s.top(), 0);
 
         s.push(Value::Integer(1));
-        s.push(Value::Integer(1));
-        assert!(s.is_valid(2));
-        assert!(s.is_valid(-2));
+        s.push(Value::Integer(2));
+        assert_eq!(s.abx_index(-2), 1);
+        assert_eq!(s.get(-2), Value::Integer(1));
 
         s.check(1);
         s.push(Value::Integer(1));
         s.set(1, Value::Integer(2));
-        // assert_eq!(s.get(1), &Value::Integer(2));
-        //
-        // assert_eq!(s.get(4), &Value::_None);
+        assert_eq!(s.get(1), Value::Integer(2));
+        assert_eq!(s.get(4), Value::Nil);
     }
 
     #[test]
-    #[should_panic]
     fn test_push() {
         let mut s = Stack::new(1);
-        s.push(Value::Integer(1));
-        s.push(Value::Integer(1));
+        for i in 0..100 {
+            s.push(Value::Integer(i));
+        }
+        assert_eq!(s.top(), 100);
+        assert_eq!(s.get(-1), Value::Integer(99));
     }
 }
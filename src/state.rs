@@ -1,54 +1,127 @@
-use std::cell::RefCell;
-use std::collections::{HashMap, LinkedList};
+use std::any::TypeId;
+use std::collections::HashMap;
 use std::rc::Rc;
 
 use crate::builtin::add_builtin_func;
+use crate::builtin_math::Random;
+use crate::builtin_package::LOADED;
 use crate::chunk::Chunk;
+use crate::error::{LuaError, LuaResult};
 use crate::func::Closure;
+use crate::gc::{self, Finalizable, GcParams, GcQueue};
 use crate::instruction::Instruction;
-use crate::stack::Stack;
+use crate::stack::{new_upval, Stack};
+use crate::state_clock::Clock;
+use crate::state_hook::{Counter, InterruptHandle};
+use crate::state_map::map_len;
+use crate::state_mem::Memory;
 use crate::state_option::Options;
-use crate::value::Value;
+use crate::state_ref::Refs;
+use crate::state_sandbox::{freeze_globals, frozen_base, restrict_builtin};
+use crate::value::{Map, Value};
+use crate::value_conv::{FromLua, IntoLua};
 use crate::Reader;
 use std::path::Path;
 
-const GLOBAL_MAP_INDEX: &'static Value = &Value::Nil;
+pub(in crate) const GLOBAL_MAP_INDEX: &Value = &Value::Nil;
+
+/// maximum number of slots of the stack, same as `LUAI_MAXSTACK`
+pub(in crate) const MAX_STACK: usize = 1_000_000;
 
 pub struct State {
     pub(in crate) depth: usize,
+    /// nested calls through `call`, each of them runs on the host stack
+    pub(in crate) ccalls: usize,
     pub(in crate) options: Options,
-    pub(in crate) chain: LinkedList<Stack>, // call stack
-    registry: HashMap<Value, Value>,
+    /// values and frames of all calls
+    pub(in crate) stack: Stack,
+    pub(in crate) registry: HashMap<Value, Value>,
+    /// integer references in registry
+    pub(in crate) refs: Refs,
+    /// metatables of userdata types
+    pub(in crate) udata_meta: HashMap<TypeId, Map>,
+    /// objects with `__gc` which are not finalized yet, in order of marking
+    pub(in crate) gc_alive: Vec<Finalizable>,
+    pub(in crate) gc_marked: u64,
+    pub(in crate) gc_queue: GcQueue,
+    pub(in crate) gc: GcParams,
+    pub(in crate) finalizing: bool,
+    pub(in crate) mem: Memory,
+    pub(in crate) counter: Counter,
+    pub(in crate) interrupt: InterruptHandle,
+    pub(in crate) clock: Clock,
+    pub(in crate) rng: Random,
 }
 
-fn new_registry_whith_builtin() -> HashMap<Value, Value> {
+/// install builtin libraries allowed by `options` into `global`
+fn new_registry_whith_builtin(global: &Map, options: &Options) -> HashMap<Value, Value> {
+    let mut registry = HashMap::new();
     let mut global_map = HashMap::new();
-    add_builtin_func(&mut global_map);
+    add_builtin_func(&mut global_map, &mut registry);
+    if let Some(sandbox) = &options.sandbox {
+        restrict_builtin(sandbox, &mut global_map, &registry);
+    }
+    {
+        let mut global = global.borrow_mut();
+        global.clear();
+        for (k, v) in global_map {
+            global.insert(k, v);
+        }
+    }
 
-    let mut registry = HashMap::new();
-    registry.insert(
-        GLOBAL_MAP_INDEX.clone(),
-        Value::Map(RefCell::new(global_map)),
-    );
+    let global_map = Value::Map(global.clone());
+    if let Value::Map(m) = &global_map {
+        let key = Value::String("_G".into());
+        m.borrow_mut().insert(key, global_map.clone());
+    }
+    if let Some(Value::Map(loaded)) = registry.get(&Value::String(LOADED.into())) {
+        let key = Value::String("_G".into());
+        loaded.borrow_mut().insert(key, global_map.clone());
+    }
+    registry.insert(GLOBAL_MAP_INDEX.clone(), global_map);
+    if options.sandbox.as_ref().is_some_and(|s| s.frozen_globals) {
+        freeze_globals(global);
+    }
 
     registry
 }
 
+impl Drop for State {
+    fn drop(&mut self) {
+        self.close_finalizers();
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl State {
     /// create new State using a default stack
     pub fn new() -> State {
-        Self::from_stack(Stack::new(20))
-    }
-
-    /// create new State using given stack
-    pub fn from_stack(stack: Stack) -> State {
-        let mut chain = LinkedList::new();
-        chain.push_back(stack);
         State {
             depth: 0,
-            chain,
-            registry: new_registry_whith_builtin(),
+            ccalls: 0,
+            stack: Stack::new(20),
+            registry: new_registry_whith_builtin(
+                &gc::new_table(HashMap::new()),
+                &Options::default(),
+            ),
+            refs: Refs::default(),
             options: Options::default(),
+            udata_meta: HashMap::new(),
+            gc_alive: vec![],
+            gc_marked: 0,
+            gc_queue: GcQueue::default(),
+            gc: GcParams::default(),
+            finalizing: false,
+            mem: Memory::default(),
+            counter: Counter::default(),
+            interrupt: InterruptHandle::default(),
+            clock: Clock::default(),
+            rng: Random::from_time(),
         }
     }
 
@@ -59,27 +132,59 @@ impl State {
     // create new State using a default stack and load chunk into stack
     pub fn from_chunk(ch: Chunk) -> State {
         let mut state = Self::new();
+        state.push_chunk(ch);
+        state
+    }
+
+    /// push main function of chunk `ch`, chunks from host are trusted
+    /// so it is loaded even if sandbox does not accept binary chunks
+    pub fn push_chunk(&mut self, ch: Chunk) {
+        let func = self.main_closure(ch, None);
+        self.push_value(func);
+    }
+
+    /// create closure of the main function of chunk, its first upvalue
+    /// is `env` or the global map by default
+    pub(in crate) fn main_closure(&self, ch: Chunk, env: Option<Value>) -> Value {
         let mut func = Closure::with_proto(Rc::new(ch.prototype));
-        if func.upval.len() > 0 {
-            let gmap = state.registry.get(GLOBAL_MAP_INDEX).unwrap();
-            func.upval[0] = Rc::from(RefCell::new(gmap.clone()));
+        if !func.upval.is_empty() {
+            let env = env.unwrap_or_else(|| self.registry.get(GLOBAL_MAP_INDEX).unwrap().clone());
+            func.upval[0] = new_upval(env);
         }
-
-        state.push_value(Value::Function(func));
-        state
+        gc::new_function(func)
     }
 
     pub fn with_option(mut self, opts: Options) -> Self {
+        let reinstall = self.options.sandbox != opts.sandbox;
         self.options = opts;
+        if reinstall {
+            self.install_builtin();
+        }
+        if let Some(det) = self.options.deterministic.clone() {
+            self.set_clock(det.time, det.clock);
+            self.rng = Random::new(det.seed);
+        }
         self
     }
 
+    /// install builtin libraries again, the global map is filled in place
+    /// since chunks loaded already share it
+    fn install_builtin(&mut self) {
+        let global = match self.registry.get(GLOBAL_MAP_INDEX) {
+            Some(Value::Map(g)) => g.clone(),
+            _ => gc::new_table(HashMap::new()),
+        };
+        global.borrow_mut().meta = None;
+        let registry = new_registry_whith_builtin(&global, &self.options);
+        self.registry.extend(registry);
+    }
+
     pub(in crate) fn stack(&self) -> &Stack {
-        self.chain.front().unwrap()
+        &self.stack
     }
 
     pub(in crate) fn stack_mut(&mut self) -> &mut Stack {
-        self.chain.front_mut().unwrap()
+        &mut self.stack
     }
 }
 
@@ -89,7 +194,7 @@ impl State {
     }
 
     pub fn top(&self) -> usize {
-        self.stack().top
+        self.stack.top()
     }
 
     pub fn add_pc(&mut self, n: i32) {
@@ -105,22 +210,27 @@ impl State {
     }
 
     pub fn fetch(&mut self) -> Instruction {
-        let stack = self.stack_mut();
-        let ins = stack.fetch();
-        stack.add_pc(1);
-        ins
+        self.stack.fetch()
     }
 
+    /// convert acceptable `index` to the positive index which refers to
+    /// the same value even if the stack changes, see `state_api` for index rules
     pub fn abs_index(&self, index: i32) -> usize {
         self.stack().abx_index(index)
     }
 
-    pub fn check_stack(&mut self, n: usize) {
+    /// make sure there are at least `n` free slots above top,
+    /// `false` if the stack can not grow to that size
+    pub fn check_stack(&mut self, n: usize) -> bool {
+        if self.stack.len() + n > MAX_STACK {
+            return false;
+        }
         self.stack_mut().check(n);
+        true
     }
 
     pub fn reg_count(&mut self) -> i32 {
-        self.stack().func.max_stack_size as i32
+        self.stack().frame().func.max_stack_size as i32
     }
 
     pub fn pop(&mut self, n: usize) {
@@ -153,7 +263,7 @@ impl State {
 
     /// push value from constant table at index
     pub fn get_const(&mut self, index: usize) {
-        let const_val = self.stack().func.constants[index].clone();
+        let const_val = self.stack().frame().func.constants[index].clone();
         self.push_value(const_val);
     }
 
@@ -176,9 +286,13 @@ impl State {
 
     pub fn global_map_get(&mut self, name: String) {
         let gmap = self.registry.get(GLOBAL_MAP_INDEX).unwrap().clone();
-        if let Value::Map(mut m) = gmap {
-            let val = m.get_mut().get(&Value::String(name)).unwrap();
-            self.push_value(val.clone());
+        if let Value::Map(m) = gmap {
+            let key = Value::String(name.into());
+            let val = m.borrow().get(&key).cloned();
+            // globals moved by `freeze_globals`
+            let val =
+                val.or_else(|| frozen_base(&m).and_then(|base| base.borrow().get(&key).cloned()));
+            self.push_value(val.unwrap_or(Value::Nil));
         } else {
             panic!("global map is nil")
         }
@@ -187,14 +301,39 @@ impl State {
     pub fn global_map_set(&mut self, name: String) {
         let gmap = self.registry.get(GLOBAL_MAP_INDEX).unwrap();
         assert!(matches!(gmap, &Value::Map(_)));
-        if let Value::Map(mut m) = gmap.clone() {
+        if let Value::Map(m) = gmap.clone() {
             let val = self.pop_value();
-            m.get_mut().insert(Value::String(name), val);
+            m.borrow_mut().insert(Value::String(name.into()), val);
         } else {
             panic!("global map is nil")
         }
     }
 
+    /// set global variable `name` to rust value `val`
+    pub fn set_global<V: IntoLua>(&mut self, name: &str, val: V) -> LuaResult<()> {
+        let val = val.into_lua()?;
+        self.check_stack(1);
+        self.push_value(val);
+        self.global_map_set(name.to_string());
+        Ok(())
+    }
+
+    /// get global variable `name` as rust value
+    pub fn get_global<V: FromLua>(&mut self, name: &str) -> LuaResult<V> {
+        self.check_stack(1);
+        self.global_map_get(name.to_string());
+        V::from_lua(self.pop_value())
+    }
+
+    pub(in crate) fn registry_get(&self, key: &str) -> Value {
+        let key = Value::String(key.into());
+        self.registry.get(&key).cloned().unwrap_or(Value::Nil)
+    }
+
+    pub(in crate) fn registry_set(&mut self, key: &str, val: Value) {
+        self.registry.insert(Value::String(key.into()), val);
+    }
+
     pub fn register(&mut self, name: String, val: Value) {
         self.push_value(val);
         self.global_map_set(name);
@@ -216,7 +355,7 @@ impl State {
             (low as i32 - n - 1) as usize
         };
 
-        let stack = self.chain.front_mut().unwrap();
+        let stack = &mut self.stack;
         stack.reverse(low, index);
         stack.reverse(index + 1, high);
         stack.reverse(low, high);
@@ -232,76 +371,64 @@ impl State {
     }
 
     pub fn set_top(&mut self, index: i32) {
-        let top = self.abs_index(index) as i32;
-        let n = self.top() as i32 - top;
-
-        let stack = self.chain.front_mut().unwrap();
-        (n..0).for_each(|_| {
-            stack.push(Value::Nil);
-        });
-        (0..n).for_each(|_| {
-            stack.pop();
-        });
+        let top = self.abs_index(index);
+        self.stack.set_top(top);
     }
 
-    pub fn len(&mut self, index: i32) {
-        let stack = self.chain.front_mut().unwrap();
+    pub fn len(&mut self, index: i32) -> LuaResult<()> {
+        let stack = &mut self.stack;
         let val = stack.get(index);
         if let Value::String(s) = val {
             let len = s.len() as i64;
             stack.push(Value::Integer(len));
         } else if let Value::Map(m) = val {
-            let len = Value::Integer(m.borrow().len() as i64);
-            stack.push(len);
+            stack.push(Value::Integer(map_len(&m)));
         } else {
-            panic!("type {} have no length", val.type_name())
+            return Err(LuaError::new(format!(
+                "attempt to get length of a {} value",
+                val.type_name()
+            )));
         }
+        Ok(())
     }
 
-    pub fn concat(&mut self, n: usize) {
-        let stack = self.chain.front_mut().unwrap();
+    pub fn concat(&mut self, n: usize) -> LuaResult<()> {
         match n {
-            0 => stack.push(Value::String("".to_string())),
+            0 => self.push_value(Value::String("".into())),
             1 => {}
-            n => (1..n).for_each(|_| {
-                let s2 = stack.pop().into_string().unwrap();
-                let s1 = stack.pop().into_string().unwrap();
-                stack.push(Value::String(s1 + s2.as_str()));
-            }),
+            n => {
+                for _ in 1..n {
+                    let v2 = self.pop_value();
+                    let v1 = self.pop_value();
+                    let bad = match v1 {
+                        Value::Integer(_) | Value::Float(_) | Value::String(_) => v2.type_name(),
+                        _ => v1.type_name(),
+                    };
+                    match (v1.into_string(), v2.into_string()) {
+                        (Ok(mut s1), Ok(s2)) => {
+                            self.alloc(s1.len() + s2.len())?;
+                            s1.push_bytes(&s2);
+                            self.push_value(Value::String(s1));
+                        }
+                        _ => {
+                            let msg = format!("attempt to concatenate a {} value", bad);
+                            return Err(LuaError::new(msg));
+                        }
+                    }
+                }
+            }
         };
-    }
-
-    pub fn compare(&mut self, a: i32, b: i32, op: &'static str) -> bool {
-        let stack = self.stack_mut();
-        let a = stack.get(a);
-        let b = stack.get(b);
-        match op {
-            "==" => a == b,
-            ">" => a > b,
-            "<" => a < b,
-            ">=" => a >= b,
-            "<=" => a <= b,
-            _ => panic!("unsupported compare operator"),
-        }
-    }
-
-    pub fn to_boolean(&self, index: i32) -> bool {
-        self.stack().get(index).into_boolean()
-    }
-
-    pub fn to_number(&self, index: i32) -> f64 {
-        self.stack().get(index).into_float().unwrap()
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::stack::Stack;
     use crate::state::State;
     use crate::value::Value;
 
     fn new_state() -> State {
-        State::from_stack(Stack::new(20))
+        State::new()
     }
 
     #[test]
@@ -0,0 +1,265 @@
+//! Stack operations of `State` like the `lua_*` functions of C API.
+//!
+//! Index rules, same as lua except that pseudo indices are not supported:
+//! - a positive index `i` is the `i`th value of current frame, `1` is the bottom
+//! - a negative index `-i` is the `i`th value from top, `-1` is the top
+//! - an index is *valid* if it refers to a value in `1..=top`, it is *acceptable*
+//!   if it is valid or a positive index above top, which is read as none
+//! - functions reading values accept acceptable indices, functions writing values
+//!   require valid indices and panic otherwise, like `api_check` of lua
+//! - registry is reached by `reference` and `get_reference` instead of
+//!   `LUA_REGISTRYINDEX`, upvalues are not reachable from host
+
+use crate::error::{LuaError, LuaResult};
+use crate::state_map::{map_len, map_raw_set};
+use crate::value::{LuaString, Value};
+use crate::value_ops::ArithOp;
+use crate::State;
+
+/// type of value at an index, `None` for an index above top like `LUA_TNONE`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LuaType {
+    None,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    Table,
+    Function,
+    UserData,
+}
+
+impl LuaType {
+    /// name used by `type()`, `"no value"` for `None`
+    pub fn name(self) -> &'static str {
+        match self {
+            LuaType::None => "no value",
+            LuaType::Nil => "nil",
+            LuaType::Boolean => "boolean",
+            LuaType::Number => "number",
+            LuaType::String => "string",
+            LuaType::Table => "table",
+            LuaType::Function => "function",
+            LuaType::UserData => "userdata",
+        }
+    }
+
+    fn of(val: &Value) -> LuaType {
+        match val {
+            Value::Nil => LuaType::Nil,
+            Value::Bool(_) => LuaType::Boolean,
+            Value::Integer(_) | Value::Float(_) => LuaType::Number,
+            Value::String(_) => LuaType::String,
+            Value::Map(_) => LuaType::Table,
+            Value::Function(_) => LuaType::Function,
+            Value::File(_) | Value::UserData(_) => LuaType::UserData,
+        }
+    }
+}
+
+impl State {
+    /// value at acceptable `index`, `None` if it is above top
+    fn value_at(&self, index: i32) -> Option<Value> {
+        let top = self.top() as i32;
+        let abs = if index < 0 { index + top + 1 } else { index };
+        assert!(index != 0 && abs > 0, "invalid index {}", index);
+        match abs > top {
+            true => None,
+            false => Some(self.stack().get(abs)),
+        }
+    }
+
+    /// type of value at `index`, `LuaType::None` if it is above top
+    pub fn type_at(&self, index: i32) -> LuaType {
+        self.value_at(index)
+            .map_or(LuaType::None, |v| LuaType::of(&v))
+    }
+
+    pub fn is_none(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::None
+    }
+
+    pub fn is_nil(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::Nil
+    }
+
+    pub fn is_none_or_nil(&self, index: i32) -> bool {
+        matches!(self.type_at(index), LuaType::None | LuaType::Nil)
+    }
+
+    pub fn is_boolean(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::Boolean
+    }
+
+    /// whether the value is a number or a string convertible to number
+    pub fn is_number(&self, index: i32) -> bool {
+        self.to_numberx(index).is_some()
+    }
+
+    /// whether the value is a number with integer representation
+    pub fn is_integer(&self, index: i32) -> bool {
+        matches!(self.value_at(index), Some(Value::Integer(_)))
+    }
+
+    /// whether the value is a string or a number
+    pub fn is_string(&self, index: i32) -> bool {
+        matches!(self.type_at(index), LuaType::String | LuaType::Number)
+    }
+
+    pub fn is_table(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::Table
+    }
+
+    pub fn is_function(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::Function
+    }
+
+    pub fn is_userdata(&self, index: i32) -> bool {
+        self.type_at(index) == LuaType::UserData
+    }
+
+    pub fn to_boolean(&self, index: i32) -> bool {
+        self.value_at(index).is_some_and(Value::into_boolean)
+    }
+
+    /// convert value to integer, strings are converted too,
+    /// `None` if it is not a number with integer representation
+    pub fn to_integerx(&self, index: i32) -> Option<i64> {
+        self.value_at(index)?.into_integer().ok()
+    }
+
+    /// same as `to_integerx` but `0` if not convertible
+    pub fn to_integer(&self, index: i32) -> i64 {
+        self.to_integerx(index).unwrap_or(0)
+    }
+
+    /// convert value to float, strings are converted too,
+    /// `None` if it is not convertible
+    pub fn to_numberx(&self, index: i32) -> Option<f64> {
+        self.value_at(index)?.into_float().ok()
+    }
+
+    /// same as `to_numberx` but `0` if not convertible
+    pub fn to_number(&self, index: i32) -> f64 {
+        self.to_numberx(index).unwrap_or(0.0)
+    }
+
+    /// get string value, a number is converted to string in place like
+    /// `lua_tolstring`, `None` for other values
+    pub fn to_lstring(&mut self, index: i32) -> Option<LuaString> {
+        let s = match self.value_at(index)? {
+            Value::String(s) => return Some(s),
+            v @ (Value::Integer(_) | Value::Float(_)) => v.into_string().ok()?,
+            _ => return None,
+        };
+        self.stack_mut().set(index, Value::String(s.clone()));
+        Some(s)
+    }
+
+    /// length without `__len`, bytes of string or border of table, `0` for others
+    pub fn raw_len(&self, index: i32) -> usize {
+        match self.value_at(index) {
+            Some(Value::String(s)) => s.len(),
+            Some(Value::Map(m)) => map_len(&m) as usize,
+            _ => 0,
+        }
+    }
+
+    /// push `t[k]` of value `t` at `index`, may call `__index`,
+    /// return type of the pushed value
+    pub fn get_field(&mut self, index: i32, k: &str) -> LuaResult<LuaType> {
+        self.get_key(index, Value::String(k.into()))
+    }
+
+    /// push `t[i]` of value `t` at `index`, may call `__index`
+    pub fn get_i(&mut self, index: i32, i: i64) -> LuaResult<LuaType> {
+        self.get_key(index, Value::Integer(i))
+    }
+
+    /// pop value `v` and do `t[k] = v` with value `t` at `index`, may call `__newindex`
+    pub fn set_field(&mut self, index: i32, k: &str) -> LuaResult<()> {
+        self.set_key(index, Value::String(k.into()))
+    }
+
+    /// pop value `v` and do `t[i] = v` with value `t` at `index`, may call `__newindex`
+    pub fn set_i(&mut self, index: i32, i: i64) -> LuaResult<()> {
+        self.set_key(index, Value::Integer(i))
+    }
+
+    /// pop key `k` and push `t[k]` without metamethods, `t` at `index` must be a table
+    pub fn raw_get(&mut self, index: i32) -> LuaResult<LuaType> {
+        let m = match self.value_at(index) {
+            Some(Value::Map(m)) => m,
+            t => return Err(table_expected(t)),
+        };
+        let k = self.pop_value();
+        let val = m.borrow().get(&k).cloned().unwrap_or(Value::Nil);
+        let tp = LuaType::of(&val);
+        self.push_value(val);
+        Ok(tp)
+    }
+
+    /// pop value `v` and key `k`, do `t[k] = v` without metamethods,
+    /// `t` at `index` must be a table
+    pub fn raw_set(&mut self, index: i32) -> LuaResult<()> {
+        let m = match self.value_at(index) {
+            Some(Value::Map(m)) => m,
+            t => return Err(table_expected(t)),
+        };
+        let v = self.pop_value();
+        let k = self.pop_value();
+        map_raw_set(&m, k, v)
+    }
+
+    /// compare values at `a` and `b` with `op` of `==`, `<`, `<=`, `>` or `>=`,
+    /// metamethods `__eq`, `__lt` and `__le` are called if needed,
+    /// `false` if any index is above top
+    pub fn compare(&mut self, a: i32, b: i32, op: &'static str) -> LuaResult<bool> {
+        let (a, b) = match (self.value_at(a), self.value_at(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(false),
+        };
+        match op {
+            "==" => self.equals(&a, &b),
+            "<" => self.less_than(&a, &b),
+            "<=" => self.less_equal(&a, &b),
+            ">" => self.less_than(&b, &a),
+            ">=" => self.less_equal(&b, &a),
+            _ => panic!("unsupported compare operator"),
+        }
+    }
+
+    /// pop two operands, or one for unary operator, and push the result,
+    /// the operand on top is the second one like `lua_arith`
+    pub fn arith(&mut self, op: ArithOp) -> LuaResult<()> {
+        let b = self.pop_value();
+        // operand of unary operator is passed twice to the metamethod like lua does
+        let a = match op.is_unary() {
+            true => b.clone(),
+            false => self.pop_value(),
+        };
+        let res = self.arith_values(op, &a, &b)?;
+        self.push_value(res);
+        Ok(())
+    }
+
+    fn get_key(&mut self, index: i32, k: Value) -> LuaResult<LuaType> {
+        let t = self.value_at(index).unwrap_or(Value::Nil);
+        let val = self.index_value(&t, &k)?;
+        let tp = LuaType::of(&val);
+        self.check_stack(1);
+        self.push_value(val);
+        Ok(tp)
+    }
+
+    fn set_key(&mut self, index: i32, k: Value) -> LuaResult<()> {
+        let t = self.value_at(index).unwrap_or(Value::Nil);
+        let v = self.pop_value();
+        self.set_index_value(&t, k, v)
+    }
+}
+
+fn table_expected(t: Option<Value>) -> LuaError {
+    let got = t.map_or("no value", |t| t.type_name());
+    LuaError::new(format!("table expected, got {}", got))
+}
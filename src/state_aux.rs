@@ -0,0 +1,165 @@
+use crate::error::{LuaError, LuaResult};
+use crate::value::{LuaString, Map, Value};
+use crate::State;
+
+// argument checks for builtin and host functions like `luaL_check*` of lua,
+// `n` is the argument position starting from 1, errors are reported with
+// the function name found at the call site
+impl State {
+    /// argument `n` of current call, `None` if absent
+    fn arg_value(&self, n: usize) -> Option<Value> {
+        match n >= 1 && n <= self.top() {
+            true => Some(self.stack().get(n as i32)),
+            false => None,
+        }
+    }
+
+    /// kind and name of the running function found at its call site,
+    /// like `getfuncname` of lua, `None` if it is called by host
+    pub(in crate) fn func_name(&self) -> Option<(&'static str, String)> {
+        let caller = self.stack().frames().nth(1)?;
+        let pc = caller.pc().checked_sub(1)?;
+        let ins = *caller.func.code.get(pc)?;
+        let name = ins.opcode().name.trim_end();
+        let event = match name {
+            "CALL" | "TAILCALL" => return caller.func.obj_name(pc, ins.abc().0),
+            "TFORCALL" => return Some(("for iterator", "for iterator".to_string())),
+            "SELF" | "GETTABUP" | "GETTABLE" => "index",
+            "SETTABUP" | "SETTABLE" => "newindex",
+            "ADD" | "SUB" | "MUL" | "MOD" | "POW" | "DIV" | "IDIV" | "BAND" | "BOR" | "BXOR"
+            | "SHL" | "SHR" | "UNM" | "BNOT" | "LEN" | "CONCAT" | "EQ" | "LT" | "LE" => name,
+            _ => return None,
+        };
+        Some(("metamethod", event.to_lowercase()))
+    }
+
+    /// error `bad argument #n to 'name' (msg)`, like `luaL_argerror`
+    pub fn arg_error(&self, mut n: usize, msg: &str) -> LuaError {
+        let (kind, name) = self.func_name().unwrap_or(("", "?".to_string()));
+        if kind == "method" {
+            // do not count `self`
+            n -= 1;
+            if n == 0 {
+                let msg = format!("calling '{}' on bad self ({})", name, msg);
+                return LuaError::new(msg);
+            }
+        }
+        LuaError::new(format!("bad argument #{} to '{}' ({})", n, name, msg))
+    }
+
+    /// error `bad argument #n to 'name' (expected expected, got type)`,
+    /// the type of userdata is its `__name`
+    pub fn type_error(&self, n: usize, expected: &str) -> LuaError {
+        let got = match self.arg_value(n) {
+            None => "no value".to_string(),
+            Some(val) => match self.metamethod(&val, "__name") {
+                Value::String(name) => name.to_string(),
+                _ => val.type_name().to_string(),
+            },
+        };
+        self.arg_error(n, &format!("{} expected, got {}", expected, got))
+    }
+
+    /// argument `n` of any type including `nil`, but it must be present
+    pub fn check_any(&self, n: usize) -> LuaResult<Value> {
+        self.arg_value(n)
+            .ok_or_else(|| self.arg_error(n, "value expected"))
+    }
+
+    /// integer argument `n`, strings are converted
+    pub fn check_integer(&self, n: usize) -> LuaResult<i64> {
+        let val = self.arg_value(n).unwrap_or(Value::Nil);
+        match val.clone().into_integer() {
+            Ok(i) => Ok(i),
+            Err(_) if val.into_float().is_ok() => {
+                Err(self.arg_error(n, "number has no integer representation"))
+            }
+            Err(_) => Err(self.type_error(n, "number")),
+        }
+    }
+
+    /// number argument `n`, strings are converted
+    pub fn check_number(&self, n: usize) -> LuaResult<f64> {
+        let val = self.arg_value(n).unwrap_or(Value::Nil);
+        val.into_float().map_err(|_| self.type_error(n, "number"))
+    }
+
+    /// string argument `n`, numbers are converted
+    pub fn check_string(&self, n: usize) -> LuaResult<LuaString> {
+        let val = self.arg_value(n).unwrap_or(Value::Nil);
+        val.into_string().map_err(|_| self.type_error(n, "string"))
+    }
+
+    /// string argument `n` which must be valid utf-8, like names of files
+    pub fn check_str(&self, n: usize) -> LuaResult<String> {
+        self.check_string(n)?
+            .into_string()
+            .map_err(|_| self.arg_error(n, "invalid UTF-8 string"))
+    }
+
+    pub fn check_table(&self, n: usize) -> LuaResult<Map> {
+        match self.arg_value(n) {
+            Some(Value::Map(m)) => Ok(m),
+            _ => Err(self.type_error(n, "table")),
+        }
+    }
+
+    /// string argument `n` which must be one of `options`, return its position,
+    /// `default` is used if the argument is absent or `nil`
+    pub fn check_option(
+        &self,
+        n: usize,
+        default: Option<&str>,
+        options: &[&str],
+    ) -> LuaResult<usize> {
+        let name = match (default, self.arg_value(n)) {
+            (Some(def), None | Some(Value::Nil)) => def.into(),
+            _ => self.check_string(n)?,
+        };
+        options
+            .iter()
+            .position(|opt| name == *opt)
+            .ok_or_else(|| self.arg_error(n, &format!("invalid option '{}'", name)))
+    }
+
+    /// make sure there are `n` free slots above top, error with `msg` if
+    /// the stack can not grow, same as `luaL_checkstack`
+    pub fn ensure_stack(&mut self, n: usize, msg: &str) -> LuaResult<()> {
+        match self.check_stack(n) {
+            true => Ok(()),
+            false => Err(LuaError::new(format!("stack overflow ({})", msg))),
+        }
+    }
+
+    /// optional integer argument `n`, `default` if it is absent or `nil`
+    pub fn opt_integer(&self, n: usize, default: i64) -> LuaResult<i64> {
+        match self.arg_value(n) {
+            None | Some(Value::Nil) => Ok(default),
+            _ => self.check_integer(n),
+        }
+    }
+
+    /// optional number argument `n`, `default` if it is absent or `nil`
+    pub fn opt_number(&self, n: usize, default: f64) -> LuaResult<f64> {
+        match self.arg_value(n) {
+            None | Some(Value::Nil) => Ok(default),
+            _ => self.check_number(n),
+        }
+    }
+
+    /// optional string argument `n`, `default` if it is absent or `nil`
+    pub fn opt_string(&self, n: usize, default: &str) -> LuaResult<LuaString> {
+        match self.arg_value(n) {
+            None | Some(Value::Nil) => Ok(default.into()),
+            _ => self.check_string(n),
+        }
+    }
+
+    /// optional utf-8 string argument `n`, `default` if it is absent or `nil`
+    pub fn opt_str(&self, n: usize, default: &str) -> LuaResult<String> {
+        match self.arg_value(n) {
+            None | Some(Value::Nil) => Ok(default.to_string()),
+            _ => self.check_str(n),
+        }
+    }
+}
@@ -1,44 +1,63 @@
 use ansi_term::Color::Green;
-use std::rc::Rc;
+use std::mem;
 
+use crate::error::{LuaError, LuaResult};
 use crate::func::Closure;
 use crate::func::Func;
-use crate::stack::Stack;
+use crate::gc;
+use crate::opcode;
+use crate::state::MAX_STACK;
+use crate::state_mem::value_size;
 use crate::value::Value;
 use crate::State;
 
+/// free slots kept above the registers of a frame, same as `LUA_MINSTACK`
+const MIN_STACK: usize = 20;
+
+/// maximum of nested calls through `call`, same as `LUAI_MAXCCALLS`
+const MAX_CCALLS: usize = 200;
+
 impl State {
     pub fn load_proto(&mut self, index: usize) {
-        let stack = self.stack_mut();
-        let proto = &stack.func.protos[index];
-        let mut closure = Closure::with_proto(proto.clone());
-        for (index, uv) in proto.upvalue.iter().enumerate() {
-            if uv.in_stack == 1 {
-                match stack.openuv.get(&(uv.idx as i32)) {
-                    Some(v) => closure.upval[index] = v.clone(),
-                    None => {
-                        let val = stack.slots[uv.idx as usize].clone();
-                        closure.upval[index] = val.clone();
-                        stack.openuv.insert(uv.idx as i32, val.clone());
-                    }
-                }
-            } else {
-                closure.upval[index] = stack.upvals[uv.idx as usize].clone();
-            }
-        }
-
-        stack.push(Value::Function(closure));
+        let proto = self.stack().frame().func.protos[index].clone();
+        let upval = proto
+            .upvalue
+            .iter()
+            .map(|uv| match uv.in_stack {
+                1 => self.stack_mut().find_upval(uv.idx as usize),
+                _ => self.stack().upval(uv.idx as usize).clone(),
+            })
+            .collect();
+        let closure = Closure::with_upvals(proto, upval);
+        self.push_value(gc::new_function(closure));
     }
 
     pub fn load_vararg(&mut self, n: i32) {
-        let stack = self.stack_mut();
-        let varargs = stack.varargs.clone();
-        stack.check(varargs.len());
-        stack.pushn(&varargs, n as i32);
+        self.stack_mut().push_varargs(n);
     }
 
-    fn run_function(&mut self) {
+    /// make room for a frame of `n` slots, the growth of stack is counted
+    /// as allocated
+    fn grow_stack(&mut self, n: usize) -> LuaResult<()> {
+        let len = self.stack().len();
+        if len + n > MAX_STACK {
+            return Err(LuaError::new("stack overflow"));
+        }
+        let cap = self.stack().capacity();
+        if len + n + MIN_STACK > cap {
+            self.stack_mut().check(n + MIN_STACK);
+            let grown = self.stack().capacity() - cap;
+            self.alloc(grown * mem::size_of::<Value>())?;
+        }
+        Ok(())
+    }
+
+    /// run frames from the current one until it returns, functions called by
+    /// lua functions are run in the same loop with frames of their own
+    fn run_function(&mut self) -> LuaResult<()> {
+        let entry = self.depth;
         loop {
+            self.count_instruction()?;
             let ins = self.fetch();
             if self.options.show_ins {
                 println!(
@@ -47,70 +66,145 @@ impl State {
                     Green.bold().paint(ins.opcode().name)
                 );
             }
-            ins.exec(self);
+            ins.exec(self)?;
             if ins.is_ret() {
-                break;
+                if self.depth == entry {
+                    break Ok(());
+                }
+                self.poscall();
+                opcode::finish_call(self);
+                if gc::should_collect(&self.gc) {
+                    gc::collect();
+                }
             }
         }
     }
 
-    pub fn call(&mut self, narg: usize, nret: i32) {
-        let val = self.stack().get(-(narg as i32 + 1));
-        if let Value::Function(f) = val {
-            match f.proto {
-                Func::Proto(proto) => {
-                    let nregs = proto.max_stack_size as usize;
-                    let nparams = proto.num_params as i32;
-                    let is_vararg = proto.is_vararg == 1;
-
-                    let mut stack = Stack::new(nregs + 20);
-                    stack.func = proto.clone();
-                    stack.upvals = f.upval;
+    /// start call of function below `narg` arguments on top, return `true`
+    /// if it is a lua function whose frame is entered to be run, builtin
+    /// functions are done at once
+    pub(in crate) fn precall(&mut self, narg: usize, nret: i32) -> LuaResult<bool> {
+        let func = -(narg as i32 + 1);
+        let f = match self.stack().get(func) {
+            Value::Function(f) => f,
+            val => {
+                return Err(LuaError::new(format!(
+                    "attempt to call a {} value",
+                    val.type_name()
+                )))
+            }
+        };
+        match &f.proto {
+            Func::Proto(proto) => {
+                let nregs = proto.max_stack_size as usize;
+                let nparams = proto.num_params as usize;
+                self.grow_stack(nregs)?;
 
-                    let func_and_args = self.stack_mut().popn(narg + 1);
-                    let (params, varargs) = func_and_args.split_at((nparams + 1) as usize);
-                    stack.pushn(&params[1..].to_vec(), nparams);
-                    stack.top = nregs;
+                let stack = self.stack_mut();
+                stack.enter(func, Some(proto.clone()), f.clone(), nret);
+                if proto.is_vararg == 1 {
+                    stack.adjust_varargs(nparams);
+                }
+                // registers above parameters are nil
+                stack.set_top(nparams);
+                stack.set_top(nregs);
+                self.add_depth();
+                Ok(true)
+            }
+            Func::Builtin(_) | Func::Rust(_) => {
+                self.grow_stack(narg)?;
+                self.stack_mut().enter(func, None, f.clone(), nret);
 
-                    if is_vararg && narg > nparams as usize {
-                        stack.varargs = Rc::new(varargs.to_vec());
+                self.add_depth();
+                let res = match &f.proto {
+                    Func::Builtin(rf) => (*rf)(self),
+                    Func::Rust(rf) => rf(self),
+                    Func::Proto(_) => unreachable!(),
+                };
+                self.sub_depth();
+                let fret = match res {
+                    Ok(n) => n,
+                    Err(e) => {
+                        self.stack_mut().unwind();
+                        return Err(e);
                     }
+                };
 
-                    self.chain.push_front(stack);
-                    self.add_depth();
-                    self.run_function();
-                    self.sub_depth();
-                    let mut stack = self.chain.pop_front().unwrap();
+                // strings returned are counted once created
+                let values = self.stack().values();
+                let size = values[values.len() - fret..].iter().map(value_size).sum();
+                self.stack_mut().leave(fret);
+                self.alloc(size)?;
+                Ok(false)
+            }
+        }
+    }
 
-                    if nret != 0 {
-                        let retval = stack.popn(stack.top - nregs);
-                        self.stack_mut().check(retval.len());
-                        self.stack_mut().pushn(&retval, nret);
-                    }
-                }
-                Func::Builtin(rf) => {
-                    let mut stack = Stack::new(narg + 20);
-                    stack.upvals = f.upval;
-                    let args = self.stack_mut().popn(narg);
-                    stack.pushn(&args, narg as i32);
-                    self.stack_mut().pop();
+    /// start tail call of function below `narg` arguments on top, a lua
+    /// function takes over the frame of the current one, return `false` if
+    /// it is a builtin function whose results are pushed like `precall`
+    pub(in crate) fn tail_call(&mut self, narg: usize) -> LuaResult<bool> {
+        let nregs = match self.stack().get(-(narg as i32 + 1)) {
+            Value::Function(f) => match &f.proto {
+                Func::Proto(proto) => proto.max_stack_size as usize,
+                Func::Builtin(_) | Func::Rust(_) => return self.precall(narg, -1),
+            },
+            _ => return self.precall(narg, -1),
+        };
+        // make room first, so the frame is only replaced if the call starts
+        self.grow_stack(nregs)?;
+        let nret = self.stack_mut().leave_for_tail(narg);
+        self.sub_depth();
+        self.precall(narg, nret)
+    }
 
-                    self.chain.push_front(stack);
-                    self.add_depth();
-                    let fret = rf(self);
-                    self.sub_depth();
-                    let mut stack = self.chain.pop_front().unwrap();
+    /// leave frame of lua function after `RETURN` pushed its results
+    fn poscall(&mut self) {
+        let nregs = self.reg_count() as usize;
+        let n = self.top() - nregs;
+        self.stack_mut().leave(n);
+        self.sub_depth();
+    }
 
-                    if nret != 0 {
-                        let retval = stack.popn(fret);
-                        self.stack_mut().check(retval.len());
-                        self.stack_mut().pushn(&retval, nret);
-                    }
+    /// call function below `narg` arguments on top, they are replaced by
+    /// `nret` results or all of them if `nret` is negative
+    pub fn call(&mut self, narg: usize, nret: i32) -> LuaResult<()> {
+        // calls of builtin functions and metamethods recurse on host stack
+        if self.ccalls >= MAX_CCALLS {
+            return Err(LuaError::new("C stack overflow"));
+        }
+        self.ccalls += 1;
+        let res = self.call_frames(narg, nret);
+        self.ccalls -= 1;
+        res
+    }
+
+    /// body of `call`, counted as one nested call
+    fn call_frames(&mut self, narg: usize, nret: i32) -> LuaResult<()> {
+        if self.depth == 0 {
+            self.reset_counter();
+            self.interrupt.clear();
+        }
+        if self.precall(narg, nret)? {
+            let entry = self.depth;
+            if let Err(e) = self.run_function() {
+                // frames from the called one are dropped
+                while self.depth >= entry {
+                    self.stack_mut().unwind();
+                    self.sub_depth();
                 }
+                return Err(e);
             }
-        } else {
-            // TODO: how to avoid this
-            panic!("not a function")
+            self.poscall();
+        }
+        if gc::should_collect(&self.gc) {
+            gc::collect();
+        }
+        // dropped userdata are finalized when back to host
+        if self.depth == 0 {
+            self.expire_registry_values();
+            self.run_finalizers()?;
         }
+        Ok(())
     }
 }
@@ -0,0 +1,21 @@
+use crate::State;
+
+/// time read by `os.time` and `os.clock` in deterministic mode
+#[derive(Default)]
+pub(in crate) struct Clock {
+    pub(in crate) time: i64,
+    pub(in crate) cpu: f64,
+}
+
+impl State {
+    /// whether the state runs in deterministic mode, see `Options::deterministic`
+    pub(in crate) fn is_deterministic(&self) -> bool {
+        self.options.deterministic.is_some()
+    }
+
+    /// set the virtual clock of deterministic mode, `os.time()` returns `time`
+    /// and `os.clock()` returns `clock`
+    pub fn set_clock(&mut self, time: i64, clock: f64) {
+        self.clock = Clock { time, cpu: clock };
+    }
+}
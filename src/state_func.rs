@@ -0,0 +1,242 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use crate::error::{LuaError, LuaResult};
+use crate::func::{Closure, RustFunc};
+use crate::value::Value;
+use crate::value_conv::{from_single, into_single};
+use crate::value_conv::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti};
+use crate::State;
+
+/// host function with the calling convention of `BuiltinFunc`
+pub(in crate) type NativeFunc<'a> = Box<dyn Fn(&mut State) -> LuaResult<usize> + 'a>;
+
+/// adapt typed function `f` to take arguments from stack and push results
+pub(in crate) fn wrap_func<'a, A, R, F>(f: F) -> NativeFunc<'a>
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut State, A) -> LuaResult<R> + 'a,
+{
+    Box::new(move |state: &mut State| {
+        let args = (1..=state.top())
+            .map(|i| state.stack().get(i as i32))
+            .collect();
+        let rets = f(state, A::from_lua_multi(args)?)?.into_lua_multi()?;
+        let n = rets.len();
+        state.ensure_stack(n, "too many results")?;
+        rets.into_iter().for_each(|v| state.push_value(v));
+        Ok(n)
+    })
+}
+
+/// `FnMut` can not be called again while it is running
+fn wrap_func_mut<'a, A, R, F>(f: F) -> NativeFunc<'a>
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: FnMut(&mut State, A) -> LuaResult<R> + 'a,
+{
+    let f = RefCell::new(f);
+    wrap_func(move |state: &mut State, args: A| {
+        let mut f = f
+            .try_borrow_mut()
+            .map_err(|_| LuaError::new("mutable function called recursively"))?;
+        (*f)(state, args)
+    })
+}
+
+pub(in crate) fn new_function(f: RustFunc) -> Value {
+    Value::Function(Rc::new(Closure::with_rust(f)))
+}
+
+/// handle of lua function, it keeps the function alive and stays valid
+/// across calls so that it can be stored as a callback
+#[derive(Clone)]
+pub struct Function(Rc<Closure>);
+
+impl Function {
+    /// call this function, see `State::call_function`
+    pub fn call<A, R>(&self, state: &mut State, args: A) -> LuaResult<R>
+    where
+        A: IntoLuaMulti,
+        R: FromLuaMulti,
+    {
+        state.call_function(self, args)
+    }
+}
+
+impl IntoLua for Function {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::Function(self.0))
+    }
+}
+
+impl FromLua for Function {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        match val {
+            Value::Function(f) => Ok(Function(f)),
+            val => Err(LuaError::from_lua(val.type_name(), "function", None)),
+        }
+    }
+}
+
+impl IntoLuaMulti for Function {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl FromLuaMulti for Function {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        from_single(vals)
+    }
+}
+
+impl State {
+    /// call `f` with `args` and convert all its results to `R`,
+    /// the stack is left unchanged even if the call fails
+    pub fn call_function<A, R>(&mut self, f: &Function, args: A) -> LuaResult<R>
+    where
+        A: IntoLuaMulti,
+        R: FromLuaMulti,
+    {
+        let args = args.into_lua_multi()?;
+        let rets = self.call_value(Value::Function(f.0.clone()), args)?;
+        R::from_lua_multi(rets)
+    }
+
+    /// call global function `name`, see `call_function`
+    pub fn call_global<A, R>(&mut self, name: &str, args: A) -> LuaResult<R>
+    where
+        A: IntoLuaMulti,
+        R: FromLuaMulti,
+    {
+        let f: Function = self.get_global(name)?;
+        self.call_function(&f, args)
+    }
+
+    /// call `f` with `args` and collect all results
+    pub(in crate) fn call_value(&mut self, f: Value, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        let base = self.top();
+        let narg = args.len();
+        self.ensure_stack(narg + 1, "too many arguments")?;
+        self.push_value(f);
+        args.into_iter().for_each(|v| self.push_value(v));
+        if let Err(e) = self.call(narg, -1) {
+            self.set_top(base as i32);
+            return Err(e);
+        }
+        let n = self.top() - base;
+        Ok(self.stack_mut().popn(n))
+    }
+
+    /// create lua function calling `f` with arguments converted to `A`,
+    /// results of `R` are returned to lua
+    pub fn create_function<A, R, F>(&mut self, f: F) -> Value
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, A) -> LuaResult<R> + 'static,
+    {
+        new_function(Rc::from(wrap_func(f)))
+    }
+
+    /// same as `create_function` but `f` is `FnMut`, calling it recursively is an error
+    pub fn create_function_mut<A, R, F>(&mut self, f: F) -> Value
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: FnMut(&mut State, A) -> LuaResult<R> + 'static,
+    {
+        new_function(Rc::from(wrap_func_mut(f)))
+    }
+
+    /// run `f` with a `Scope` which creates functions borrowing locals,
+    /// these functions can not be called after `scope` returns
+    pub fn scope<'scope, R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Scope<'_, 'scope>) -> R,
+    {
+        let mut scope = Scope {
+            state: self,
+            funcs: vec![],
+            _scope: PhantomData,
+        };
+        f(&mut scope)
+    }
+}
+
+/// slot of scoped function, emptied when scope ends
+type ScopedFunc = Rc<RefCell<Option<Rc<NativeFunc<'static>>>>>;
+
+/// created by `State::scope`, deref to the `State`
+pub struct Scope<'a, 'scope> {
+    state: &'a mut State,
+    funcs: Vec<ScopedFunc>,
+    // `'scope` is invariant
+    _scope: PhantomData<&'scope mut &'scope ()>,
+}
+
+impl<'a, 'scope> Scope<'a, 'scope> {
+    fn add_function(&mut self, f: NativeFunc<'scope>) -> Value {
+        // SAFETY: `f` is dropped when scope ends, the callers only reach it
+        // through `slot` which is emptied at that time
+        let f = unsafe { mem::transmute::<NativeFunc<'scope>, NativeFunc<'static>>(f) };
+        let slot: ScopedFunc = Rc::new(RefCell::new(Some(Rc::new(f))));
+        self.funcs.push(slot.clone());
+
+        new_function(Rc::new(move |state: &mut State| {
+            let f = slot.borrow().clone();
+            match f {
+                Some(f) => f(state),
+                None => Err(LuaError::new("scoped function called after its scope")),
+            }
+        }))
+    }
+
+    /// same as `State::create_function` but `f` only lives for `'scope`
+    pub fn create_function<A, R, F>(&mut self, f: F) -> Value
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, A) -> LuaResult<R> + 'scope,
+    {
+        self.add_function(wrap_func(f))
+    }
+
+    /// same as `State::create_function_mut` but `f` only lives for `'scope`
+    pub fn create_function_mut<A, R, F>(&mut self, f: F) -> Value
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: FnMut(&mut State, A) -> LuaResult<R> + 'scope,
+    {
+        self.add_function(wrap_func_mut(f))
+    }
+}
+
+impl Deref for Scope<'_, '_> {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        self.state
+    }
+}
+
+impl DerefMut for Scope<'_, '_> {
+    fn deref_mut(&mut self) -> &mut State {
+        self.state
+    }
+}
+
+impl Drop for Scope<'_, '_> {
+    fn drop(&mut self) {
+        for slot in self.funcs.drain(..) {
+            slot.borrow_mut().take();
+        }
+    }
+}
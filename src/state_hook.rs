@@ -0,0 +1,144 @@
+use std::cmp;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{LuaError, LuaResult};
+use crate::State;
+
+type HookFn = Box<dyn FnMut(&mut State) -> LuaResult<()>>;
+
+/// token to interrupt a state from another thread, see `State::interrupt_handle`
+#[derive(Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// stop the state at the next instruction boundary with `LuaError::Interrupted`,
+    /// it is dropped if the state is not running until the next call from host
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// whether the state is interrupted but has not stopped yet
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// error if interrupted, the interruption is taken by the error
+    pub(in crate) fn check(&self) -> LuaResult<()> {
+        match self.flag.swap(false, Ordering::Relaxed) {
+            true => Err(LuaError::Interrupted),
+            false => Ok(()),
+        }
+    }
+
+    /// drop interruption which came after the last call from host had returned
+    pub(in crate) fn clear(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+}
+
+/// callback run every `count` instructions
+struct Hook {
+    count: u64,
+    next: u64,
+    func: HookFn,
+}
+
+/// instructions run since the last call from host
+#[derive(Default)]
+pub(in crate) struct Counter {
+    executed: u64,
+    /// check the budget or run the hook when `executed` reaches this
+    next_event: u64,
+    hook: Option<Hook>,
+}
+
+impl State {
+    /// count an instruction to be executed
+    #[inline]
+    pub(in crate) fn count_instruction(&mut self) -> LuaResult<()> {
+        self.counter.executed += 1;
+        if self.counter.executed < self.counter.next_event && !self.interrupt.is_interrupted() {
+            return Ok(());
+        }
+        self.instruction_event()
+    }
+
+    fn instruction_event(&mut self) -> LuaResult<()> {
+        self.check_interrupt()?;
+        let executed = self.counter.executed;
+        // raised again by the next instruction until back to host
+        if self
+            .options
+            .instruction_limit
+            .is_some_and(|limit| executed > limit)
+        {
+            return Err(LuaError::new("instruction limit exceeded"));
+        }
+
+        let mut res = Ok(());
+        if let Some(mut hook) = self.counter.hook.take() {
+            if executed >= hook.next {
+                hook.next = executed + hook.count;
+                res = (hook.func)(self);
+            }
+            // the hook may be replaced by itself
+            self.counter.hook.get_or_insert(hook);
+        }
+        self.schedule_event();
+        res
+    }
+
+    fn schedule_event(&mut self) {
+        let limit = self.options.instruction_limit.map_or(u64::MAX, |n| n + 1);
+        let hook = self.counter.hook.as_ref().map_or(u64::MAX, |h| h.next);
+        self.counter.next_event = cmp::min(limit, hook);
+    }
+
+    /// restart counting when called from host
+    pub(in crate) fn reset_counter(&mut self) {
+        self.counter.executed = 0;
+        if let Some(hook) = &mut self.counter.hook {
+            hook.next = hook.count;
+        }
+        self.schedule_event();
+    }
+
+    /// token to stop this state from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// error `LuaError::Interrupted` if the state is interrupted,
+    /// long running functions should call it from time to time
+    pub fn check_interrupt(&self) -> LuaResult<()> {
+        self.interrupt.check()
+    }
+
+    /// instructions executed since the last call from host
+    pub fn instruction_count(&self) -> u64 {
+        self.counter.executed
+    }
+
+    /// call `f` every `count` instructions, errors returned are raised in
+    /// script, return `LuaError::Interrupted` to abort it outright
+    pub fn set_count_hook<F>(&mut self, count: u32, f: F)
+    where
+        F: FnMut(&mut State) -> LuaResult<()> + 'static,
+    {
+        let count = cmp::max(count, 1) as u64;
+        self.counter.hook = Some(Hook {
+            count,
+            next: self.counter.executed + count,
+            func: Box::new(f),
+        });
+        self.schedule_event();
+    }
+
+    pub fn remove_count_hook(&mut self) {
+        self.counter.hook = None;
+        self.schedule_event();
+    }
+}
@@ -1,63 +1,180 @@
-use std::cell::RefCell;
 use std::collections::HashMap;
 
-use crate::value::Value;
+use crate::builtin_io::FILE_HANDLE;
+use crate::error::{LuaError, LuaResult};
+use crate::gc;
+use crate::state_mem::{entry_size, value_size};
+use crate::value::{Map, Table, Value};
+use crate::value_impl::float_to_integer;
 use crate::State;
 
+/// limit for the length of `__index` and `__newindex` chains, same as `MAXTAGLOOP`
+const MAXTAGLOOP: usize = 2000;
+
+/// raw `m[key] = val`, assign `nil` removes the entry
+pub fn map_raw_set(m: &Map, key: Value, val: Value) -> LuaResult<()> {
+    let key = match key {
+        Value::Nil => return Err(LuaError::new("table index is nil")),
+        Value::Float(f) if f.is_nan() => return Err(LuaError::new("table index is NaN")),
+        // float keys with integral value are stored as integer
+        Value::Float(f) => float_to_integer(f).map_or(Value::Float(f), Value::Integer),
+        key => key,
+    };
+
+    let mut m = m.borrow_mut();
+    if m.frozen {
+        return Err(LuaError::new("attempt to modify a read-only table"));
+    }
+    match val {
+        Value::Nil => m.remove(&key),
+        val => m.insert(key, val),
+    };
+    Ok(())
+}
+
+/// a border of table, `n` that `m[n]` is not nil and `m[n + 1]` is nil
+pub fn map_len(m: &Map) -> i64 {
+    m.borrow().border() as i64
+}
+
 impl State {
     pub fn map_new(&mut self, n: usize) {
-        self.push_value(Value::Map(RefCell::new(HashMap::with_capacity(n))));
+        self.push_value(Value::new_map(HashMap::with_capacity(n)));
     }
 
-    fn map_get(&mut self, index: i32, key: &Value) {
-        let stack = self.stack_mut();
-        if let Value::Map(m) = stack.get(index) {
-            let val = m.borrow().get(key).unwrap().clone();
-            stack.push(val);
-        } else {
-            panic!("not a Map");
+    /// push new table with room for `narr` array items and `nrec` other entries
+    pub fn create_table(&mut self, narr: usize, nrec: usize) {
+        let m = gc::track_table(Table::with_capacity(narr, nrec));
+        self.push_value(Value::Map(m));
+    }
+
+    /// grow the array part of table at `index` to hold keys `1..=n`
+    pub(in crate) fn map_reserve(&mut self, index: i32, n: usize) {
+        if let Value::Map(m) = self.stack().get(index) {
+            m.borrow_mut().reserve_array(n);
         }
     }
 
-    pub fn map_get_top(&mut self, index: i32) {
+    /// get `obj[key]`, file handles lookup their methods, userdata and
+    /// tables without the key lookup their `__index` metamethod
+    pub(in crate) fn index_value(&mut self, obj: &Value, key: &Value) -> LuaResult<Value> {
+        let mut obj = obj.clone();
+        for _ in 0..MAXTAGLOOP {
+            let h = match &obj {
+                Value::Map(m) => {
+                    if let Some(val) = m.borrow().get(key) {
+                        return Ok(val.clone());
+                    }
+                    match self.metamethod(&obj, "__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        h => h,
+                    }
+                }
+                Value::File(_) => {
+                    return match self.registry_get(FILE_HANDLE) {
+                        Value::Map(m) => Ok(m.borrow().get(key).cloned().unwrap_or(Value::Nil)),
+                        _ => Ok(Value::Nil),
+                    }
+                }
+                v => match self.metamethod(v, "__index") {
+                    Value::Nil => {
+                        return Err(LuaError::new(format!(
+                            "attempt to index a {} value",
+                            v.type_name()
+                        )))
+                    }
+                    h => h,
+                },
+            };
+            if let Value::Function(_) = h {
+                return self.call_meta(h, vec![obj, key.clone()]);
+            }
+            obj = h;
+        }
+        Err(LuaError::new("'__index' chain too long; possibly a loop"))
+    }
+
+    /// `obj[key] = val`, userdata and tables without the key use their
+    /// `__newindex` metamethod
+    pub(in crate) fn set_index_value(
+        &mut self,
+        obj: &Value,
+        key: Value,
+        val: Value,
+    ) -> LuaResult<()> {
+        let mut obj = obj.clone();
+        for _ in 0..MAXTAGLOOP {
+            let h = match &obj {
+                Value::Map(m) if m.borrow().contains_key(&key) => {
+                    self.alloc(value_size(&val))?;
+                    return map_raw_set(m, key, val);
+                }
+                Value::Map(m) => match self.metamethod(&obj, "__newindex") {
+                    Value::Nil => {
+                        self.alloc(entry_size(&key, &val))?;
+                        return map_raw_set(m, key, val);
+                    }
+                    h => h,
+                },
+                v => match self.metamethod(v, "__newindex") {
+                    Value::Nil => {
+                        return Err(LuaError::new(format!(
+                            "attempt to index a {} value",
+                            v.type_name()
+                        )))
+                    }
+                    h => h,
+                },
+            };
+            if let Value::Function(_) = h {
+                return self.call_meta(h, vec![obj, key, val]).map(|_| ());
+            }
+            obj = h;
+        }
+        Err(LuaError::new("'__newindex' chain too long; possibly a loop"))
+    }
+
+    fn map_get(&mut self, index: i32, key: &Value) -> LuaResult<()> {
+        let obj = self.stack().get(index);
+        let val = self.index_value(&obj, key)?;
+        self.push_value(val);
+        Ok(())
+    }
+
+    pub fn map_get_top(&mut self, index: i32) -> LuaResult<()> {
         // `immutable borrow` must occured after `mutable borrow`
         // so pop key first then get map from stack at index
         // we must get absolute index first, because pop will change negative index
         let index = self.abs_index(index);
         let key = self.stack_mut().pop();
 
-        self.map_get(index as i32, &key);
+        self.map_get(index as i32, &key)
     }
 
-    pub fn map_get_str(&mut self, index: i32, key: String) {
-        self.map_get(index, &Value::String(key));
+    pub fn map_get_str(&mut self, index: i32, key: String) -> LuaResult<()> {
+        self.map_get(index, &Value::String(key.into()))
     }
 
-    fn map_set(&mut self, index: usize, key: Value, val: Value) {
-        let stack = self.stack_mut();
-        if let Value::Map(m) = stack.get(index as i32) {
-            m.borrow_mut().insert(key, val);
-            stack.set(index as i32, Value::Map(m));
-        } else {
-            panic!("not a Map");
-        }
+    fn map_set(&mut self, index: usize, key: Value, val: Value) -> LuaResult<()> {
+        let obj = self.stack().get(index as i32);
+        self.set_index_value(&obj, key, val)
     }
 
-    pub fn map_set_top(&mut self, index: i32) {
+    pub fn map_set_top(&mut self, index: i32) -> LuaResult<()> {
         let index = self.abs_index(index);
-        assert!(index <= self.top() - 2);
+        assert!(index + 2 <= self.top());
         let stack = self.stack_mut();
         let val = stack.pop();
         let key = stack.pop();
-        self.map_set(index, key, val);
+        self.map_set(index, key, val)
     }
 
-    pub fn map_set_idx(&mut self, index: i32, key: i64) {
+    pub fn map_set_idx(&mut self, index: i32, key: i64) -> LuaResult<()> {
         let index = self.abs_index(index);
-        assert!(index <= self.top() - 2);
+        assert!(index < self.top());
         let stack = self.stack_mut();
         let val = stack.pop();
         let key = Value::Integer(key);
-        self.map_set(index, key, val);
+        self.map_set(index, key, val)
     }
 }
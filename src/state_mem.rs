@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::{LuaError, LuaResult};
+use crate::func::Closure;
+use crate::gc;
+use crate::stack::{Upval, UpvalRef};
+use crate::userdata::LuaUserData;
+use crate::value::{Table, Value};
+use crate::State;
+
+/// measure again after allocating at least this since the last measure
+const MIN_CHECK: usize = 64 * 1024;
+
+/// bytes of an upvalue
+pub(in crate) const CELL_SIZE: usize =
+    mem::size_of::<UpvalRef>() + 2 * mem::size_of::<usize>() + mem::size_of::<RefCell<Upval>>();
+
+/// estimated memory of a state, objects are measured from the roots
+/// sometimes, allocations between the measures are counted but not frees
+#[derive(Default)]
+pub(in crate) struct Memory {
+    /// bytes measured plus those allocated since
+    used: usize,
+    peak: usize,
+    /// measure again when `used` grows over this
+    next_check: usize,
+}
+
+/// bytes of table with room for `n` entries
+pub(in crate) fn table_size(n: usize) -> usize {
+    mem::size_of::<RefCell<Table>>() + n * (2 * mem::size_of::<Value>() + 1)
+}
+
+/// bytes of closure with `n` upvalues, the upvalues are included
+pub(in crate) fn closure_size(n: usize) -> usize {
+    mem::size_of::<Closure>() + n * CELL_SIZE
+}
+
+/// bytes of new entry `k` and `v` of table
+pub(in crate) fn entry_size(k: &Value, v: &Value) -> usize {
+    match v {
+        Value::Nil => 0,
+        v => 2 * mem::size_of::<Value>() + 1 + value_size(k) + value_size(v),
+    }
+}
+
+/// bytes owned by value besides itself
+pub(in crate) fn value_size(v: &Value) -> usize {
+    match v {
+        Value::String(s) => s.capacity(),
+        _ => 0,
+    }
+}
+
+/// objects reachable from the roots of a state
+#[derive(Default)]
+struct Meter {
+    seen: HashSet<*const ()>,
+    pending: Vec<Value>,
+    bytes: usize,
+}
+
+impl Meter {
+    fn value(&mut self, v: &Value) {
+        self.bytes += value_size(v);
+        let ptr = match v {
+            Value::Map(m) => Rc::as_ptr(m) as *const (),
+            Value::Function(c) => Rc::as_ptr(c) as *const (),
+            Value::UserData(ud) => Rc::as_ptr(ud) as *const (),
+            _ => return,
+        };
+        if self.seen.insert(ptr) {
+            self.pending.push(v.clone());
+        }
+    }
+
+    fn cell(&mut self, cell: &UpvalRef) {
+        if !self.seen.insert(Rc::as_ptr(cell) as *const ()) {
+            return;
+        }
+        self.bytes += CELL_SIZE;
+        // value of open upvalue is measured with the stack
+        if let Ok(Upval::Closed(v)) = cell.try_borrow().as_deref() {
+            self.value(v);
+        }
+    }
+
+    fn table(&mut self, t: &Table) {
+        self.bytes += table_size(t.capacity());
+        for (k, v) in t.iter() {
+            self.value(&k);
+            self.value(v);
+        }
+        if let Some(meta) = &t.meta {
+            self.value(&Value::Map(meta.clone()));
+        }
+        if let Some(keys) = &t.sorted {
+            self.bytes += keys.capacity() * mem::size_of::<Value>();
+            keys.iter().for_each(|k| self.value(k));
+        }
+    }
+
+    fn closure(&mut self, c: &Closure) {
+        self.bytes += mem::size_of::<Closure>();
+        c.upval.iter().for_each(|uv| self.cell(uv));
+    }
+
+    fn userdata(&mut self, ud: &LuaUserData) {
+        self.bytes += mem::size_of::<LuaUserData>() + ud.data_size();
+        self.value(&Value::Map(ud.meta.clone()));
+    }
+
+    fn run(&mut self) -> usize {
+        while let Some(v) = self.pending.pop() {
+            match &v {
+                Value::Map(m) => match m.try_borrow() {
+                    Ok(t) => self.table(&t),
+                    Err(_) => self.bytes += table_size(0),
+                },
+                Value::Function(c) => self.closure(c),
+                Value::UserData(ud) => self.userdata(ud),
+                _ => {}
+            }
+        }
+        self.bytes
+    }
+}
+
+impl State {
+    /// bytes of objects reachable from registry and call stack
+    pub(in crate) fn measure(&self) -> usize {
+        let mut meter = Meter::default();
+        for (k, v) in self.registry.iter() {
+            meter.value(k);
+            meter.value(v);
+        }
+        meter.bytes += self.stack.capacity() * mem::size_of::<Value>();
+        self.stack.values().iter().for_each(|v| meter.value(v));
+        self.stack.open_upvals().for_each(|uv| meter.cell(uv));
+        meter.run()
+    }
+
+    /// measure again, return whether `bytes` more can be allocated
+    fn check_memory(&mut self, bytes: usize) -> bool {
+        let limit = self.options.memory_limit.unwrap_or(usize::MAX);
+        self.mem.used = self.measure().saturating_add(bytes);
+        if self.mem.used > limit {
+            // emergency collection, finalizers are not run
+            gc::collect();
+            self.mem.used = self.measure().saturating_add(bytes);
+        }
+
+        let used = self.mem.used;
+        let next = cmp::max(used.saturating_mul(2), used.saturating_add(MIN_CHECK));
+        self.mem.next_check = cmp::min(next, limit);
+        if used > limit {
+            self.mem.used -= bytes;
+            return false;
+        }
+        true
+    }
+
+    /// count `bytes` to be allocated, error if it exceeds the memory limit
+    pub(in crate) fn alloc(&mut self, bytes: usize) -> LuaResult<()> {
+        self.mem.used = self.mem.used.saturating_add(bytes);
+        if self.mem.used > self.mem.next_check && !self.check_memory(bytes) {
+            return Err(LuaError::new("not enough memory"));
+        }
+        self.mem.peak = cmp::max(self.mem.peak, self.mem.used);
+        Ok(())
+    }
+
+    /// bytes of strings, tables, closures, userdata and stacks in use
+    pub fn memory_used(&mut self) -> usize {
+        self.check_memory(0);
+        self.mem.peak = cmp::max(self.mem.peak, self.mem.used);
+        self.mem.used
+    }
+
+    /// the highest memory usage observed, it is an estimate from above
+    /// since frees between measures are not seen
+    pub fn memory_peak(&self) -> usize {
+        self.mem.peak
+    }
+}
@@ -0,0 +1,312 @@
+use std::any::{type_name, TypeId};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::{LuaError, LuaResult};
+use crate::func::RustFunc;
+use crate::gc::{self, Finalizable, Finalizer};
+use crate::state_func::{new_function, wrap_func};
+use crate::userdata::{LuaUserData, UserData, UserDataMethods};
+use crate::value::{LuaString, Map, Value};
+use crate::value_conv::{FromLuaMulti, IntoLuaMulti};
+use crate::value_ops::{arith_error, arith_raw, ArithOp};
+use crate::State;
+
+/// convert `HashMap<String, RustFunc>` to function values
+fn functions(m: HashMap<String, RustFunc>) -> HashMap<String, Value> {
+    m.into_iter().map(|(k, f)| (k, new_function(f))).collect()
+}
+
+/// name of field `key` if it is a utf-8 string
+fn key_str(key: &Value) -> Option<&str> {
+    match key {
+        Value::String(s) => s.to_str().ok(),
+        _ => None,
+    }
+}
+
+/// short name of type `T` without module path and generic arguments
+fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+impl State {
+    /// move `data` into lua as userdata, see `UserData` for its methods
+    pub fn create_userdata<T: UserData>(&mut self, data: T) -> Value {
+        let meta = self.userdata_meta::<T>();
+        let has_gc = meta
+            .borrow()
+            .contains_key(&Value::String("__gc".into()));
+        let ud = Rc::new(LuaUserData::new(Box::new(data), meta, None));
+        if has_gc {
+            let f = self.new_finalizer(Finalizable::UserData(Rc::downgrade(&ud)));
+            ud.set_finalizer(f);
+        }
+        Value::UserData(ud)
+    }
+
+    /// mark `obj` for finalization, return its pending finalizer
+    pub(in crate) fn new_finalizer(&mut self, obj: Finalizable) -> Finalizer {
+        // forget the dropped ones before it grows
+        if self.gc_alive.len() == self.gc_alive.capacity() {
+            self.gc_alive.retain(Finalizable::is_alive);
+        }
+        self.gc_alive.push(obj);
+        self.gc_marked += 1;
+        Finalizer::new(&self.gc_queue, self.gc_marked)
+    }
+
+    /// metatable of userdata type `T`, created once for each state
+    fn userdata_meta<T: UserData>(&mut self) -> Map {
+        if let Some(m) = self.udata_meta.get(&TypeId::of::<T>()) {
+            return m.clone();
+        }
+
+        let mut methods = UserDataMethods::<T>::new();
+        T::add_methods(&mut methods);
+        let getters = functions(methods.getters);
+        let setters = functions(methods.setters);
+        let funcs = functions(methods.methods);
+        let mut meta = functions(methods.meta);
+
+        if !funcs.is_empty() || !getters.is_empty() {
+            let index = meta.remove("__index").unwrap_or(Value::Nil);
+            let f = move |state: &mut State, (ud, key): (Value, Value)| {
+                if let Some(name) = key_str(&key) {
+                    if let Some(f) = getters.get(name) {
+                        return state.call_meta(f.clone(), vec![ud]);
+                    }
+                    if let Some(f) = funcs.get(name) {
+                        return Ok(f.clone());
+                    }
+                }
+                match &index {
+                    Value::Nil => Ok(Value::Nil),
+                    Value::Function(_) => state.call_meta(index.clone(), vec![ud, key]),
+                    index => state.index_value(index, &key),
+                }
+            };
+            meta.insert("__index".to_string(), state_function(f));
+        }
+
+        if !setters.is_empty() {
+            let newindex = meta.remove("__newindex").unwrap_or(Value::Nil);
+            let f = move |state: &mut State, (ud, key, val): (Value, Value, Value)| {
+                if let Some(name) = key_str(&key) {
+                    if let Some(f) = setters.get(name) {
+                        return state.call_meta(f.clone(), vec![ud, val]).map(|_| ());
+                    }
+                }
+                match &newindex {
+                    Value::Nil => Err(LuaError::new(format!(
+                        "attempt to set unknown field '{}' of {}",
+                        key,
+                        short_type_name::<T>()
+                    ))),
+                    Value::Function(_) => state
+                        .call_meta(newindex.clone(), vec![ud, key, val])
+                        .map(|_| ()),
+                    newindex => state.set_index_value(newindex, key, val),
+                }
+            };
+            meta.insert("__newindex".to_string(), state_function(f));
+        }
+
+        let mut m = HashMap::new();
+        for (k, v) in meta {
+            m.insert(Value::String(k.into()), v);
+        }
+        m.insert(
+            Value::String("__name".into()),
+            Value::String(short_type_name::<T>().into()),
+        );
+        let m = gc::new_table(m);
+        self.udata_meta.insert(TypeId::of::<T>(), m.clone());
+        m
+    }
+
+    /// get field `event` of metatable of `val`, `nil` if absent
+    pub(in crate) fn metamethod(&self, val: &Value, event: &str) -> Value {
+        let key = Value::String(event.into());
+        match val {
+            Value::UserData(ud) => ud.meta.borrow().get(&key).cloned().unwrap_or(Value::Nil),
+            Value::Map(m) => match &m.borrow().meta {
+                Some(meta) => meta.borrow().get(&key).cloned().unwrap_or(Value::Nil),
+                None => Value::Nil,
+            },
+            _ => Value::Nil,
+        }
+    }
+
+    /// call metamethod `f` with `args` and return its first result
+    pub(in crate) fn call_meta(&mut self, f: Value, args: Vec<Value>) -> LuaResult<Value> {
+        let rets = self.call_value(f, args)?;
+        Ok(rets.into_iter().next().unwrap_or(Value::Nil))
+    }
+
+    /// binary operator `event` like `__add`, `None` if neither operand has it
+    pub(in crate) fn binary_meta(
+        &mut self,
+        a: &Value,
+        b: &Value,
+        event: &str,
+    ) -> LuaResult<Option<Value>> {
+        let h = match self.metamethod(a, event) {
+            Value::Nil => self.metamethod(b, event),
+            h => h,
+        };
+        match h {
+            Value::Nil => Ok(None),
+            h => self.call_meta(h, vec![a.clone(), b.clone()]).map(Some),
+        }
+    }
+
+    /// `a == b` with `__eq` of userdata or tables
+    pub(in crate) fn equals(&mut self, a: &Value, b: &Value) -> LuaResult<bool> {
+        if a == b {
+            return Ok(true);
+        }
+        match (a, b) {
+            (Value::UserData(_), Value::UserData(_)) | (Value::Map(_), Value::Map(_)) => Ok(self
+                .binary_meta(a, b, "__eq")?
+                .is_some_and(Value::into_boolean)),
+            _ => Ok(false),
+        }
+    }
+
+    /// `a < b` with `__lt`
+    pub(in crate) fn less_than(&mut self, a: &Value, b: &Value) -> LuaResult<bool> {
+        if let Some(res) = compare_raw(a, b, false) {
+            return Ok(res);
+        }
+        match self.binary_meta(a, b, "__lt")? {
+            Some(res) => Ok(res.into_boolean()),
+            None => Err(compare_error(a, b)),
+        }
+    }
+
+    /// `a <= b` with `__le`, or `not (b < a)` with `__lt` if absent
+    pub(in crate) fn less_equal(&mut self, a: &Value, b: &Value) -> LuaResult<bool> {
+        if let Some(res) = compare_raw(a, b, true) {
+            return Ok(res);
+        }
+        if let Some(res) = self.binary_meta(a, b, "__le")? {
+            return Ok(res.into_boolean());
+        }
+        match self.binary_meta(b, a, "__lt")? {
+            Some(res) => Ok(!res.into_boolean()),
+            None => Err(compare_error(a, b)),
+        }
+    }
+
+    /// `a op b` with metamethods, unary operators take the operand twice
+    pub(in crate) fn arith_values(
+        &mut self,
+        op: ArithOp,
+        a: &Value,
+        b: &Value,
+    ) -> LuaResult<Value> {
+        if let Some(res) = arith_raw(op, a, b) {
+            return res;
+        }
+        match self.binary_meta(a, b, op.event())? {
+            Some(res) => Ok(res),
+            None => Err(arith_error(op, a, b)),
+        }
+    }
+
+    /// convert `val` to string like `luaL_tolstring` with `__tostring` and `__name`
+    pub(in crate) fn display_value(&mut self, val: &Value) -> LuaResult<LuaString> {
+        match self.metamethod(val, "__tostring") {
+            Value::Nil => {}
+            h => {
+                return match self.call_meta(h, vec![val.clone()])? {
+                    Value::String(s) => Ok(s),
+                    _ => Err(LuaError::new("'__tostring' must return a string")),
+                };
+            }
+        }
+        match (val, self.metamethod(val, "__name")) {
+            (Value::UserData(ud), Value::String(name)) => {
+                Ok(format!("{}: {:p}", name, Rc::as_ptr(ud)).into())
+            }
+            (Value::String(s), _) => Ok(s.clone()),
+            _ => Ok(val.to_string().into()),
+        }
+    }
+
+    /// call `__gc` of unreachable objects in reverse order of marking,
+    /// finalizers do not run recursively
+    pub(in crate) fn run_finalizers(&mut self) -> LuaResult<()> {
+        if self.finalizing {
+            return Ok(());
+        }
+        self.finalizing = true;
+        let mut res = Ok(());
+        while res.is_ok() {
+            // finalizers may queue more objects
+            let mut queue = mem::take(&mut *self.gc_queue.borrow_mut());
+            if queue.is_empty() {
+                break;
+            }
+            queue.sort_by_key(|(order, _)| Reverse(*order));
+            let mut queue = queue.into_iter();
+            for (_, obj) in queue.by_ref() {
+                // metatable of table may be changed after marked
+                match self.metamethod(&obj, "__gc") {
+                    Value::Nil => {}
+                    h => res = self.call_meta(h, vec![obj]).map(|_| ()),
+                }
+                if res.is_err() {
+                    break;
+                }
+            }
+            // the others wait for the next run
+            self.gc_queue.borrow_mut().extend(queue);
+        }
+        self.finalizing = false;
+        res
+    }
+
+    /// call `__gc` of all objects marked for finalization when state
+    /// is closed, like `lua_close`
+    pub(in crate) fn close_finalizers(&mut self) {
+        for obj in mem::take(&mut self.gc_alive) {
+            if let Some((f, obj)) = obj.take() {
+                f.push(obj);
+            }
+        }
+        // go on with the others if one fails
+        while self.run_finalizers().is_err() {}
+    }
+}
+
+/// compare numbers or strings, `None` for other values
+fn compare_raw(a: &Value, b: &Value, or_equal: bool) -> Option<bool> {
+    match (a, b) {
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_))
+        | (Value::String(_), Value::String(_)) => Some(if or_equal { a <= b } else { a < b }),
+        _ => None,
+    }
+}
+
+fn compare_error(a: &Value, b: &Value) -> LuaError {
+    let (t1, t2) = (a.type_name(), b.type_name());
+    match t1 == t2 {
+        true => LuaError::new(format!("attempt to compare two {} values", t1)),
+        false => LuaError::new(format!("attempt to compare {} with {}", t1, t2)),
+    }
+}
+
+fn state_function<A, R, F>(f: F) -> Value
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut State, A) -> LuaResult<R> + 'static,
+{
+    new_function(Rc::from(wrap_func(f)))
+}
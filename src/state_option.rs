@@ -1,10 +1,76 @@
+use std::path::PathBuf;
+
 #[derive(Debug, Default, Clone)]
 pub struct Options {
     pub show_ins: bool,
+    /// bytes of memory a state can use, exceeding it raises "not enough memory"
+    pub memory_limit: Option<usize>,
+    /// instructions each call from host can run, exceeding it raises
+    /// "instruction limit exceeded"
+    pub instruction_limit: Option<u64>,
+    /// restrict libraries and files scripts can access, builtin libraries
+    /// are installed again when it is changed by `State::with_option`
+    pub sandbox: Option<Sandbox>,
+    /// make runs of the same chunks with the same inputs give the same results
+    pub deterministic: Option<Deterministic>,
 }
 
 impl Options {
     pub fn new(show_ins: bool) -> Options {
-        Options { show_ins }
+        Options {
+            show_ins,
+            ..Options::default()
+        }
+    }
+
+    /// options with the default sandbox
+    pub fn sandboxed() -> Options {
+        Options {
+            sandbox: Some(Sandbox::default()),
+            ..Options::default()
+        }
+    }
+}
+
+/// what scripts in sandbox can access, the default allows none of them
+/// and freezes globals. `collectgarbage` only reports memory in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    /// install library `io`
+    pub io: bool,
+    /// install `os.exit`, `os.getenv`, `os.remove`, `os.rename` and `os.tmpname`
+    pub os_host: bool,
+    /// `load`, `loadfile`, `dofile` and `require` accept binary chunks,
+    /// chunks from host are always accepted
+    pub binary_chunks: bool,
+    /// files can only be opened under this directory and relative names
+    /// start from it, no file can be opened if it is not set
+    pub file_root: Option<PathBuf>,
+    /// move builtin globals into a read-only table, see `State::freeze_globals`
+    pub frozen_globals: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Sandbox {
+            io: false,
+            os_host: false,
+            binary_chunks: false,
+            file_root: None,
+            frozen_globals: true,
+        }
     }
 }
+
+/// inputs of deterministic mode, tables are traversed in the same order in
+/// every run, `os.time` and `os.clock` read a virtual clock set by host,
+/// local time is UTC and `math.random` starts from the seed of host
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Deterministic {
+    /// seed of `math.random`
+    pub seed: u64,
+    /// initial `os.time()`, see `State::set_clock`
+    pub time: i64,
+    /// initial `os.clock()`
+    pub clock: f64,
+}
@@ -0,0 +1,157 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::error::{LuaError, LuaResult};
+use crate::value::Value;
+use crate::value_conv::{FromLua, IntoLua};
+use crate::State;
+
+/// reference returned for `nil`, same as `LUA_REFNIL`
+pub const REFNIL: i32 = -1;
+
+/// reference which never refers to a value, same as `LUA_NOREF`
+pub const NOREF: i32 = -2;
+
+/// references of dropped `RegistryKey` waiting to be released
+type DropQueue = Rc<RefCell<Vec<i32>>>;
+
+/// allocation of integer references in registry
+#[derive(Default)]
+pub(in crate) struct Refs {
+    free: Vec<i32>,
+    next: i32,
+    dropped: DropQueue,
+}
+
+/// handle of a value pinned in registry, the value is released
+/// when the key is dropped
+pub struct RegistryKey {
+    id: i32,
+    dropped: Weak<RefCell<Vec<i32>>>,
+}
+
+impl RegistryKey {
+    /// the integer reference, usable with `State::get_reference`
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Drop for RegistryKey {
+    fn drop(&mut self) {
+        if self.id < 0 {
+            return;
+        }
+        if let Some(queue) = self.dropped.upgrade() {
+            queue.borrow_mut().push(self.id);
+        }
+    }
+}
+
+impl fmt::Debug for RegistryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RegistryKey({})", self.id)
+    }
+}
+
+impl State {
+    /// pop the value on top and store it in registry like `luaL_ref`,
+    /// return its reference or `REFNIL` if the value is `nil`
+    pub fn reference(&mut self) -> i32 {
+        let val = self.pop_value();
+        self.ref_value(val)
+    }
+
+    /// release reference `r` like `luaL_unref`, `REFNIL` and `NOREF` are ignored
+    pub fn unreference(&mut self, r: i32) {
+        if r < 0 {
+            return;
+        }
+        if self.registry.remove(&Value::Integer(r as i64)).is_some() {
+            self.refs.free.push(r);
+        }
+    }
+
+    /// push the value of reference `r`, `nil` if it is released
+    pub fn get_reference(&mut self, r: i32) {
+        let val = self.registry_ref(r);
+        self.check_stack(1);
+        self.push_value(val);
+    }
+
+    /// store `val` in registry and return a key which releases it when dropped
+    pub fn create_registry_value<V: IntoLua>(&mut self, val: V) -> LuaResult<RegistryKey> {
+        let val = val.into_lua()?;
+        Ok(RegistryKey {
+            id: self.ref_value(val),
+            dropped: Rc::downgrade(&self.refs.dropped),
+        })
+    }
+
+    /// get the value of `key` as rust value
+    pub fn registry_value<V: FromLua>(&self, key: &RegistryKey) -> LuaResult<V> {
+        self.check_key(key)?;
+        V::from_lua(self.registry_ref(key.id))
+    }
+
+    /// replace the value of `key`, a `nil` key can not be replaced
+    pub fn replace_registry_value<V: IntoLua>(
+        &mut self,
+        key: &RegistryKey,
+        val: V,
+    ) -> LuaResult<()> {
+        self.check_key(key)?;
+        if key.id < 0 {
+            return Err(LuaError::new(
+                "can not replace the value of a nil RegistryKey",
+            ));
+        }
+        self.registry
+            .insert(Value::Integer(key.id as i64), val.into_lua()?);
+        Ok(())
+    }
+
+    /// release the value of `key` now instead of at next reference
+    pub fn remove_registry_value(&mut self, key: RegistryKey) -> LuaResult<()> {
+        self.check_key(&key)?;
+        drop(key);
+        self.expire_registry_values();
+        Ok(())
+    }
+
+    /// release values of dropped `RegistryKey`
+    pub(in crate) fn expire_registry_values(&mut self) {
+        let dropped = mem::take(&mut *self.refs.dropped.borrow_mut());
+        dropped.into_iter().for_each(|r| self.unreference(r));
+    }
+
+    fn ref_value(&mut self, val: Value) -> i32 {
+        if val == Value::Nil {
+            return REFNIL;
+        }
+        self.expire_registry_values();
+        let r = match self.refs.free.pop() {
+            Some(r) => r,
+            None => {
+                self.refs.next += 1;
+                self.refs.next
+            }
+        };
+        self.registry.insert(Value::Integer(r as i64), val);
+        r
+    }
+
+    fn registry_ref(&self, r: i32) -> Value {
+        let key = Value::Integer(r as i64);
+        self.registry.get(&key).cloned().unwrap_or(Value::Nil)
+    }
+
+    fn check_key(&self, key: &RegistryKey) -> LuaResult<()> {
+        match Weak::ptr_eq(&key.dropped, &Rc::downgrade(&self.refs.dropped)) {
+            true => Ok(()),
+            false => Err(LuaError::new("RegistryKey used with a different state")),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::builtin_package::LOADED;
+use crate::gc;
+use crate::state::GLOBAL_MAP_INDEX;
+use crate::state_option::{Options, Sandbox};
+use crate::value::{Map, Table, Value};
+use crate::State;
+
+/// functions of `os` touching the host
+const OS_HOST: [&str; 5] = ["exit", "getenv", "remove", "rename", "tmpname"];
+
+/// `package.path` in sandbox, relative to the file root
+const SANDBOX_PATH: &str = "./?.luac;./?/init.luac";
+
+fn key(k: &str) -> Value {
+    Value::String(k.into())
+}
+
+/// remove libraries and functions not allowed by `sandbox` from builtin globals `m`
+pub(in crate) fn restrict_builtin(
+    sandbox: &Sandbox,
+    m: &mut HashMap<Value, Value>,
+    registry: &HashMap<Value, Value>,
+) {
+    if !sandbox.io {
+        m.remove(&key("io"));
+        if let Some(Value::Map(loaded)) = registry.get(&key(LOADED)) {
+            loaded.borrow_mut().remove(&key("io"));
+        }
+    }
+    if !sandbox.os_host {
+        if let Some(Value::Map(os)) = m.get(&key("os")) {
+            let mut os = os.borrow_mut();
+            OS_HOST.iter().for_each(|name| {
+                os.remove(&key(name));
+            });
+        }
+    }
+    // modules are searched under the file root instead of paths of host
+    if let Some(Value::Map(package)) = m.get(&key("package")) {
+        package.borrow_mut().insert(key("path"), key(SANDBOX_PATH));
+    }
+}
+
+/// read-only table behind `__index` of global map `g` if it is frozen
+pub(in crate) fn frozen_base(g: &Map) -> Option<Map> {
+    let meta = g.borrow().meta.clone()?;
+    let meta = meta.borrow();
+    match meta.get(&key("__index")) {
+        Some(Value::Map(base)) if base.borrow().frozen => Some(base.clone()),
+        _ => None,
+    }
+}
+
+/// move entries of global map `g` into its read-only base table
+pub(in crate) fn freeze_globals(g: &Map) {
+    let base = frozen_base(g).unwrap_or_else(|| {
+        let base = gc::new_table(HashMap::new());
+        base.borrow_mut().frozen = true;
+        let mut meta = HashMap::new();
+        meta.insert(key("__index"), Value::Map(base.clone()));
+        meta.insert(key("__metatable"), Value::Bool(false));
+        let meta = gc::new_table(meta);
+        meta.borrow_mut().frozen = true;
+        g.borrow_mut().meta = Some(meta);
+        base
+    });
+
+    let entries: Vec<_> = g.borrow_mut().drain().collect();
+    // tables such as libraries are read-only too
+    freeze_tables(entries.iter().map(|(_, v)| v), g);
+    let mut base = base.borrow_mut();
+    for (k, v) in entries {
+        base.insert(k, v);
+    }
+}
+
+/// make tables reachable from `roots` read-only, like `package.searchers`
+/// inside libraries, except global map `g`
+fn freeze_tables<'a>(roots: impl Iterator<Item = &'a Value>, g: &Map) {
+    let mut visited: HashSet<*const RefCell<Table>> = HashSet::new();
+    let mut pending: Vec<Map> = roots
+        .filter_map(|v| match v {
+            Value::Map(m) => Some(m.clone()),
+            _ => None,
+        })
+        .collect();
+    while let Some(m) = pending.pop() {
+        if Rc::ptr_eq(&m, g) || !visited.insert(Rc::as_ptr(&m)) {
+            continue;
+        }
+        let mut t = match m.try_borrow_mut() {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        t.frozen = true;
+        for (k, v) in t.iter() {
+            for v in [&k, v] {
+                if let Value::Map(m) = v {
+                    pending.push(m.clone());
+                }
+            }
+        }
+        if let Some(meta) = &t.meta {
+            pending.push(meta.clone());
+        }
+    }
+}
+
+impl State {
+    /// create state for untrusted scripts with the default sandbox
+    pub fn sandboxed() -> State {
+        Self::new().with_option(Options::sandboxed())
+    }
+
+    /// move globals into a read-only table shared by the scripts of state,
+    /// scripts read them through the global map and set their own globals
+    /// over them. tables reachable from globals such as libraries and
+    /// `package.loaded` become read-only too
+    pub fn freeze_globals(&mut self) {
+        if let Some(Value::Map(g)) = self.registry.get(GLOBAL_MAP_INDEX) {
+            freeze_globals(g);
+        }
+    }
+
+    /// whether scripts can load binary chunks
+    pub(in crate) fn binary_chunks_allowed(&self) -> bool {
+        self.options
+            .sandbox
+            .as_ref()
+            .is_none_or(|sandbox| sandbox.binary_chunks)
+    }
+
+    /// path of file `name` scripts can access, names are resolved from the
+    /// file root in sandbox and those out of it are denied
+    pub(in crate) fn sandbox_path(&self, name: &str) -> io::Result<PathBuf> {
+        let sandbox = match &self.options.sandbox {
+            Some(sandbox) => sandbox,
+            None => return Ok(PathBuf::from(name)),
+        };
+        let denied = || io::Error::from_raw_os_error(libc::EACCES);
+        let root = sandbox
+            .file_root
+            .as_ref()
+            .ok_or_else(denied)?
+            .canonicalize()?;
+        let path = root.join(name);
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            // file to be created, its directory must exist
+            Err(_) if fs::symlink_metadata(&path).is_err() => {
+                let file = path.file_name().ok_or_else(denied)?;
+                path.parent().ok_or_else(denied)?.canonicalize()?.join(file)
+            }
+            Err(e) => return Err(e),
+        };
+        match path.starts_with(&root) {
+            true => Ok(path),
+            false => Err(denied()),
+        }
+    }
+}
@@ -1,14 +1,14 @@
+use crate::error::LuaResult;
 use crate::value::Value;
 use crate::State;
 
 impl State {
-    fn uv_get_index(&mut self, index: i32) -> Value {
-        self.stack_mut().upvals[index as usize].borrow_mut().clone()
+    pub(in crate) fn uv_get_index(&mut self, index: i32) -> Value {
+        self.stack().upval_get(index as usize)
     }
 
     fn uv_set_index(&mut self, index: i32, val: Value) {
-        let uvref = self.stack_mut().upvals[index as usize].clone();
-        *uvref.borrow_mut() = val;
+        self.stack_mut().upval_set(index as usize, val);
     }
 
     pub fn uv_get(&mut self, uv_idx: i32, to: i32) {
@@ -22,32 +22,26 @@ impl State {
         self.uv_set_index(uv_idx - 1, val);
     }
 
-    pub fn uv_map_get(&mut self, uv_idx: i32) {
+    pub fn uv_map_get(&mut self, uv_idx: i32) -> LuaResult<()> {
         let uvmap = self.uv_get_index(uv_idx - 1);
-        let stack = self.stack_mut();
-        let key = stack.pop();
-        if let Value::Map(m) = uvmap {
-            let val = m.borrow_mut().get(&key).unwrap_or(&Value::Nil).clone();
-            stack.push(val);
-        } else {
-            panic!("not a map");
-        }
+        let key = self.pop_value();
+        let val = self.index_value(&uvmap, &key)?;
+        self.push_value(val);
+        Ok(())
     }
 
-    pub fn uv_map_set(&mut self, uv_idx: i32) {
+    pub fn uv_map_set(&mut self, uv_idx: i32) -> LuaResult<()> {
         let uvmap = self.uv_get_index(uv_idx - 1);
         let stack = self.stack_mut();
         let val = stack.pop();
         let key = stack.pop();
-        if let Value::Map(m) = uvmap {
-            m.borrow_mut().insert(key, val);
-            self.uv_set_index(uv_idx - 1, Value::Map(m));
-        } else {
-            panic!("not a map");
-        }
+        self.set_index_value(&uvmap, key, val)
     }
 
-    pub fn close_upval(&mut self, _: i32) {
-        // ???
+    /// close upvalues of registers from `a - 1` of current frame, `a` is
+    /// the operand of `JMP`
+    pub fn close_upval(&mut self, a: i32) {
+        let level = self.stack().base() + a as usize - 1;
+        self.stack_mut().close_upvals(level);
     }
 }
@@ -0,0 +1,272 @@
+use std::any::{type_name, Any};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+
+use crate::error::{LuaError, LuaResult};
+use crate::func::RustFunc;
+use crate::gc::{self, Finalizer};
+use crate::state_func::wrap_func;
+use crate::value::{Map, Value};
+use crate::value_conv::{from_single, into_single, Variadic};
+use crate::value_conv::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti};
+use crate::State;
+
+/// rust value owned by lua, created by `State::create_userdata`
+pub struct LuaUserData {
+    data: RefCell<Box<dyn Any>>,
+    pub(in crate) meta: Map,
+    /// pending `__gc` which queues the data when dropped, `None` if finalized
+    gc: RefCell<Option<Finalizer>>,
+    /// creation order, see `gc::next_seq`
+    pub(in crate) seq: u64,
+}
+
+impl LuaUserData {
+    pub(in crate) fn new(data: Box<dyn Any>, meta: Map, gc: Option<Finalizer>) -> Self {
+        LuaUserData {
+            data: RefCell::new(data),
+            meta,
+            gc: RefCell::new(gc),
+            seq: gc::next_seq(),
+        }
+    }
+
+    /// whether the data is of type `T`
+    pub fn is<T: 'static>(&self) -> bool {
+        self.data.borrow().is::<T>()
+    }
+
+    /// borrow the data as `T`
+    pub fn borrow<T: 'static>(&self) -> LuaResult<Ref<'_, T>> {
+        let data = self
+            .data
+            .try_borrow()
+            .map_err(|_| LuaError::new("userdata is already mutably borrowed"))?;
+        if !data.is::<T>() {
+            return Err(LuaError::from_lua("userdata", type_name::<T>(), None));
+        }
+        Ok(Ref::map(data, |d| d.downcast_ref::<T>().unwrap()))
+    }
+
+    /// mutably borrow the data as `T`
+    pub fn borrow_mut<T: 'static>(&self) -> LuaResult<RefMut<'_, T>> {
+        let data = self
+            .data
+            .try_borrow_mut()
+            .map_err(|_| LuaError::new("userdata is already borrowed"))?;
+        if !data.is::<T>() {
+            return Err(LuaError::from_lua("userdata", type_name::<T>(), None));
+        }
+        Ok(RefMut::map(data, |d| d.downcast_mut::<T>().unwrap()))
+    }
+
+    pub(in crate) fn set_finalizer(&self, f: Finalizer) {
+        *self.gc.borrow_mut() = Some(f);
+    }
+
+    pub(in crate) fn has_finalizer(&self) -> bool {
+        self.gc.borrow().is_some()
+    }
+
+    /// do not call `__gc` for this userdata, return the pending one
+    pub(in crate) fn take_finalizer(&self) -> Option<Finalizer> {
+        self.gc.borrow_mut().take()
+    }
+
+    /// size of the data in bytes
+    pub(in crate) fn data_size(&self) -> usize {
+        self.data.try_borrow().map_or(0, |d| mem::size_of_val(&**d))
+    }
+}
+
+impl Drop for LuaUserData {
+    fn drop(&mut self) {
+        // data is moved into a new userdata which is passed to `__gc` later
+        let f = match self.gc.get_mut().take() {
+            Some(f) => f,
+            None => return,
+        };
+        let data = mem::replace(self.data.get_mut(), Box::new(()));
+        let ud = LuaUserData::new(data, self.meta.clone(), None);
+        f.push(Value::UserData(Rc::new(ud)));
+    }
+}
+
+impl fmt::Debug for LuaUserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "userdata: {:p}", self)
+    }
+}
+
+impl FromLua for Rc<LuaUserData> {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        match val {
+            Value::UserData(ud) => Ok(ud),
+            val => Err(LuaError::from_lua(val.type_name(), "userdata", None)),
+        }
+    }
+}
+
+impl IntoLua for Rc<LuaUserData> {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::UserData(self))
+    }
+}
+
+impl IntoLuaMulti for Rc<LuaUserData> {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl FromLuaMulti for Rc<LuaUserData> {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        from_single(vals)
+    }
+}
+
+/// rust type which can be passed to lua as userdata
+pub trait UserData: Sized + 'static {
+    /// register methods, fields and metamethods of this type
+    fn add_methods(_methods: &mut UserDataMethods<Self>) {}
+}
+
+/// methods, fields and metamethods of userdata type `T`
+pub struct UserDataMethods<T> {
+    pub(in crate) methods: HashMap<String, RustFunc>,
+    pub(in crate) getters: HashMap<String, RustFunc>,
+    pub(in crate) setters: HashMap<String, RustFunc>,
+    pub(in crate) meta: HashMap<String, RustFunc>,
+    _type: PhantomData<T>,
+}
+
+/// take the userdata `self` from the first argument
+fn this(args: &mut Vec<Value>) -> LuaResult<Rc<LuaUserData>> {
+    match args.is_empty() {
+        true => Err(LuaError::from_lua("no value", "userdata", None)),
+        false => Rc::<LuaUserData>::from_lua(args.remove(0)),
+    }
+}
+
+fn rust_func<A, R, F>(f: F) -> RustFunc
+where
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut State, A) -> LuaResult<R> + 'static,
+{
+    Rc::from(wrap_func(f))
+}
+
+impl<T: UserData> UserDataMethods<T> {
+    pub(in crate) fn new() -> Self {
+        UserDataMethods {
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            meta: HashMap::new(),
+            _type: PhantomData,
+        }
+    }
+
+    /// method called as `ud:name(...)`
+    pub fn add_method<A, R, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, &T, A) -> LuaResult<R> + 'static,
+    {
+        self.methods.insert(name.to_string(), method(f));
+    }
+
+    /// method called as `ud:name(...)` which borrows `self` mutably
+    pub fn add_method_mut<A, R, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, &mut T, A) -> LuaResult<R> + 'static,
+    {
+        self.methods.insert(name.to_string(), method_mut(f));
+    }
+
+    /// function called as `ud.name(...)`
+    pub fn add_function<A, R, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, A) -> LuaResult<R> + 'static,
+    {
+        self.methods.insert(name.to_string(), rust_func(f));
+    }
+
+    /// field read as `ud.name`
+    pub fn add_field_get<R, F>(&mut self, name: &str, f: F)
+    where
+        R: IntoLuaMulti,
+        F: Fn(&mut State, &T) -> LuaResult<R> + 'static,
+    {
+        let getter = method(move |state, this: &T, ()| f(state, this));
+        self.getters.insert(name.to_string(), getter);
+    }
+
+    /// field written as `ud.name = v`
+    pub fn add_field_set<A, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLua,
+        F: Fn(&mut State, &mut T, A) -> LuaResult<()> + 'static,
+    {
+        let setter = method_mut(move |state, this: &mut T, (v,): (A,)| f(state, this, v));
+        self.setters.insert(name.to_string(), setter);
+    }
+
+    /// metamethod like `__tostring` or `__gc` with userdata as first argument
+    pub fn add_meta_method<A, R, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, &T, A) -> LuaResult<R> + 'static,
+    {
+        self.meta.insert(name.to_string(), method(f));
+    }
+
+    /// metamethod like `__add` or `__eq`, the userdata may be either operand
+    pub fn add_meta_function<A, R, F>(&mut self, name: &str, f: F)
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(&mut State, A) -> LuaResult<R> + 'static,
+    {
+        self.meta.insert(name.to_string(), rust_func(f));
+    }
+}
+
+fn method<T, A, R, F>(f: F) -> RustFunc
+where
+    T: 'static,
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut State, &T, A) -> LuaResult<R> + 'static,
+{
+    rust_func(move |state, Variadic(mut args): Variadic<Value>| {
+        let ud = this(&mut args)?;
+        let this = ud.borrow::<T>()?;
+        f(state, &this, A::from_lua_multi(args)?)
+    })
+}
+
+fn method_mut<T, A, R, F>(f: F) -> RustFunc
+where
+    T: 'static,
+    A: FromLuaMulti,
+    R: IntoLuaMulti,
+    F: Fn(&mut State, &mut T, A) -> LuaResult<R> + 'static,
+{
+    rust_func(move |state, Variadic(mut args): Variadic<Value>| {
+        let ud = this(&mut args)?;
+        let mut this = ud.borrow_mut::<T>()?;
+        f(state, &mut this, A::from_lua_multi(args)?)
+    })
+}
@@ -1,11 +1,14 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::num::ParseFloatError;
 use std::rc::Rc;
 
-use crate::func::Closure;
+use crate::builtin_io::LuaFile;
+use crate::func::{Closure, Func};
+use crate::userdata::LuaUserData;
+use crate::value_impl::{float_to_integer, float_to_string};
+pub use crate::value_string::LuaString;
+pub use crate::value_table::Table;
 
 #[derive(Copy, Clone, Hash)]
 pub struct Upvalue {
@@ -27,8 +30,7 @@ pub const CONST_TAG_INT: u8 = 0x13;
 pub const CONST_TAG_SHORT_STR: u8 = 0x04;
 pub const CONST_TAG_LONG_STR: u8 = 0x14;
 
-pub type Map = RefCell<HashMap<Value, Value>>;
-pub type MutValue = Rc<RefCell<Value>>;
+pub type Map = Rc<RefCell<Table>>;
 
 #[derive(Clone)]
 pub enum Value {
@@ -36,9 +38,11 @@ pub enum Value {
     Bool(bool),
     Integer(i64),
     Float(f64),
-    String(String),
+    String(LuaString),
     Map(Map),
-    Function(Closure),
+    Function(Rc<Closure>),
+    File(Rc<RefCell<LuaFile>>),
+    UserData(Rc<LuaUserData>),
 }
 
 impl Hash for Value {
@@ -48,10 +52,16 @@ impl Hash for Value {
             Value::Nil => {}
             Value::Bool(v) => v.hash(state),
             Value::Integer(i) => i.hash(state),
-            Value::Float(f) => f.to_be_bytes().hash(state),
+            // float with integral value equals to the integer
+            Value::Float(f) => match float_to_integer(*f) {
+                Ok(i) => i.hash(state),
+                Err(_) => f.to_be_bytes().hash(state),
+            },
             Value::String(s) => s.hash(state),
-            Value::Map(m) => m.borrow().keys().for_each(|k| k.hash(state)),
-            Value::Function(f) => f.hash(state),
+            Value::Map(m) => Rc::as_ptr(m).hash(state),
+            Value::Function(f) => Rc::as_ptr(f).hash(state),
+            Value::File(f) => Rc::as_ptr(f).hash(state),
+            Value::UserData(u) => Rc::as_ptr(u).hash(state),
         }
     }
 }
@@ -59,13 +69,23 @@ impl Hash for Value {
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Nil => write!(f, "Nil"),
+            Value::Nil => write!(f, "nil"),
             Value::Bool(v) => write!(f, "{}", v),
             Value::Integer(v) => write!(f, "{}", v),
-            Value::Float(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", float_to_string(*v)),
             Value::String(v) => write!(f, "{}", v),
-            Value::Map(m) => write!(f, "{:?}", m),
-            Value::Function(_) => write!(f, "{}", "Function?"),
+            Value::Map(m) => write!(f, "table: {:p}", Rc::as_ptr(m)),
+            Value::Function(func) => match func.proto {
+                Func::Proto(_) => write!(f, "function: {:p}", Rc::as_ptr(func)),
+                Func::Builtin(_) | Func::Rust(_) => {
+                    write!(f, "function: builtin: {:p}", Rc::as_ptr(func))
+                }
+            },
+            Value::File(file) => match file.borrow().is_closed() {
+                true => write!(f, "file (closed)"),
+                false => write!(f, "file ({:p})", Rc::as_ptr(file)),
+            },
+            Value::UserData(u) => write!(f, "userdata: {:p}", Rc::as_ptr(u)),
         }
     }
 }
@@ -79,7 +99,6 @@ impl fmt::Debug for Value {
 pub type IntoResult<T> = Result<T, IntoError>;
 
 pub enum IntoError {
-    Float(ParseFloatError),
     FloatToInteger,
     TypeUnsupported,
 }
@@ -87,9 +106,8 @@ pub enum IntoError {
 impl fmt::Display for IntoError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            IntoError::Float(e) => write!(f, "{}", e),
-            IntoError::FloatToInteger => write!(f, "{}", "float convert to int error"),
-            IntoError::TypeUnsupported => write!(f, "{}", "unsupported type conversion"),
+            IntoError::FloatToInteger => write!(f, "float convert to int error"),
+            IntoError::TypeUnsupported => write!(f, "unsupported type conversion"),
         }
     }
 }
@@ -1,5 +1,35 @@
 use crate::value::Value;
 use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// rank of key type in deterministic traversal
+fn key_rank(v: &Value) -> (u8, u64) {
+    match v {
+        Value::Nil => (0, 0),
+        Value::Bool(_) => (1, 0),
+        Value::Integer(_) | Value::Float(_) => (2, 0),
+        Value::String(_) => (3, 0),
+        Value::Map(m) => (4, m.try_borrow().map_or(0, |m| m.seq)),
+        Value::Function(f) => (5, f.seq),
+        Value::File(f) => (6, f.try_borrow().map_or(0, |f| f.seq)),
+        Value::UserData(u) => (7, u.seq),
+    }
+}
+
+/// total order of table keys which is the same in every run, numbers and
+/// strings are sorted by value and objects by creation
+pub(in crate) fn key_order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+        // integer first if they are equal after conversion
+        (Value::Integer(i), Value::Float(f)) => (*i as f64).total_cmp(f).then(Ordering::Less),
+        (Value::Float(f), Value::Integer(i)) => f.total_cmp(&(*i as f64)).then(Ordering::Greater),
+        _ => key_rank(a).cmp(&key_rank(b)),
+    }
+}
 
 impl std::cmp::PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
@@ -9,14 +39,14 @@ impl std::cmp::PartialEq for Value {
                 Value::Bool(b) => a == b,
                 _ => false,
             },
-            &Value::Integer(i1) => match other {
-                &Value::Integer(i2) => i1 == i2,
-                &Value::Float(f2) => (i1 as f64) == f2,
+            &Value::Integer(i1) => match *other {
+                Value::Integer(i2) => i1 == i2,
+                Value::Float(f2) => (i1 as f64) == f2,
                 _ => false,
             },
-            &Value::Float(f1) => match other {
-                &Value::Integer(i2) => f1 == (i2 as f64),
-                &Value::Float(f2) => f1 == f2,
+            &Value::Float(f1) => match *other {
+                Value::Integer(i2) => f1 == (i2 as f64),
+                Value::Float(f2) => f1 == f2,
                 _ => false,
             },
             Value::String(s1) => match other {
@@ -24,16 +54,23 @@ impl std::cmp::PartialEq for Value {
                 _ => false,
             },
             Value::Map(m1) => match other {
-                Value::Map(m2) => m1 == m2,
+                Value::Map(m2) => Rc::ptr_eq(m1, m2),
+                _ => false,
+            },
+            Value::Function(f1) => match other {
+                Value::Function(f2) => Rc::ptr_eq(f1, f2),
+                _ => false,
+            },
+            Value::File(f1) => match other {
+                Value::File(f2) => Rc::ptr_eq(f1, f2),
+                _ => false,
+            },
+            Value::UserData(u1) => match other {
+                Value::UserData(u2) => Rc::ptr_eq(u1, u2),
                 _ => false,
             },
-            Value::Function(_) => false,
         }
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        !self.eq(other)
-    }
 }
 
 impl std::cmp::PartialOrd for Value {
@@ -51,14 +88,14 @@ impl std::cmp::PartialOrd for Value {
 
     fn lt(&self, other: &Self) -> bool {
         match self {
-            &Value::Integer(i1) => match other {
-                &Value::Integer(i2) => i1 < i2,
-                &Value::Float(f2) => (i1 as f64) < f2,
+            &Value::Integer(i1) => match *other {
+                Value::Integer(i2) => i1 < i2,
+                Value::Float(f2) => (i1 as f64) < f2,
                 _ => panic!("comparison error"),
             },
-            &Value::Float(f1) => match other {
-                &Value::Float(f2) => f1 < f2,
-                &Value::Integer(i2) => f1 < (i2 as f64),
+            &Value::Float(f1) => match *other {
+                Value::Float(f2) => f1 < f2,
+                Value::Integer(i2) => f1 < (i2 as f64),
                 _ => panic!("comparison error"),
             },
             Value::String(s1) => match other {
@@ -75,14 +112,14 @@ impl std::cmp::PartialOrd for Value {
 
     fn gt(&self, other: &Self) -> bool {
         match self {
-            &Value::Integer(i1) => match other {
-                &Value::Integer(i2) => i1 > i2,
-                &Value::Float(f2) => (i1 as f64) > f2,
+            &Value::Integer(i1) => match *other {
+                Value::Integer(i2) => i1 > i2,
+                Value::Float(f2) => (i1 as f64) > f2,
                 _ => panic!("comparison error"),
             },
-            &Value::Float(f1) => match other {
-                &Value::Float(f2) => f1 > f2,
-                &Value::Integer(i2) => f1 > (i2 as f64),
+            &Value::Float(f1) => match *other {
+                Value::Float(f2) => f1 > f2,
+                Value::Integer(i2) => f1 > (i2 as f64),
                 _ => panic!("comparison error"),
             },
             Value::String(s1) => match other {
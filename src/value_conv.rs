@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+
+use crate::error::{LuaError, LuaResult};
+use crate::gc;
+use crate::state_map::{map_len, map_raw_set};
+use crate::value::{IntoError, LuaString, Map, Value};
+
+/// convert rust value into lua value
+pub trait IntoLua {
+    fn into_lua(self) -> LuaResult<Value>;
+}
+
+/// convert lua value into rust value
+pub trait FromLua: Sized {
+    fn from_lua(val: Value) -> LuaResult<Self>;
+}
+
+impl IntoLua for Value {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(self)
+    }
+}
+
+impl FromLua for Value {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        Ok(val)
+    }
+}
+
+impl IntoLua for bool {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::Bool(self))
+    }
+}
+
+/// only `nil` and `false` are false, same as `lua_toboolean`
+impl FromLua for bool {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        Ok(val.into_boolean())
+    }
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {$(
+        impl IntoLua for $t {
+            fn into_lua(self) -> LuaResult<Value> {
+                // integers out of `i64` range are kept as float
+                Ok(match i64::try_from(self) {
+                    Ok(i) => Value::Integer(i),
+                    Err(_) => Value::Float(self as f64),
+                })
+            }
+        }
+
+        impl FromLua for $t {
+            fn from_lua(val: Value) -> LuaResult<Self> {
+                let from = val.type_name();
+                let to = stringify!($t);
+                let i = match val.into_integer() {
+                    Ok(i) => i,
+                    Err(IntoError::FloatToInteger) => {
+                        let msg = "number has no integer representation".to_string();
+                        return Err(LuaError::from_lua(from, to, Some(msg)));
+                    }
+                    Err(_) => return Err(LuaError::from_lua(from, to, None)),
+                };
+                <$t>::try_from(i).map_err(|_| LuaError::from_lua(from, to, Some("out of range".to_string())))
+            }
+        }
+    )*};
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! impl_float {
+    ($($t:ty),*) => {$(
+        impl IntoLua for $t {
+            fn into_lua(self) -> LuaResult<Value> {
+                Ok(Value::Float(self as f64))
+            }
+        }
+
+        impl FromLua for $t {
+            fn from_lua(val: Value) -> LuaResult<Self> {
+                let from = val.type_name();
+                match val.into_float() {
+                    Ok(f) => Ok(f as $t),
+                    Err(_) => Err(LuaError::from_lua(from, stringify!($t), None)),
+                }
+            }
+        }
+    )*};
+}
+
+impl_float!(f32, f64);
+
+impl IntoLua for String {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::String(self.into()))
+    }
+}
+
+impl IntoLua for LuaString {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::String(self))
+    }
+}
+
+impl IntoLua for &str {
+    fn into_lua(self) -> LuaResult<Value> {
+        Ok(Value::String(self.into()))
+    }
+}
+
+/// numbers are converted to string, same as `lua_tolstring`
+impl FromLua for LuaString {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        let from = val.type_name();
+        val.into_string()
+            .map_err(|_| LuaError::from_lua(from, "LuaString", None))
+    }
+}
+
+/// same as `LuaString` but the bytes must be valid utf-8
+impl FromLua for String {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        let from = val.type_name();
+        let s = val
+            .into_string()
+            .map_err(|_| LuaError::from_lua(from, "String", None))?;
+        s.into_string().map_err(|e| {
+            LuaError::from_lua(from, "String", Some(e.utf8_error().to_string()))
+        })
+    }
+}
+
+impl<T: IntoLua> IntoLua for Option<T> {
+    fn into_lua(self) -> LuaResult<Value> {
+        match self {
+            Some(v) => v.into_lua(),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+impl<T: FromLua> FromLua for Option<T> {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        match val {
+            Value::Nil => Ok(None),
+            val => T::from_lua(val).map(Some),
+        }
+    }
+}
+
+fn new_map(n: usize) -> Map {
+    gc::new_table(HashMap::with_capacity(n))
+}
+
+/// take the table out of `val` for converting into `to`
+fn expect_map(val: Value, to: &'static str) -> LuaResult<Map> {
+    match val {
+        Value::Map(m) => Ok(m),
+        val => Err(LuaError::from_lua(
+            val.type_name(),
+            to,
+            Some("expected table".to_string()),
+        )),
+    }
+}
+
+/// get `m[key]` and convert it, errors are marked with `key`
+fn convert_entry<T: FromLua>(m: &Map, key: Value) -> LuaResult<T> {
+    let val = m.borrow().get(&key).cloned().unwrap_or(Value::Nil);
+    T::from_lua(val).map_err(|e| {
+        let at = match &key {
+            Value::String(s) => format!("field '{}'", s),
+            key => format!("index {}", key),
+        };
+        mark_error(e, at)
+    })
+}
+
+/// prefix message of conversion error with position `at`
+fn mark_error(e: LuaError, at: String) -> LuaError {
+    match e {
+        LuaError::FromLua { from, to, message } => {
+            let message = match message {
+                Some(msg) => format!("{}: {}", at, msg),
+                None => at,
+            };
+            LuaError::from_lua(from, to, Some(message))
+        }
+        e => e,
+    }
+}
+
+/// array table with elements at `1..=n`
+impl<T: IntoLua> IntoLua for Vec<T> {
+    fn into_lua(self) -> LuaResult<Value> {
+        let m = new_map(self.len());
+        for (i, v) in self.into_iter().enumerate() {
+            map_raw_set(&m, Value::Integer(i as i64 + 1), v.into_lua()?)?;
+        }
+        Ok(Value::Map(m))
+    }
+}
+
+/// elements from `1` to the border `#t`
+impl<T: FromLua> FromLua for Vec<T> {
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        let m = expect_map(val, "Vec")?;
+        (1..=map_len(&m))
+            .map(|i| convert_entry(&m, Value::Integer(i)))
+            .collect()
+    }
+}
+
+impl<K: IntoLua, V: IntoLua, S> IntoLua for HashMap<K, V, S> {
+    fn into_lua(self) -> LuaResult<Value> {
+        let m = new_map(self.len());
+        for (k, v) in self {
+            map_raw_set(&m, k.into_lua()?, v.into_lua()?)?;
+        }
+        Ok(Value::Map(m))
+    }
+}
+
+impl<K, V, S> FromLua for HashMap<K, V, S>
+where
+    K: FromLua + Eq + Hash,
+    V: FromLua,
+    S: BuildHasher + Default,
+{
+    fn from_lua(val: Value) -> LuaResult<Self> {
+        let m = expect_map(val, "HashMap")?;
+        let entries = m.borrow().keys().collect::<Vec<_>>();
+        let mut res = HashMap::with_capacity_and_hasher(entries.len(), S::default());
+        for key in entries {
+            let val = convert_entry(&m, key.clone())?;
+            res.insert(K::from_lua(key)?, val);
+        }
+        Ok(res)
+    }
+}
+
+/// tuples are array tables, the `n`th element at index `n`
+macro_rules! impl_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: IntoLua),+> IntoLua for ($($name,)+) {
+            fn into_lua(self) -> LuaResult<Value> {
+                let m = new_map(0);
+                $(map_raw_set(&m, Value::Integer($idx + 1), self.$idx.into_lua()?)?;)+
+                Ok(Value::Map(m))
+            }
+        }
+
+        impl<$($name: FromLua),+> FromLua for ($($name,)+) {
+            fn from_lua(val: Value) -> LuaResult<Self> {
+                let m = expect_map(val, "tuple")?;
+                Ok(($(convert_entry::<$name>(&m, Value::Integer($idx + 1))?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// convert rust value into multiple lua values, like arguments and results
+///
+/// tuples are expanded into their elements, other values are a single
+/// value, wrap it in 1-tuple `(v,)` if a type only implements `IntoLua`
+pub trait IntoLuaMulti {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>>;
+}
+
+/// convert multiple lua values into rust value, missing values are `nil`
+pub trait FromLuaMulti: Sized {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self>;
+}
+
+/// any number of values of the same type, like `...` in lua
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Variadic<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.0
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for Variadic<T> {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        self.0.into_iter().map(IntoLua::into_lua).collect()
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Variadic<T> {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        let vals = vals
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| convert_value(v, i));
+        vals.collect::<LuaResult<_>>().map(Variadic)
+    }
+}
+
+/// convert the `i`th of multiple values, errors are marked with its position
+fn convert_value<T: FromLua>(val: Value, i: usize) -> LuaResult<T> {
+    T::from_lua(val).map_err(|e| mark_error(e, format!("argument #{}", i + 1)))
+}
+
+pub(in crate) fn into_single<T: IntoLua>(val: T) -> LuaResult<Vec<Value>> {
+    Ok(vec![val.into_lua()?])
+}
+
+pub(in crate) fn from_single<T: FromLua>(vals: Vec<Value>) -> LuaResult<T> {
+    let val = vals.into_iter().next().unwrap_or(Value::Nil);
+    convert_value(val, 0)
+}
+
+macro_rules! impl_multi_single {
+    ($($t:ty),*) => {$(
+        impl IntoLuaMulti for $t {
+            fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+                into_single(self)
+            }
+        }
+
+        impl FromLuaMulti for $t {
+            fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+                from_single(vals)
+            }
+        }
+    )*};
+}
+
+impl_multi_single!(Value, bool, String, LuaString, f32, f64);
+impl_multi_single!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoLuaMulti for &str {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for Option<T> {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Option<T> {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        from_single(vals)
+    }
+}
+
+impl<T: IntoLua> IntoLuaMulti for Vec<T> {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Vec<T> {
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        from_single(vals)
+    }
+}
+
+impl<K: IntoLua, V: IntoLua, S> IntoLuaMulti for HashMap<K, V, S> {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        into_single(self)
+    }
+}
+
+impl<K, V, S> FromLuaMulti for HashMap<K, V, S>
+where
+    K: FromLua + Eq + Hash,
+    V: FromLua,
+    S: BuildHasher + Default,
+{
+    fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+        from_single(vals)
+    }
+}
+
+impl IntoLuaMulti for () {
+    fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+        Ok(vec![])
+    }
+}
+
+impl FromLuaMulti for () {
+    fn from_lua_multi(_: Vec<Value>) -> LuaResult<Self> {
+        Ok(())
+    }
+}
+
+/// tuples are expanded, extra values are dropped
+macro_rules! impl_multi_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name: IntoLua),+> IntoLuaMulti for ($($name,)+) {
+            fn into_lua_multi(self) -> LuaResult<Vec<Value>> {
+                Ok(vec![$(self.$idx.into_lua()?),+])
+            }
+        }
+
+        impl<$($name: FromLua),+> FromLuaMulti for ($($name,)+) {
+            fn from_lua_multi(vals: Vec<Value>) -> LuaResult<Self> {
+                let mut vals = vals.into_iter();
+                Ok(($(convert_value::<$name>(vals.next().unwrap_or(Value::Nil), $idx)?,)+))
+            }
+        }
+    };
+}
+
+impl_multi_tuple!(A 0);
+impl_multi_tuple!(A 0, B 1);
+impl_multi_tuple!(A 0, B 1, C 2);
+impl_multi_tuple!(A 0, B 1, C 2, D 3);
+impl_multi_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_multi_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_multi_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_multi_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// helpers for code generated by `#[derive(IntoLua, FromLua)]`
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    /// build table with `fields`, fields with `nil` value are absent
+    pub fn new_table(fields: Vec<(Value, Value)>) -> LuaResult<Value> {
+        let m = new_map(fields.len());
+        for (k, v) in fields {
+            map_raw_set(&m, k, v)?;
+        }
+        Ok(Value::Map(m))
+    }
+
+    pub fn expect_table(val: Value, to: &'static str) -> LuaResult<Map> {
+        expect_map(val, to)
+    }
+
+    pub fn get_field<T: FromLua>(m: &Map, name: &str) -> LuaResult<T> {
+        convert_entry(m, Value::String(name.into()))
+    }
+
+    pub fn get_index<T: FromLua>(m: &Map, i: i64) -> LuaResult<T> {
+        convert_entry(m, Value::Integer(i))
+    }
+
+    pub fn into_multi<T: IntoLua>(val: T) -> LuaResult<Vec<Value>> {
+        into_single(val)
+    }
+
+    pub fn from_multi<T: FromLua>(vals: Vec<Value>) -> LuaResult<T> {
+        from_single(vals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::error::LuaError;
+    use crate::value::Value;
+    use crate::value_conv::{FromLua, IntoLua};
+
+    #[test]
+    fn test_integer_range() {
+        assert_eq!(u8::from_lua(Value::Integer(255)).unwrap(), 255);
+        assert_eq!(i32::from_lua(Value::Float(3.0)).unwrap(), 3);
+        assert_eq!(
+            u64::from_lua(Value::String("0x10".into())).unwrap(),
+            16
+        );
+        assert!(matches!(
+            u8::from_lua(Value::Integer(256)),
+            Err(LuaError::FromLua {
+                from: "number",
+                to: "u8",
+                ..
+            })
+        ));
+        assert!(matches!(
+            i64::from_lua(Value::Float(1.5)),
+            Err(LuaError::FromLua { .. })
+        ));
+        assert!(matches!(u64::MAX.into_lua().unwrap(), Value::Float(_)));
+    }
+
+    #[test]
+    fn test_nested_error() {
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), vec![1, 2]);
+        m.insert("b".to_string(), vec![3]);
+        let val = m.clone().into_lua().unwrap();
+        assert_eq!(
+            HashMap::<String, Vec<i32>>::from_lua(val.clone()).unwrap(),
+            m
+        );
+        let err = HashMap::<String, (i32, String)>::from_lua(val).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("error converting Lua nil to String (field '"));
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
+use crate::gc;
 use crate::value::IntoError;
 use crate::value::IntoResult;
-use crate::value::Value;
+use crate::value::{LuaString, Value};
 
-fn float_to_integer(n: f64) -> Result<i64, IntoError> {
+pub fn float_to_integer(n: f64) -> Result<i64, IntoError> {
     match n == (n as i64) as f64 {
         true => Ok(n as i64),
         false => Err(IntoError::FloatToInteger),
@@ -42,15 +45,129 @@ pub fn fb2int(x: i32) -> i32 {
     }
 }
 
+/// whitespace characters accepted around numerals, same as C `isspace`
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0b' | '\x0c')
+}
+
+fn str_to_integer(s: &str) -> Option<i64> {
+    let (neg, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        // hexadecimal integer wraps around on overflow
+        if hex.is_empty() {
+            return None;
+        }
+        let n = hex.chars().try_fold(0i64, |n, c| {
+            c.to_digit(16)
+                .map(|d| n.wrapping_mul(16).wrapping_add(d as i64))
+        })?;
+        return Some(if neg { n.wrapping_neg() } else { n });
+    }
+
+    // decimal integer which overflows is read as float
+    if s.is_empty() {
+        return None;
+    }
+    s.chars().try_fold(0i64, |n, c| {
+        let d = c.to_digit(10)? as i64;
+        n.checked_mul(10)?.checked_add(if neg { -d } else { d })
+    })
+}
+
+fn str_to_float(s: &str) -> Option<f64> {
+    // reject 'inf' and 'nan'
+    if s.contains(['n', 'N']) {
+        return None;
+    }
+
+    let (neg, body) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let f = match body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        Some(hex) => hex_to_float(hex)?,
+        None if body.starts_with(|c: char| c.is_ascii_digit() || c == '.') => body.parse().ok()?,
+        None => return None,
+    };
+
+    Some(if neg { -f } else { f })
+}
+
+/// hexadecimal float like `A.8p1`, without prefix `0x`
+fn hex_to_float(s: &str) -> Option<f64> {
+    let (mantissa, exp) = match s.find(['p', 'P']) {
+        Some(pos) => (&s[..pos], s[pos + 1..].parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(pos) => (&mantissa[..pos], &mantissa[pos + 1..]),
+        None => (mantissa, ""),
+    };
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+
+    let mut f = 0.0;
+    for c in int.chars().chain(frac.chars()) {
+        f = f * 16.0 + c.to_digit(16)? as f64;
+    }
+    Some(f * 2f64.powi(exp - 4 * frac.len() as i32))
+}
+
+/// convert string to number like `lua_stringtonumber`
+pub fn str_to_number(s: &[u8]) -> Option<Value> {
+    // numerals are ascii, other strings are never numbers
+    let s = std::str::from_utf8(s).ok()?.trim_matches(is_space);
+    match str_to_integer(s) {
+        Some(i) => Some(Value::Integer(i)),
+        None => str_to_float(s).map(Value::Float),
+    }
+}
+
+/// format float like `LUAI_NUMFFORMAT` which is `%.14g`
+pub fn fmt_float(f: f64) -> String {
+    let mut buff = [0u8; 64];
+    let len = unsafe {
+        libc::snprintf(
+            buff.as_mut_ptr() as *mut libc::c_char,
+            buff.len(),
+            b"%.14g\0".as_ptr() as *const libc::c_char,
+            f,
+        )
+    };
+    String::from_utf8_lossy(&buff[..len as usize]).to_string()
+}
+
+/// format float like `tostring`, looks like a float even with integral value
+pub fn float_to_string(f: f64) -> String {
+    let mut s = fmt_float(f);
+    if s.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+        s.push_str(".0");
+    }
+    s
+}
+
 impl Value {
+    /// create a table holding entries of `m`
+    pub fn new_map(m: HashMap<Value, Value>) -> Value {
+        Value::Map(gc::new_table(m))
+    }
+
     pub fn into_integer(self) -> IntoResult<i64> {
         match self {
             Value::Integer(v) => Ok(v),
             Value::Float(f) => float_to_integer(f),
-            Value::String(s) => s
-                .parse::<f64>()
-                .map_err(IntoError::Float)
-                .and_then(float_to_integer),
+            Value::String(s) => match str_to_number(&s) {
+                Some(Value::Integer(i)) => Ok(i),
+                Some(Value::Float(f)) => float_to_integer(f),
+                _ => Err(IntoError::TypeUnsupported),
+            },
             _ => Err(IntoError::TypeUnsupported),
         }
     }
@@ -59,15 +176,19 @@ impl Value {
         match self {
             Value::Float(f) => Ok(f),
             Value::Integer(v) => Ok(v as f64),
-            Value::String(s) => s.parse::<f64>().map_err(IntoError::Float),
+            Value::String(s) => match str_to_number(&s) {
+                Some(Value::Integer(i)) => Ok(i as f64),
+                Some(Value::Float(f)) => Ok(f),
+                _ => Err(IntoError::TypeUnsupported),
+            },
             _ => Err(IntoError::TypeUnsupported),
         }
     }
 
-    pub fn into_string(self) -> IntoResult<String> {
+    pub fn into_string(self) -> IntoResult<LuaString> {
         match self {
-            Value::Float(f) => Ok(f.to_string()),
-            Value::Integer(i) => Ok(i.to_string()),
+            Value::Float(f) => Ok(float_to_string(f).into()),
+            Value::Integer(i) => Ok(i.to_string().into()),
             Value::String(s) => Ok(s),
             _ => Err(IntoError::TypeUnsupported),
         }
@@ -83,13 +204,13 @@ impl Value {
 
     pub fn type_name(&self) -> &'static str {
         match self {
-            Value::Nil => "Nil",
-            Value::Integer(_) => "Integer",
-            Value::Float(_) => "Float",
-            Value::String(_) => "String",
-            Value::Bool(_) => "Boolean",
-            Value::Map(_) => "Map",
-            Value::Function(_) => "Function",
+            Value::Nil => "nil",
+            Value::Integer(_) | Value::Float(_) => "number",
+            Value::String(_) => "string",
+            Value::Bool(_) => "boolean",
+            Value::Map(_) => "table",
+            Value::Function(_) => "function",
+            Value::File(_) | Value::UserData(_) => "userdata",
         }
     }
 }
@@ -1,5 +1,6 @@
 use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Not, Rem, Shl, Shr, Sub};
 
+use crate::error::{LuaError, LuaResult};
 use crate::value::IntoError;
 use crate::value::IntoResult;
 use crate::value::Value;
@@ -63,7 +64,7 @@ impl_opb!(BitXor, bitxor, ^);
 impl_opb!(Shl, shl, <<);
 impl_opb!(Shr, shr, >>);
 
-impl<'m> Neg for Value {
+impl Neg for Value {
     type Output = IntoResult<Value>;
 
     fn neg(self) -> Self::Output {
@@ -75,7 +76,7 @@ impl<'m> Neg for Value {
     }
 }
 
-impl<'m> Not for Value {
+impl Not for Value {
     type Output = IntoResult<Value>;
 
     fn not(self) -> Self::Output {
@@ -83,3 +84,165 @@ impl<'m> Not for Value {
         Ok(Value::Integer(!v1))
     }
 }
+
+/// arithmetic and bitwise operators, same order as `LUA_OPADD` ...
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Unm,
+    BNot,
+}
+
+impl ArithOp {
+    /// name of the metamethod of this operator
+    pub fn event(self) -> &'static str {
+        match self {
+            ArithOp::Add => "__add",
+            ArithOp::Sub => "__sub",
+            ArithOp::Mul => "__mul",
+            ArithOp::Mod => "__mod",
+            ArithOp::Pow => "__pow",
+            ArithOp::Div => "__div",
+            ArithOp::IDiv => "__idiv",
+            ArithOp::BAnd => "__band",
+            ArithOp::BOr => "__bor",
+            ArithOp::BXor => "__bxor",
+            ArithOp::Shl => "__shl",
+            ArithOp::Shr => "__shr",
+            ArithOp::Unm => "__unm",
+            ArithOp::BNot => "__bnot",
+        }
+    }
+
+    /// whether the operator takes only one operand
+    pub fn is_unary(self) -> bool {
+        matches!(self, ArithOp::Unm | ArithOp::BNot)
+    }
+
+    fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            ArithOp::BAnd
+                | ArithOp::BOr
+                | ArithOp::BXor
+                | ArithOp::Shl
+                | ArithOp::Shr
+                | ArithOp::BNot
+        )
+    }
+}
+
+/// `a << n` of lua, shift right if `n` is negative
+fn shift_left(a: i64, n: i64) -> i64 {
+    match n {
+        n if n <= -64 || n >= 64 => 0,
+        n if n >= 0 => ((a as u64) << n) as i64,
+        n => ((a as u64) >> -n) as i64,
+    }
+}
+
+fn integer_arith(op: ArithOp, a: i64, b: i64) -> LuaResult<i64> {
+    Ok(match op {
+        ArithOp::Add => a.wrapping_add(b),
+        ArithOp::Sub => a.wrapping_sub(b),
+        ArithOp::Mul => a.wrapping_mul(b),
+        ArithOp::Mod => match b {
+            0 => return Err(LuaError::new("attempt to perform 'n%0'")),
+            -1 => 0,
+            b => {
+                let m = a % b;
+                if m != 0 && (m ^ b) < 0 {
+                    m + b
+                } else {
+                    m
+                }
+            }
+        },
+        ArithOp::IDiv => match b {
+            0 => return Err(LuaError::new("attempt to divide by zero")),
+            -1 => a.wrapping_neg(),
+            b => {
+                let q = a / b;
+                if a % b != 0 && (a ^ b) < 0 {
+                    q - 1
+                } else {
+                    q
+                }
+            }
+        },
+        ArithOp::BAnd => a & b,
+        ArithOp::BOr => a | b,
+        ArithOp::BXor => a ^ b,
+        ArithOp::Shl => shift_left(a, b),
+        ArithOp::Shr => shift_left(a, b.wrapping_neg()),
+        ArithOp::Unm => a.wrapping_neg(),
+        ArithOp::BNot => !a,
+        ArithOp::Pow | ArithOp::Div => unreachable!(),
+    })
+}
+
+fn float_arith(op: ArithOp, a: f64, b: f64) -> f64 {
+    match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::Mod => {
+            let m = a % b;
+            if m != 0.0 && (m < 0.0) != (b < 0.0) {
+                m + b
+            } else {
+                m
+            }
+        }
+        ArithOp::Pow => a.powf(b),
+        ArithOp::Div => a / b,
+        ArithOp::IDiv => (a / b).floor(),
+        ArithOp::Unm => -a,
+        _ => unreachable!(),
+    }
+}
+
+/// `a op b` without metamethods, strings are converted to numbers,
+/// `None` if the operands do not support the operator
+pub(in crate) fn arith_raw(op: ArithOp, a: &Value, b: &Value) -> Option<LuaResult<Value>> {
+    if op.is_bitwise() {
+        let a = a.clone().into_integer().ok()?;
+        let b = b.clone().into_integer().ok()?;
+        return Some(integer_arith(op, a, b).map(Value::Integer));
+    }
+    match (a, b) {
+        (&Value::Integer(a), &Value::Integer(b)) if op != ArithOp::Pow && op != ArithOp::Div => {
+            Some(integer_arith(op, a, b).map(Value::Integer))
+        }
+        _ => {
+            let a = a.clone().into_float().ok()?;
+            let b = b.clone().into_float().ok()?;
+            Some(Ok(Value::Float(float_arith(op, a, b))))
+        }
+    }
+}
+
+/// error of `a op b` which has neither raw result nor metamethod
+pub(in crate) fn arith_error(op: ArithOp, a: &Value, b: &Value) -> LuaError {
+    let is_number = |v: &Value| v.clone().into_float().is_ok();
+    if op.is_bitwise() && is_number(a) && is_number(b) {
+        return LuaError::new("number has no integer representation");
+    }
+    let bad = if is_number(a) { b } else { a };
+    let action = match op.is_bitwise() {
+        true => "perform bitwise operation on",
+        false => "perform arithmetic on",
+    };
+    LuaError::new(format!("attempt to {} a {} value", action, bad.type_name()))
+}
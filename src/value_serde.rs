@@ -0,0 +1,783 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde::Deserialize;
+
+use crate::error::{LuaError, LuaResult};
+use crate::gc;
+use crate::state_map::{map_len, map_raw_set};
+use crate::value::{Map, Table, Value};
+use crate::value_impl::float_to_integer;
+use crate::State;
+
+impl ser::Error for LuaError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        LuaError::new(msg.to_string())
+    }
+}
+
+impl de::Error for LuaError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        LuaError::new(msg.to_string())
+    }
+}
+
+fn new_map(n: usize) -> Map {
+    gc::new_table(HashMap::with_capacity(n))
+}
+
+/// length of `m` if its keys are exactly `1..=n`
+fn sequence_len(m: &Map) -> Option<usize> {
+    let m = m.borrow();
+    let n = m.len();
+    let is_index = |k: Value| matches!(k, Value::Integer(i) if i >= 1 && i as usize <= n);
+    match n > 0 && m.keys().all(is_index) {
+        true => Some(n),
+        false => None,
+    }
+}
+
+/// tables being serialized from the root to the current one
+type Visiting = RefCell<Vec<*const RefCell<Table>>>;
+
+/// serialize `value` with tables on the path, recursive tables are errors
+struct SerializeValue<'a> {
+    value: &'a Value,
+    visiting: &'a Visiting,
+}
+
+impl<'a> SerializeValue<'a> {
+    fn with(&self, value: &'a Value) -> Self {
+        SerializeValue {
+            value,
+            visiting: self.visiting,
+        }
+    }
+}
+
+impl Serialize for SerializeValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let m = match self.value {
+            Value::Nil => return serializer.serialize_unit(),
+            Value::Bool(b) => return serializer.serialize_bool(*b),
+            Value::Integer(i) => return serializer.serialize_i64(*i),
+            Value::Float(f) => return serializer.serialize_f64(*f),
+            // strings which are not utf-8 are kept as bytes
+            Value::String(s) => {
+                return match s.to_str() {
+                    Ok(s) => serializer.serialize_str(s),
+                    Err(_) => serializer.serialize_bytes(s),
+                }
+            }
+            Value::Map(m) => m,
+            v => {
+                let msg = format!("cannot serialize a {} value", v.type_name());
+                return Err(ser::Error::custom(msg));
+            }
+        };
+
+        if self.visiting.borrow().contains(&Rc::as_ptr(m)) {
+            return Err(ser::Error::custom("cannot serialize a recursive table"));
+        }
+        self.visiting.borrow_mut().push(Rc::as_ptr(m));
+        let res = serialize_map(self, m, serializer);
+        self.visiting.borrow_mut().pop();
+        res
+    }
+}
+
+/// sequences become arrays, other tables including empty ones become maps
+fn serialize_map<S: Serializer>(
+    this: &SerializeValue,
+    m: &Map,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    // clone entries so that the table is not borrowed while serializing
+    if let Some(n) = sequence_len(m) {
+        let items: Vec<Value> = {
+            let m = m.borrow();
+            (1..=n as i64)
+                .map(|i| m[&Value::Integer(i)].clone())
+                .collect()
+        };
+        let mut seq = serializer.serialize_seq(Some(n))?;
+        for item in items.iter() {
+            seq.serialize_element(&this.with(item))?;
+        }
+        return seq.end();
+    }
+
+    let entries: Vec<(Value, Value)> = m
+        .borrow()
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let mut map = serializer.serialize_map(Some(entries.len()))?;
+    for (k, v) in entries.iter() {
+        map.serialize_entry(&this.with(k), &this.with(v))?;
+    }
+    map.end()
+}
+
+/// `nil` is unit, tables with keys `1..=n` are sequences, other tables are maps,
+/// functions, files and userdata can not be serialized
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let visiting = RefCell::new(vec![]);
+        SerializeValue {
+            value: self,
+            visiting: &visiting,
+        }
+        .serialize(serializer)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a lua value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    /// integers out of `i64` range are kept as float
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(match i64::try_from(v) {
+            Ok(i) => Value::Integer(i),
+            Err(_) => Value::Float(v as f64),
+        })
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        Deserialize::deserialize(d)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        Deserialize::deserialize(d)
+    }
+
+    /// `nil` elements leave holes in the array
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let m = new_map(seq.size_hint().unwrap_or(0));
+        let mut i = 0;
+        while let Some(v) = seq.next_element::<Value>()? {
+            i += 1;
+            map_raw_set(&m, Value::Integer(i), v).map_err(de::Error::custom)?;
+        }
+        Ok(Value::Map(m))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let m = new_map(map.size_hint().unwrap_or(0));
+        while let Some((k, v)) = map.next_entry::<Value, Value>()? {
+            map_raw_set(&m, k, v).map_err(de::Error::custom)?;
+        }
+        Ok(Value::Map(m))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        d.deserialize_any(ValueVisitor)
+    }
+}
+
+/// build lua values from rust values
+struct ValueSerializer;
+
+struct SerializeArray {
+    m: Map,
+    len: i64,
+}
+
+struct SerializeTable {
+    m: Map,
+    key: Option<Value>,
+}
+
+/// `{ variant = value }` for enum variants with data
+struct SerializeVariant<T> {
+    name: &'static str,
+    inner: T,
+}
+
+fn variant(name: &'static str, val: Value) -> LuaResult<Value> {
+    let m = new_map(1);
+    map_raw_set(&m, Value::String(name.into()), val)?;
+    Ok(Value::Map(m))
+}
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = LuaError;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeTable;
+    type SerializeStruct = SerializeTable;
+    type SerializeStructVariant = SerializeVariant<SerializeTable>;
+
+    fn serialize_bool(self, v: bool) -> LuaResult<Value> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> LuaResult<Value> {
+        Ok(Value::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> LuaResult<Value> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> LuaResult<Value> {
+        ValueVisitor.visit_u64::<LuaError>(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> LuaResult<Value> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> LuaResult<Value> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> LuaResult<Value> {
+        Ok(Value::String(v.to_string().into()))
+    }
+
+    fn serialize_str(self, v: &str) -> LuaResult<Value> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> LuaResult<Value> {
+        Ok(Value::String(v.into()))
+    }
+
+    fn serialize_none(self) -> LuaResult<Value> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> LuaResult<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> LuaResult<Value> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> LuaResult<Value> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> LuaResult<Value> {
+        Ok(Value::String(variant.into()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> LuaResult<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        value: &T,
+    ) -> LuaResult<Value> {
+        variant(name, value.serialize(self)?)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> LuaResult<SerializeArray> {
+        Ok(SerializeArray {
+            m: new_map(len.unwrap_or(0)),
+            len: 0,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> LuaResult<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> LuaResult<SerializeArray> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        len: usize,
+    ) -> LuaResult<Self::SerializeTupleVariant> {
+        let inner = self.serialize_seq(Some(len))?;
+        Ok(SerializeVariant { name, inner })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> LuaResult<SerializeTable> {
+        Ok(SerializeTable {
+            m: new_map(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> LuaResult<SerializeTable> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        name: &'static str,
+        len: usize,
+    ) -> LuaResult<Self::SerializeStructVariant> {
+        let inner = self.serialize_map(Some(len))?;
+        Ok(SerializeVariant { name, inner })
+    }
+}
+
+impl SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> LuaResult<()> {
+        self.len += 1;
+        map_raw_set(
+            &self.m,
+            Value::Integer(self.len),
+            value.serialize(ValueSerializer)?,
+        )
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        Ok(Value::Map(self.m))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> LuaResult<()> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> LuaResult<()> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> LuaResult<()> {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        variant(self.name, SerializeSeq::end(self.inner)?)
+    }
+}
+
+impl SerializeMap for SerializeTable {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> LuaResult<()> {
+        self.key = Some(key.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> LuaResult<()> {
+        let key = self.key.take().unwrap_or(Value::Nil);
+        map_raw_set(&self.m, key, value.serialize(ValueSerializer)?)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        Ok(Value::Map(self.m))
+    }
+}
+
+impl ser::SerializeStruct for SerializeTable {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeTable> {
+    type Ok = Value;
+    type Error = LuaError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        SerializeMap::serialize_entry(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> LuaResult<Value> {
+        variant(self.name, SerializeMap::end(self.inner)?)
+    }
+}
+
+/// read rust values from lua value, `path` holds the enclosing tables
+struct ValueDeserializer {
+    value: Value,
+    path: Vec<*const RefCell<Table>>,
+}
+
+impl ValueDeserializer {
+    fn new(value: Value) -> Self {
+        ValueDeserializer {
+            value,
+            path: vec![],
+        }
+    }
+
+    /// deserializer of `value` which is inside table `m`
+    fn child(&self, m: &Map, value: Value) -> LuaResult<Self> {
+        let mut path = self.path.clone();
+        path.push(Rc::as_ptr(m));
+        if let Value::Map(m) = &value {
+            if path.contains(&Rc::as_ptr(m)) {
+                return Err(LuaError::new("cannot deserialize a recursive table"));
+            }
+        }
+        Ok(ValueDeserializer { value, path })
+    }
+
+    fn unsupported(&self) -> LuaError {
+        let msg = format!("cannot deserialize a {} value", self.value.type_name());
+        LuaError::new(msg)
+    }
+
+    fn deserialize_array<'de, V: Visitor<'de>>(self, m: Map, visitor: V) -> LuaResult<V::Value> {
+        let items: Vec<Value> = {
+            let len = map_len(&m);
+            let m = m.borrow();
+            (1..=len)
+                .map(|i| m.get(&Value::Integer(i)).cloned().unwrap_or(Value::Nil))
+                .collect()
+        };
+        let items = items
+            .into_iter()
+            .map(|v| self.child(&m, v))
+            .collect::<LuaResult<Vec<_>>>()?;
+        visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
+    }
+
+    fn deserialize_table<'de, V: Visitor<'de>>(self, m: Map, visitor: V) -> LuaResult<V::Value> {
+        let entries: Vec<(Value, Value)> = m
+            .borrow()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let entries = entries
+            .into_iter()
+            .map(|(k, v)| Ok((self.child(&m, k)?, self.child(&m, v)?)))
+            .collect::<LuaResult<Vec<_>>>()?;
+        visitor.visit_map(de::value::MapDeserializer::new(entries.into_iter()))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, LuaError> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// deserialize integer types, floats with integral value are accepted
+macro_rules! deserialize_integer {
+    ($($method:ident),*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+            match self.value {
+                Value::Float(f) => match float_to_integer(f) {
+                    Ok(i) => visitor.visit_i64(i),
+                    Err(_) => visitor.visit_f64(f),
+                },
+                _ => self.deserialize_any(visitor),
+            }
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = LuaError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        match self.value.clone() {
+            Value::Nil => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => match s.into_string() {
+                Ok(s) => visitor.visit_string(s),
+                Err(e) => visitor.visit_byte_buf(e.into_bytes()),
+            },
+            Value::Map(m) => match sequence_len(&m) {
+                Some(_) => self.deserialize_array(m, visitor),
+                None => self.deserialize_table(m, visitor),
+            },
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    deserialize_integer!(
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        match self.value {
+            Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// elements from `1` to the border `#t`, empty table is an empty sequence
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        match self.value.clone() {
+            Value::Map(m) => self.deserialize_array(m, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        match self.value.clone() {
+            Value::Map(m) => self.deserialize_table(m, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    /// variant name as string, or `{ variant = value }` for variants with data
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        let (name, value) = match self.value.clone() {
+            Value::String(s) => (s, None),
+            Value::Map(m) if m.borrow().len() == 1 => {
+                let (k, v) = m
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .next()
+                    .unwrap();
+                match k {
+                    Value::String(s) => (s, Some(self.child(&m, v)?)),
+                    _ => return Err(LuaError::new("enum variant name must be a string")),
+                }
+            }
+            _ => return Err(LuaError::new("expected string or table with single key")),
+        };
+        let name = name.to_string();
+        visitor.visit_enum(EnumDeserializer { name, value })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct identifier ignored_any
+        i128 u128
+    }
+}
+
+struct EnumDeserializer {
+    name: String,
+    value: Option<ValueDeserializer>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = LuaError;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> LuaResult<(V::Value, VariantDeserializer)> {
+        let name = seed.deserialize(de::value::StringDeserializer::<LuaError>::new(self.name))?;
+        Ok((name, VariantDeserializer(self.value)))
+    }
+}
+
+struct VariantDeserializer(Option<ValueDeserializer>);
+
+impl VariantDeserializer {
+    fn value(self) -> LuaResult<ValueDeserializer> {
+        self.0
+            .ok_or_else(|| LuaError::new("expected enum variant with data"))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = LuaError;
+
+    /// value of unit variant in table is ignored
+    fn unit_variant(self) -> LuaResult<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> LuaResult<T::Value> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> LuaResult<V::Value> {
+        self.value()?.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.value()?.deserialize_map(visitor)
+    }
+}
+
+impl State {
+    /// convert rust value into lua value with serde, see `Value` for the layout
+    pub fn to_value<T: ?Sized + Serialize>(&self, value: &T) -> LuaResult<Value> {
+        value.serialize(ValueSerializer)
+    }
+
+    /// convert lua value into rust value with serde
+    pub fn from_value<T: DeserializeOwned>(&self, value: Value) -> LuaResult<T> {
+        T::deserialize(ValueDeserializer::new(value))
+    }
+}
@@ -0,0 +1,98 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+/// string of lua, a sequence of bytes which is not necessarily utf-8
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LuaString(Vec<u8>);
+
+impl LuaString {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// bytes allocated for the string
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// the string as `str` if it is valid utf-8
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.0)
+    }
+
+    /// the string with invalid utf-8 sequences replaced by `U+FFFD`, only for display
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    pub fn into_string(self) -> Result<String, FromUtf8Error> {
+        String::from_utf8(self.0)
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes)
+    }
+}
+
+impl Deref for LuaString {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for LuaString {
+    fn from(v: Vec<u8>) -> Self {
+        LuaString(v)
+    }
+}
+
+impl From<&[u8]> for LuaString {
+    fn from(v: &[u8]) -> Self {
+        LuaString(v.to_vec())
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(s: String) -> Self {
+        LuaString(s.into_bytes())
+    }
+}
+
+impl From<&str> for LuaString {
+    fn from(s: &str) -> Self {
+        LuaString(s.as_bytes().to_vec())
+    }
+}
+
+impl PartialEq<str> for LuaString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for LuaString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl fmt::Display for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_lossy())
+    }
+}
+
+impl fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string_lossy())
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Index;
+
+use crate::error::{LuaError, LuaResult};
+use crate::gc::{self, Finalizer};
+use crate::value::{Map, Value};
+use crate::value_cmp::key_order;
+use crate::value_impl::float_to_integer;
+
+/// number of slices of integer keys counted by rehash, the `i`th one
+/// is `(2^(i-1), 2^i]`
+const MAX_BITS: usize = 64;
+
+/// entries of a table and its metatable, keys `1..=n` are kept in the
+/// array part and the others in the hash part like Lua
+#[derive(Default)]
+pub struct Table {
+    /// values of keys `1..=array.len()`, `nil` for absent ones
+    pub(in crate) array: Vec<Value>,
+    /// entries of the hash part in the order they are added, removed ones
+    /// keep their key with a `nil` value until rehash so traversals go on
+    nodes: Vec<(Value, Value)>,
+    /// position of keys in `nodes`
+    index: HashMap<Value, usize>,
+    pub meta: Option<Map>,
+    /// keys sorted for traversal in deterministic mode, dropped when a key is added
+    pub(in crate) sorted: Option<Vec<Value>>,
+    /// pending `__gc`, set by `setmetatable`
+    pub(in crate) gc: Option<Finalizer>,
+    /// entries and metatable can not be changed by scripts
+    pub(in crate) frozen: bool,
+    /// creation order, see `gc::next_seq`
+    pub(in crate) seq: u64,
+}
+
+/// integer value of key, floats with integral value included
+fn key_integer(key: &Value) -> Option<i64> {
+    match key {
+        Value::Integer(i) => Some(*i),
+        Value::Float(f) => float_to_integer(*f).ok(),
+        _ => None,
+    }
+}
+
+/// slice of rehash counting positive integer `i`, `ceil(log2(i))`
+fn slice_of(i: u64) -> usize {
+    (u64::BITS - (i - 1).leading_zeros()) as usize
+}
+
+impl Table {
+    pub fn new(map: HashMap<Value, Value>) -> Self {
+        let mut t = Table::with_capacity(0, map.len());
+        for (k, v) in map {
+            t.insert_node(k, v);
+        }
+        if !t.nodes.is_empty() {
+            t.rehash(None);
+        }
+        t
+    }
+
+    /// table with room for `narr` array items and `nrec` other entries
+    pub fn with_capacity(narr: usize, nrec: usize) -> Self {
+        Table {
+            array: vec![Value::Nil; narr],
+            nodes: Vec::with_capacity(nrec),
+            index: HashMap::with_capacity(nrec),
+            meta: None,
+            sorted: None,
+            gc: None,
+            frozen: false,
+            seq: gc::next_seq(),
+        }
+    }
+
+    /// index of key in the array part
+    fn array_index(&self, key: &Value) -> Option<usize> {
+        let i = key_integer(key)?;
+        match i >= 1 && (i as u64) <= self.array.len() as u64 {
+            true => Some(i as usize - 1),
+            false => None,
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        match self.array_index(key) {
+            Some(i) => match &self.array[i] {
+                Value::Nil => None,
+                v => Some(v),
+            },
+            None => match self.index.get(key) {
+                Some(&i) if !matches!(self.nodes[i].1, Value::Nil) => Some(&self.nodes[i].1),
+                _ => None,
+            },
+        }
+    }
+
+    /// set entry of the hash part, the node of a removed key is used again
+    fn insert_node(&mut self, key: Value, val: Value) -> Option<Value> {
+        let old = match self.index.get(&key) {
+            Some(&i) => mem::replace(&mut self.nodes[i].1, val),
+            None => {
+                self.index.insert(key.clone(), self.nodes.len());
+                self.nodes.push((key, val));
+                Value::Nil
+            }
+        };
+        match old {
+            Value::Nil => {
+                self.sorted = None;
+                None
+            }
+            old => Some(old),
+        }
+    }
+
+    /// remove entry of the hash part, its key is kept for traversals
+    fn remove_node(&mut self, key: &Value) -> Option<Value> {
+        let i = *self.index.get(key)?;
+        match mem::replace(&mut self.nodes[i].1, Value::Nil) {
+            Value::Nil => None,
+            old => Some(old),
+        }
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// set `key` to `val`, `nil` removes the entry. keys are not checked,
+    /// see `map_raw_set`
+    pub fn insert(&mut self, key: Value, val: Value) -> Option<Value> {
+        if let Value::Nil = val {
+            return self.remove(&key);
+        }
+        if let Some(i) = self.array_index(&key) {
+            return match mem::replace(&mut self.array[i], val) {
+                Value::Nil => {
+                    self.sorted = None;
+                    None
+                }
+                old => Some(old),
+            };
+        }
+        let next = self.array.len() as i64 + 1;
+        if key_integer(&key) == Some(next) {
+            // the key is left in the hash part when the array part shrinks
+            let old = self.remove_node(&Value::Integer(next));
+            if old.is_none() {
+                self.sorted = None;
+            }
+            self.push(val);
+            return old;
+        }
+        if self.index.contains_key(&key) {
+            return self.insert_node(key, val);
+        }
+
+        // new key and the hash part is full
+        if self.nodes.len() == self.nodes.capacity() {
+            self.rehash(Some(&key));
+            if let Some(i) = self.array_index(&key) {
+                self.array[i] = val;
+                self.sorted = None;
+                return None;
+            }
+        }
+        self.insert_node(key, val)
+    }
+
+    /// append to the array part, following keys in the hash part are moved too
+    fn push(&mut self, val: Value) {
+        self.array.push(val);
+        while !self.index.is_empty() {
+            let next = Value::Integer(self.array.len() as i64 + 1);
+            match self.remove_node(&next) {
+                Some(v) => self.array.push(v),
+                None => break,
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        match self.array_index(key) {
+            // the array part is kept for traversals, it shrinks by rehash
+            Some(i) => match mem::replace(&mut self.array[i], Value::Nil) {
+                Value::Nil => None,
+                old => Some(old),
+            },
+            None => self.remove_node(key),
+        }
+    }
+
+    /// number of entries
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// number of entries the table can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.array.capacity() + self.nodes.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.array.clear();
+        self.nodes.clear();
+        self.index.clear();
+        self.sorted = None;
+    }
+
+    /// entries of the array part then those of the hash part
+    pub fn iter(&self) -> impl Iterator<Item = (Value, &Value)> {
+        let array = self.array.iter().enumerate();
+        let array = array.map(|(i, v)| (Value::Integer(i as i64 + 1), v));
+        let nodes = self.nodes.iter().map(|(k, v)| (k.clone(), v));
+        array.chain(nodes).filter(|(_, v)| !matches!(v, Value::Nil))
+    }
+
+    /// keys of removed entries which are kept for traversals
+    pub(in crate) fn dead_keys(&self) -> impl Iterator<Item = &Value> {
+        let nodes = self.nodes.iter().filter(|(_, v)| matches!(v, Value::Nil));
+        nodes.map(|(k, _)| k).filter(|k| !matches!(k, Value::Nil))
+    }
+
+    /// forget keys of removed entries for which `f` returns `true`, return them
+    pub(in crate) fn remove_dead_keys<F: FnMut(&Value) -> bool>(&mut self, mut f: F) -> Vec<Value> {
+        let mut removed = vec![];
+        for (k, v) in self.nodes.iter_mut() {
+            if matches!(v, Value::Nil) && !matches!(k, Value::Nil) && f(k) {
+                self.index.remove(k);
+                removed.push(mem::replace(k, Value::Nil));
+            }
+        }
+        removed
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Value> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// remove all entries and return them
+    pub fn drain(&mut self) -> impl Iterator<Item = (Value, Value)> {
+        let array = mem::take(&mut self.array).into_iter().enumerate();
+        let array = array.map(|(i, v)| (Value::Integer(i as i64 + 1), v));
+        self.index.clear();
+        self.sorted = None;
+        let nodes = mem::take(&mut self.nodes);
+        array.chain(nodes).filter(|(_, v)| !matches!(v, Value::Nil))
+    }
+
+    /// keep entries for which `f` returns `true`
+    pub fn retain<F: FnMut(&Value, &mut Value) -> bool>(&mut self, mut f: F) {
+        for (i, v) in self.array.iter_mut().enumerate() {
+            if !matches!(v, Value::Nil) && !f(&Value::Integer(i as i64 + 1), v) {
+                *v = Value::Nil;
+            }
+        }
+        for (k, v) in self.nodes.iter_mut() {
+            if !matches!(v, Value::Nil) && !f(k, v) {
+                *v = Value::Nil;
+            }
+        }
+    }
+
+    /// resize the array part to the largest `n` such that more than half
+    /// of keys `1..=n` are present, counting `extra` key to be inserted
+    fn rehash(&mut self, extra: Option<&Value>) {
+        let mut nums = [0usize; MAX_BITS + 1];
+        let mut total = 0;
+        let mut count = |i: i64| {
+            if i >= 1 {
+                nums[slice_of(i as u64)] += 1;
+                total += 1;
+            }
+        };
+        for (i, v) in self.array.iter().enumerate() {
+            if !matches!(v, Value::Nil) {
+                count(i as i64 + 1);
+            }
+        }
+        self.nodes
+            .iter()
+            .filter(|(_, v)| !matches!(v, Value::Nil))
+            .filter_map(|(k, _)| key_integer(k))
+            .for_each(&mut count);
+        extra.and_then(key_integer).into_iter().for_each(&mut count);
+
+        // same as `computesizes` of `ltable.c`
+        let (mut size, mut a) = (0, 0);
+        let mut twotoi: usize = 1;
+        for n in nums.iter() {
+            if twotoi == 0 || total <= twotoi / 2 {
+                break;
+            }
+            a += n;
+            if a > twotoi / 2 {
+                size = twotoi;
+            }
+            twotoi = twotoi.wrapping_mul(2);
+        }
+        self.resize_array(size);
+        self.compact();
+    }
+
+    /// drop nodes of removed entries, traversals of the table are ended
+    fn compact(&mut self) {
+        let n = self.nodes.len();
+        let nodes = mem::replace(&mut self.nodes, Vec::with_capacity(n));
+        self.index.clear();
+        for (k, v) in nodes {
+            if !matches!(v, Value::Nil) {
+                self.index.insert(k.clone(), self.nodes.len());
+                self.nodes.push((k, v));
+            }
+        }
+    }
+
+    /// grow the array part to hold keys `1..=n`, used by `SETLIST`
+    pub(in crate) fn reserve_array(&mut self, n: usize) {
+        if n > self.array.len() {
+            self.resize_array(n);
+        }
+    }
+
+    fn resize_array(&mut self, size: usize) {
+        if size < self.array.len() {
+            let rest = self.array.split_off(size);
+            for (i, v) in rest.into_iter().enumerate() {
+                if !matches!(v, Value::Nil) {
+                    self.insert_node(Value::Integer((size + i) as i64 + 1), v);
+                }
+            }
+        } else if size > self.array.len() {
+            let start = self.array.len();
+            self.array.resize(size, Value::Nil);
+            if !self.index.is_empty() {
+                for i in start..size {
+                    if let Some(v) = self.remove_node(&Value::Integer(i as i64 + 1)) {
+                        self.array[i] = v;
+                    }
+                }
+            }
+        }
+    }
+
+    /// a border of the table, `t[n]` is present and `t[n + 1]` is absent,
+    /// 0 if `t[1]` is absent. same as `luaH_getn`
+    pub fn border(&self) -> usize {
+        let n = self.array.len();
+        if n > 0 && matches!(self.array[n - 1], Value::Nil) {
+            // binary search in the array part
+            let (mut i, mut j) = (0, n);
+            while j - i > 1 {
+                let m = (i + j) / 2;
+                match self.array[m - 1] {
+                    Value::Nil => j = m,
+                    _ => i = m,
+                }
+            }
+            return i;
+        }
+        if self.index.is_empty() {
+            return n;
+        }
+        self.unbound_search(n as u64)
+    }
+
+    /// border beyond the array part of size `j`
+    fn unbound_search(&self, mut j: u64) -> usize {
+        let present = |i: u64| self.contains_key(&Value::Integer(i as i64));
+        let mut i = j;
+        j += 1;
+        while present(j) {
+            i = j;
+            if j > i64::MAX as u64 / 2 {
+                // resort to linear search
+                let mut n = 1;
+                while present(n + 1) {
+                    n += 1;
+                }
+                return n as usize;
+            }
+            j *= 2;
+        }
+
+        // binary search between them
+        while j - i > 1 {
+            let m = (i + j) / 2;
+            match present(m) {
+                true => i = m,
+                false => j = m,
+            }
+        }
+        i as usize
+    }
+
+    /// sort keys for traversal in deterministic mode if they are not kept
+    /// yet, return the bytes allocated for them
+    pub(in crate) fn sort_keys(&mut self) -> usize {
+        if self.sorted.is_some() {
+            return 0;
+        }
+        let mut keys: Vec<Value> = self.keys().collect();
+        keys.sort_by(key_order);
+        let size = keys.capacity() * mem::size_of::<Value>();
+        self.sorted = Some(keys);
+        size
+    }
+
+    /// entry after `key` in traversal, `nil` key starts a new one. entries
+    /// removed during traversal are skipped, keys are sorted by `sort_keys`
+    /// if `ordered`
+    pub(in crate) fn next(&self, key: &Value, ordered: bool) -> LuaResult<Option<(Value, Value)>> {
+        let invalid = || LuaError::new("invalid key to 'next'");
+        if let (true, Some(keys)) = (ordered, &self.sorted) {
+            let start = match key {
+                Value::Nil => 0,
+                key => match keys.binary_search_by(|k| key_order(k, key)) {
+                    Ok(i) => i + 1,
+                    Err(_) => keys.iter().position(|k| k == key).ok_or_else(invalid)? + 1,
+                },
+            };
+            let mut entries = keys[start..].iter();
+            return Ok(entries.find_map(|k| self.get(k).map(|v| (k.clone(), v.clone()))));
+        }
+
+        // array part then hash part by position
+        let start = match key {
+            Value::Nil => 0,
+            key => match self.array_index(key) {
+                Some(i) => i + 1,
+                None => self.array.len() + self.index.get(key).ok_or_else(invalid)? + 1,
+            },
+        };
+        let mut array = self.array.iter().enumerate().skip(start);
+        if let Some((i, v)) = array.find(|(_, v)| !matches!(v, Value::Nil)) {
+            return Ok(Some((Value::Integer(i as i64 + 1), v.clone())));
+        }
+        let n = self.array.len();
+        let mut nodes = self.nodes[start.max(n) - n..].iter();
+        Ok(nodes.find(|(_, v)| !matches!(v, Value::Nil)).cloned())
+    }
+}
+
+impl Index<&Value> for Table {
+    type Output = Value;
+
+    fn index(&self, key: &Value) -> &Value {
+        self.get(key).expect("key not found in table")
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        // entries are moved into a new table which is passed to `__gc` later
+        if let Some(f) = self.gc.take() {
+            let m = gc::new_table(HashMap::new());
+            {
+                let mut t = m.borrow_mut();
+                t.array = mem::take(&mut self.array);
+                t.nodes = mem::take(&mut self.nodes);
+                t.index = mem::take(&mut self.index);
+                t.meta = self.meta.take();
+            }
+            f.push(Value::Map(m));
+        }
+    }
+}
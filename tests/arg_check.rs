@@ -0,0 +1,71 @@
+mod arg_check {
+    use nad::{State, Value};
+
+    const WHERE: &[&str] = &["first", "last"];
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/arg_check.luac");
+        let insert = state.create_function(|state, ()| {
+            let t = state.check_table(1)?;
+            let pos = state.check_integer(2)?;
+            let at = state.check_option(3, Some("last"), WHERE)?;
+            t.borrow_mut()
+                .insert(Value::Integer(pos), Value::Integer(at as i64));
+            Ok(())
+        });
+        state.set_global("insert", insert).unwrap();
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    fn error_of(state: &mut State, name: &str) -> String {
+        state
+            .call_global::<_, ()>(name, ())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn call_site_names() {
+        let mut state = new_state();
+        let cases = [
+            (
+                "by_global",
+                "bad argument #2 to 'insert' (number expected, got nil)",
+            ),
+            (
+                "by_local",
+                "bad argument #2 to 'add' (number has no integer representation)",
+            ),
+            (
+                "by_field",
+                "bad argument #1 to 'insert' (table expected, got number)",
+            ),
+            (
+                "by_method",
+                "bad argument #1 to 'insert' (number expected, got string)",
+            ),
+            (
+                "by_option",
+                "bad argument #3 to 'insert' (invalid option 'middle')",
+            ),
+            (
+                "by_builtin",
+                "bad argument #2 to 'char' (value out of range)",
+            ),
+        ];
+        for (name, msg) in cases.iter() {
+            assert_eq!(error_of(&mut state, name), *msg);
+        }
+    }
+
+    #[test]
+    fn called_by_host() {
+        let mut state = new_state();
+        let err = state.call_global::<_, ()>("insert", ()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad argument #1 to '?' (table expected, got no value)"
+        );
+    }
+}
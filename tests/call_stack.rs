@@ -0,0 +1,68 @@
+mod call_stack {
+    use nad::{Function, State};
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/call_stack.luac");
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn upvalues() {
+        let mut state = new_state();
+        let n: i64 = state.call_global("counters", ()).unwrap();
+        assert_eq!(n, 57);
+        let n: i64 = state.call_global("escaped", ()).unwrap();
+        assert_eq!(n, 87);
+
+        // closed once `shared` returns, still shared by both closures
+        let (inc, get): (Function, Function) = state.call_global("shared", ()).unwrap();
+        inc.call::<_, ()>(&mut state, ()).unwrap();
+        assert_eq!(get.call::<_, i64>(&mut state, ()).unwrap(), 11);
+    }
+
+    #[test]
+    fn varargs() {
+        let mut state = new_state();
+        let r: (i64, Option<i64>, i64, Option<i64>, Option<i64>) =
+            state.call_global("varargs", 1).unwrap();
+        assert_eq!(r, (1, None, 0, None, None));
+        let r: (i64, Option<i64>, i64, Option<i64>, Option<i64>) =
+            state.call_global("varargs", (1, 2, 3, 4, 5)).unwrap();
+        assert_eq!(r, (1, Some(2), 3, Some(3), Some(4)));
+        let r: (i64, Option<i64>, i64, Option<i64>, Option<i64>) =
+            state.call_global("pass_varargs", (1, 2, 3)).unwrap();
+        assert_eq!(r, (1, Some(2), 1, Some(3), None));
+    }
+
+    #[test]
+    fn deep_calls() {
+        let mut state = new_state();
+        let top = state.top();
+        let n: i64 = state.call_global("depth", 100000).unwrap();
+        assert_eq!(n, 100000);
+        let n: i64 = state.call_global("sum_calls", 1000).unwrap();
+        assert_eq!(n, 500500);
+
+        let (ok, err): (bool, String) = state.call_global("overflow", ()).unwrap();
+        assert!(!ok);
+        assert!(err.ends_with("stack overflow"));
+        assert_eq!(state.top(), top);
+        let n: i64 = state.call_global("depth", 10).unwrap();
+        assert_eq!(n, 10);
+    }
+
+    #[test]
+    fn nested_host_calls() {
+        let mut state = new_state();
+        let top = state.top();
+        let err: String = state.call_global("nested_pcall", ()).unwrap();
+        assert!(err.ends_with("C stack overflow"));
+        let (ok, err): (bool, String) = state.call_global("nested_index", ()).unwrap();
+        assert!(!ok);
+        assert!(err.ends_with("C stack overflow"));
+        assert_eq!(state.top(), top);
+        let n: i64 = state.call_global("depth", 10).unwrap();
+        assert_eq!(n, 10);
+    }
+}
@@ -0,0 +1,49 @@
+mod callback {
+    use nad::{Function, LuaError, State, Value};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn new_state(handlers: Rc<RefCell<Vec<Function>>>) -> State {
+        let mut state = State::from_file("tests/bytecode/callback.luac");
+        let on = state.create_function(move |_, (_, f): (String, Function)| {
+            handlers.borrow_mut().push(f);
+            Ok(())
+        });
+        state.set_global("on", on).unwrap();
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn call_global() {
+        let mut state = new_state(Rc::default());
+        let top = state.top();
+
+        let s: String = state.call_global("greet", ("lua",)).unwrap();
+        assert_eq!(s, "hello lua");
+        let (sum, prod): (i64, i64) = state.call_global("addmul", (3, 4)).unwrap();
+        assert_eq!((sum, prod), (7, 12));
+
+        let err = state.call_global::<_, ()>("fail_with", "boom").unwrap_err();
+        assert!(matches!(err, LuaError::Runtime(Value::String(s)) if s == "boom"));
+        let err = state.call_global::<_, ()>("missing", ()).unwrap_err();
+        assert_eq!(err.to_string(), "error converting Lua nil to function");
+        assert_eq!(state.top(), top);
+    }
+
+    #[test]
+    fn keep_handles() {
+        let handlers = Rc::new(RefCell::new(vec![]));
+        let mut state = new_state(handlers.clone());
+        let counter: Function = state.get_global("counter").unwrap();
+        state.set_global("counter", Value::Nil).unwrap();
+
+        assert_eq!(counter.call::<_, i64>(&mut state, ()).unwrap(), 1);
+        let handlers = handlers.borrow().clone();
+        assert_eq!(handlers.len(), 2);
+        for f in handlers.iter() {
+            f.call::<_, ()>(&mut state, 10).unwrap();
+        }
+        assert_eq!(state.call_function::<_, i64>(&counter, ()).unwrap(), 13);
+    }
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "derive")]
+
+mod convert {
+    use nad::{FromLua, IntoLua, LuaError, State, Value};
+    use std::collections::HashMap;
+
+    #[derive(IntoLua, FromLua, Debug, PartialEq, Clone)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(IntoLua, FromLua, Debug, PartialEq, Clone)]
+    struct Shape {
+        name: String,
+        points: Vec<Point>,
+        color: Option<(u8, u8, u8)>,
+        tags: HashMap<String, bool>,
+    }
+
+    #[derive(IntoLua, FromLua, Debug, PartialEq)]
+    struct Pair<T>(T, T);
+
+    #[derive(IntoLua, FromLua, Debug, PartialEq)]
+    struct Unit;
+
+    #[test]
+    fn primitives() {
+        let mut state = State::new();
+        state.set_global("n", 42).unwrap();
+        state.set_global("s", "text").unwrap();
+        state.set_global("nothing", None::<i32>).unwrap();
+
+        assert_eq!(state.get_global::<i64>("n").unwrap(), 42);
+        assert_eq!(state.get_global::<f64>("n").unwrap(), 42.0);
+        assert_eq!(state.get_global::<String>("n").unwrap(), "42");
+        assert_eq!(state.get_global::<String>("s").unwrap(), "text");
+        assert_eq!(state.get_global::<Option<i32>>("nothing").unwrap(), None);
+        assert_eq!(state.get_global::<String>("_VERSION").unwrap(), "Lua 5.3");
+        assert!(state.get_global::<bool>("print").unwrap());
+        assert!(matches!(
+            state.get_global::<Value>("print").unwrap(),
+            Value::Function(_)
+        ));
+    }
+
+    #[test]
+    fn structs() {
+        let shape = Shape {
+            name: "triangle".to_string(),
+            points: vec![
+                Point { x: 0.0, y: 0.0 },
+                Point { x: 1.0, y: 0.0 },
+                Point { x: 0.0, y: 1.5 },
+            ],
+            color: Some((255, 0, 128)),
+            tags: vec![("closed".to_string(), true)].into_iter().collect(),
+        };
+
+        let mut state = State::new();
+        state.set_global("shape", shape.clone()).unwrap();
+        assert_eq!(state.get_global::<Shape>("shape").unwrap(), shape);
+        assert_eq!(
+            state.get_global::<Vec<Point>>("shape").unwrap(),
+            Vec::<Point>::new()
+        );
+
+        state.set_global("pair", Pair(1, 2)).unwrap();
+        assert_eq!(state.get_global::<Pair<i32>>("pair").unwrap(), Pair(1, 2));
+        assert_eq!(state.get_global::<(i32, i32)>("pair").unwrap(), (1, 2));
+
+        state.set_global("unit", Unit).unwrap();
+        assert_eq!(state.get_global::<Unit>("unit").unwrap(), Unit);
+    }
+
+    #[test]
+    fn errors() {
+        let mut state = State::new();
+        state.set_global("n", 300).unwrap();
+        state.set_global("p", Point { x: 1.0, y: 2.0 }).unwrap();
+        state.set_global("missing", vec![Some(1), None]).unwrap();
+
+        let err = state.get_global::<u8>("n").unwrap_err();
+        assert!(matches!(
+            err,
+            LuaError::FromLua {
+                from: "number",
+                to: "u8",
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "error converting Lua number to u8 (out of range)"
+        );
+
+        let err = state.get_global::<Point>("n").unwrap_err();
+        assert!(matches!(err, LuaError::FromLua { to: "Point", .. }));
+
+        let err = state.get_global::<Pair<bool>>("undefined").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error converting Lua nil to Pair (expected table)"
+        );
+
+        let err = state.get_global::<Shape>("p").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error converting Lua nil to String (field 'name')"
+        );
+
+        let err = state.get_global::<(i32, i32)>("missing").unwrap_err();
+        assert_eq!(err.to_string(), "error converting Lua nil to i32 (index 2)");
+
+        let mut keys = HashMap::new();
+        keys.insert(None::<i32>, 1);
+        assert!(state.set_global("bad", keys).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+mod deterministic {
+    use nad::{Deterministic, Options, State, Value};
+
+    fn new_state(seed: u64) -> State {
+        let options = Options {
+            deterministic: Some(Deterministic {
+                seed,
+                time: 86400,
+                clock: 1.5,
+            }),
+            ..Options::default()
+        };
+        let mut state = State::from_file("tests/bytecode/deterministic.luac").with_option(options);
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn traversal_order() {
+        let mut state = new_state(0);
+        let s: String = state.call_global("traverse", ()).unwrap();
+        // booleans, numbers, strings, then objects in creation order
+        assert_eq!(s, "t,a,f,c,1,2,A,B,F,");
+
+        let s: String = state.call_global("encoded", ()).unwrap();
+        assert_eq!(s, r#"{"a":2,"b":1,"c":{"y":2,"z":1}}"#);
+    }
+
+    #[test]
+    fn random_seed() {
+        let a: String = new_state(42).call_global("rolls", 10).unwrap();
+        let b: String = new_state(42).call_global("rolls", 10).unwrap();
+        let c: String = new_state(7).call_global("rolls", 10).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        let mut state = new_state(42);
+        let err = state.call_global::<_, i64>("roll", (3, 1)).unwrap_err();
+        assert!(err.to_string().ends_with("interval is empty)"));
+        let n: i64 = state.call_global("roll", (i64::MIN, i64::MAX)).unwrap();
+        assert_ne!(n, 0);
+
+        // integer with all bits random
+        let a: Value = new_state(42).call_global("roll", 0).unwrap();
+        let b: Value = new_state(42).call_global("roll", 0).unwrap();
+        assert!(matches!(a, Value::Integer(_)));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn virtual_clock() {
+        let mut state = new_state(0);
+        let (time, clock, date): (i64, f64, String) = state.call_global("now", ()).unwrap();
+        assert_eq!((time, clock), (86400, 1.5));
+        assert_eq!(date, "1970-01-02 00:00:00");
+
+        state.set_clock(86400 * 2 + 60, 3.0);
+        let (time, clock, date): (i64, f64, String) = state.call_global("now", ()).unwrap();
+        assert_eq!((time, clock), (86400 * 2 + 60, 3.0));
+        assert_eq!(date, "1970-01-03 00:01:00");
+
+        // real clock by default
+        let mut state = State::from_file("tests/bytecode/deterministic.luac");
+        state.call(0, 0).unwrap();
+        let (time, _, _): (i64, f64, String) = state.call_global("now", ()).unwrap();
+        assert!(time > 86400 * 365);
+    }
+}
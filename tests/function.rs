@@ -0,0 +1,150 @@
+mod function {
+    use nad::{FromLua, IntoLua, LuaError, LuaResult, State, Value, Variadic};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// converted by hand, the derive macros are optional
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    impl IntoLua for Point {
+        fn into_lua(self) -> LuaResult<Value> {
+            let fields: HashMap<&str, f64> = vec![("x", self.x), ("y", self.y)].into_iter().collect();
+            fields.into_lua()
+        }
+    }
+
+    impl FromLua for Point {
+        fn from_lua(val: Value) -> LuaResult<Self> {
+            let fields = HashMap::<String, f64>::from_lua(val)?;
+            let field = |k: &str| fields.get(k).copied().unwrap_or_default();
+            Ok(Point {
+                x: field("x"),
+                y: field("y"),
+            })
+        }
+    }
+
+    /// call global function `name` with `args` and collect all results
+    fn call(state: &mut State, name: &str, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        let f: Value = state.get_global(name)?;
+        let base = state.top();
+        assert!(state.check_stack(args.len() + 1));
+        state.push_value(f);
+        let narg = args.len();
+        args.into_iter().for_each(|v| state.push_value(v));
+        state.call(narg, -1)?;
+        let mut rets = (base..state.top())
+            .map(|_| state.pop_value())
+            .collect::<Vec<_>>();
+        rets.reverse();
+        Ok(rets)
+    }
+
+    fn register(state: &mut State, logs: Rc<Cell<usize>>) {
+        let add = state.create_function(|_, (a, b): (f64, f64)| Ok(a + b));
+        let split = state.create_function(|_, s: String| {
+            let mut parts = s.split(',');
+            Ok((parts.next().map(str::to_string), s.split(',').count()))
+        });
+        let mut n = 0;
+        let count = state.create_function_mut(move |_, ()| {
+            n += 1;
+            Ok(n)
+        });
+        let sum = state.create_function(|_, nums: Variadic<i64>| Ok(nums.iter().sum::<i64>()));
+        let point = state.create_function(|_, (x, y)| Point { x, y }.into_lua());
+        let norm = state.create_function(|_, p: Value| {
+            let p = Point::from_lua(p)?;
+            Ok((p.x * p.x + p.y * p.y).sqrt())
+        });
+        let log = state.create_function(move |_, _: String| {
+            logs.set(logs.get() + 1);
+            Ok(())
+        });
+
+        state.set_global("add", add).unwrap();
+        state.set_global("split", split).unwrap();
+        state.set_global("count", count).unwrap();
+        state.set_global("sum", sum).unwrap();
+        state.set_global("point", point).unwrap();
+        state.set_global("norm", norm).unwrap();
+        state.set_global("log", log).unwrap();
+    }
+
+    #[test]
+    fn call_from_lua() {
+        let logs = Rc::new(Cell::new(0));
+        let mut state = State::from_file("tests/bytecode/host_func.luac");
+        register(&mut state, logs.clone());
+        state.call(0, 0).unwrap();
+
+        assert_eq!(logs.get(), 2);
+        let result: (i64, String, usize) = state.get_global("result").unwrap();
+        assert_eq!(result, (30, "x".to_string(), 1));
+    }
+
+    #[test]
+    fn call_from_host() {
+        let mut state = State::new();
+        register(&mut state, Rc::new(Cell::new(0)));
+
+        let rets = call(
+            &mut state,
+            "add",
+            vec![Value::Integer(1), Value::Integer(2)],
+        )
+        .unwrap();
+        assert_eq!(f64::from_lua(rets[0].clone()).unwrap(), 3.0);
+
+        let err = call(&mut state, "add", vec![Value::Integer(1)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "error converting Lua nil to f64 (argument #2)"
+        );
+
+        let p = Point { x: 1.0, y: 0.0 }.into_lua().unwrap();
+        let rets = call(&mut state, "norm", vec![p]).unwrap();
+        assert_eq!(rets.len(), 1);
+    }
+
+    #[test]
+    fn recursive_mut() {
+        let mut state = State::new();
+        let f = state.create_function_mut(|state, ()| {
+            let rets = call(state, "f", vec![])?;
+            Ok(rets.len())
+        });
+        state.set_global("f", f).unwrap();
+
+        let err = call(&mut state, "f", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "mutable function called recursively");
+    }
+
+    #[test]
+    fn scoped() {
+        let mut state = State::new();
+        let mut total = 0;
+        let name = String::from("scoped");
+
+        state.scope(|scope| {
+            let f = scope.create_function_mut(|_, n: i64| {
+                total += n;
+                Ok(name.len())
+            });
+            scope.set_global("f", f).unwrap();
+
+            let rets = call(scope, "f", vec![Value::Integer(2)]).unwrap();
+            assert_eq!(usize::from_lua(rets[0].clone()).unwrap(), 6);
+            call(scope, "f", vec![Value::Integer(3)]).unwrap();
+        });
+        assert_eq!(total, 5);
+
+        let err = call(&mut state, "f", vec![Value::Integer(1)]).unwrap_err();
+        assert_eq!(err.to_string(), "scoped function called after its scope");
+        assert_eq!(total, 5);
+    }
+}
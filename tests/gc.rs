@@ -0,0 +1,142 @@
+mod gc {
+    use nad::{FromLua, Function, State, UserData, UserDataMethods, Value};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/gc_cycle.luac");
+        state.call(0, 0).unwrap();
+        state.gc_collect().unwrap();
+        state
+    }
+
+    #[test]
+    fn free_cycles() {
+        let mut state = new_state();
+        let t: Value = state.call_global("make_cycle", 1).unwrap();
+        let weak = match &t {
+            Value::Map(m) => Rc::downgrade(m),
+            _ => unreachable!(),
+        };
+        // held by host
+        assert_eq!(state.gc_collect().unwrap(), 0);
+        assert!(weak.upgrade().is_some());
+
+        // table, closure and its upvalue
+        drop(t);
+        assert!(weak.upgrade().is_some());
+        assert_eq!(state.gc_collect().unwrap(), 3);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(state.gc_collect().unwrap(), 0);
+    }
+
+    #[test]
+    fn keep_reachable() {
+        let mut state = new_state();
+        // reachable from globals
+        let keep: Value = state.get_global("keep").unwrap();
+        let get = match &keep {
+            Value::Map(m) => m.borrow()[&Value::String("get".into())].clone(),
+            _ => unreachable!(),
+        };
+        drop(keep);
+        state.gc_collect().unwrap();
+        let get = Function::from_lua(get).unwrap();
+        let n: i64 = get.call(&mut state, ()).unwrap();
+        assert_eq!(n, 42);
+
+        // held by host only
+        state.set_global("keep", Value::Nil).unwrap();
+        assert_eq!(state.gc_collect().unwrap(), 0);
+        let n: i64 = get.call(&mut state, ()).unwrap();
+        assert_eq!(n, 42);
+        drop(get);
+        assert_eq!(state.gc_collect().unwrap(), 3);
+    }
+
+    fn set_field(t: &Value, k: &str, v: Value) {
+        if let Value::Map(m) = t {
+            m.borrow_mut().insert(Value::String(k.into()), v);
+        }
+    }
+
+    #[test]
+    fn finalize_tables() {
+        let closed = Rc::new(Cell::new(0));
+        let mut state = State::new();
+        let c = closed.clone();
+        let gc = state.create_function(move |_, _: Value| {
+            c.set(c.get() + 1);
+            Ok(())
+        });
+        let mt = Value::new_map(Default::default());
+        set_field(&mt, "__gc", gc);
+
+        for name in ["t1", "t2"] {
+            let t = Value::new_map(Default::default());
+            let t: Value = state.call_global("setmetatable", (t, mt.clone())).unwrap();
+            state.set_global(name, t).unwrap();
+        }
+        state.gc_collect().unwrap();
+        assert_eq!(closed.get(), 0);
+
+        // cycle which is unreachable
+        let t: Value = state.get_global("t1").unwrap();
+        set_field(&t, "self", t.clone());
+        drop(t);
+        state.set_global("t1", Value::Nil).unwrap();
+        state.gc_collect().unwrap();
+        assert_eq!(closed.get(), 1);
+
+        // the others are finalized when state is closed
+        drop(state);
+        assert_eq!(closed.get(), 2);
+    }
+
+    struct Buffer([u8; 4096]);
+
+    impl UserData for Buffer {
+        fn add_methods(methods: &mut UserDataMethods<Self>) {
+            methods.add_method("len", |_, b, ()| Ok(b.0.len() as i64));
+        }
+    }
+
+    #[test]
+    fn control() {
+        let mut state = State::new();
+        let before = state.gc_count();
+        let buf = state.create_userdata(Buffer([0; 4096]));
+        state.set_global("buf", buf).unwrap();
+        assert!(state.gc_count() >= before + 4096);
+        state.set_global("buf", Value::Nil).unwrap();
+        state.gc_collect().unwrap();
+        assert!(state.gc_count() < before + 4096);
+
+        assert!(state.gc_is_running());
+        state.gc_stop();
+        assert!(!state.gc_is_running());
+        state.gc_restart();
+        assert!(state.gc_is_running());
+        assert!(state.gc_step().unwrap());
+
+        assert_eq!(state.gc_set_pause(150), 200);
+        assert_eq!(state.gc_set_pause(200), 150);
+    }
+
+    #[test]
+    fn per_state() {
+        let mut a = State::new();
+        let mut b = State::new();
+        let before = b.gc_count();
+        let buf = a.create_userdata(Buffer([0; 4096]));
+        a.set_global("buf", buf).unwrap();
+        assert!(a.gc_count() >= before + 4096);
+        assert_eq!(b.gc_count(), before);
+
+        a.gc_stop();
+        assert!(!a.gc_is_running());
+        assert!(b.gc_is_running());
+        assert_eq!(a.gc_set_pause(150), 200);
+        assert_eq!(b.gc_set_pause(200), 200);
+    }
+}
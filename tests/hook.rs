@@ -0,0 +1,72 @@
+mod hook {
+    use nad::{LuaError, Options, State, Value};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn new_state(options: Options) -> State {
+        let mut state = State::from_file("tests/bytecode/hook.luac").with_option(options);
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let mut state = new_state(Options {
+            instruction_limit: Some(10000),
+            ..Options::default()
+        });
+        let err = state.call_global::<_, ()>("spin", ()).unwrap_err();
+        assert_eq!(err.to_string(), "instruction limit exceeded");
+        assert!(state.instruction_count() > 10000);
+
+        // caught by pcall but raised again until back to host
+        let spin: Value = state.get_global("spin").unwrap();
+        let err = state
+            .call_global::<_, (bool, String)>("guarded", spin)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "instruction limit exceeded");
+
+        // each call from host has its own budget
+        let n: i64 = state.call_global("sum", 100).unwrap();
+        assert_eq!(n, 5050);
+        assert!(state.instruction_count() < 10000);
+    }
+
+    #[test]
+    fn count_hook() {
+        let mut state = new_state(Options::default());
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        state.set_count_hook(100, move |_| {
+            c.set(c.get() + 1);
+            Ok(())
+        });
+        let n: i64 = state.call_global("sum", 1000).unwrap();
+        assert_eq!(n, 500500);
+        let executed = state.instruction_count();
+        assert_eq!(calls.get() as u64, executed / 100);
+
+        state.remove_count_hook();
+        let _: i64 = state.call_global("sum", 1000).unwrap();
+        assert_eq!(calls.get() as u64, executed / 100);
+    }
+
+    #[test]
+    fn hook_errors() {
+        let mut state = new_state(Options::default());
+        let spin: Value = state.get_global("spin").unwrap();
+
+        // error of hook is raised in script
+        state.set_count_hook(1000, |_| Err(LuaError::new("stopped")));
+        let (ok, err): (bool, String) = state.call_global("guarded", spin.clone()).unwrap();
+        assert!(!ok);
+        assert_eq!(err, "stopped");
+
+        // abort outright
+        state.set_count_hook(1000, |_| Err(LuaError::Interrupted));
+        let err = state
+            .call_global::<_, (bool, String)>("guarded", spin)
+            .unwrap_err();
+        assert!(matches!(err, LuaError::Interrupted));
+    }
+}
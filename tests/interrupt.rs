@@ -0,0 +1,85 @@
+mod interrupt {
+    use nad::{InterruptHandle, LuaError, LuaResult, Options, State, Value};
+    use std::thread;
+    use std::time::Duration;
+
+    fn new_state() -> State {
+        let mut state =
+            State::from_file("tests/bytecode/hook.luac").with_option(Options::default());
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    fn watchdog(handle: InterruptHandle) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        })
+    }
+
+    #[test]
+    fn send_sync() {
+        fn check<T: Send + Sync + 'static>() {}
+        check::<InterruptHandle>();
+    }
+
+    #[test]
+    fn interrupt_script() {
+        let mut state = new_state();
+        let handle = state.interrupt_handle();
+
+        let dog = watchdog(handle.clone());
+        let err = state.call_global::<_, ()>("spin", ()).unwrap_err();
+        assert!(matches!(err, LuaError::Interrupted));
+        assert!(!handle.is_interrupted());
+        dog.join().unwrap();
+
+        // not caught by pcall
+        let spin: Value = state.get_global("spin").unwrap();
+        let dog = watchdog(handle.clone());
+        let err = state
+            .call_global::<_, (bool, String)>("guarded", spin)
+            .unwrap_err();
+        assert!(matches!(err, LuaError::Interrupted));
+        dog.join().unwrap();
+
+        // state is usable after interrupted
+        let n: i64 = state.call_global("sum", 100).unwrap();
+        assert_eq!(n, 5050);
+    }
+
+    #[test]
+    fn interrupt_builtin() {
+        let mut state = new_state();
+        let busy = state.create_function(|state, ()| -> LuaResult<()> {
+            loop {
+                state.check_interrupt()?;
+                thread::yield_now();
+            }
+        });
+        let dog = watchdog(state.interrupt_handle());
+        let err = state
+            .call_global::<_, (bool, String)>("guarded", busy)
+            .unwrap_err();
+        assert!(matches!(err, LuaError::Interrupted));
+        dog.join().unwrap();
+        state.check_interrupt().unwrap();
+    }
+
+    #[test]
+    fn interrupt_before_call() {
+        let mut state = new_state();
+        let handle = state.interrupt_handle();
+
+        // late interrupt of a call which has returned
+        let n: i64 = state.call_global("sum", 100).unwrap();
+        assert_eq!(n, 5050);
+        handle.interrupt();
+        assert!(handle.is_interrupted());
+
+        // does not abort the next call
+        let n: i64 = state.call_global("sum", 100).unwrap();
+        assert_eq!(n, 5050);
+        assert!(!handle.is_interrupted());
+    }
+}
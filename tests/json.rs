@@ -0,0 +1,86 @@
+mod json {
+    use nad::{FromLua, Function, IntoLua, State, Value};
+    use std::collections::HashMap;
+
+    fn json_func(state: &mut State, name: &str) -> Function {
+        let lib: HashMap<String, Value> = state.get_global("json").unwrap();
+        Function::from_lua(lib[name].clone()).unwrap()
+    }
+
+    fn encode_error(state: &mut State, val: Value) -> String {
+        let encode = json_func(state, "encode");
+        let res: Result<String, _> = encode.call(state, val);
+        res.unwrap_err().to_string()
+    }
+
+    fn decode_error(state: &mut State, s: &str) -> String {
+        let decode = json_func(state, "decode");
+        let res: Result<Value, _> = decode.call(state, s);
+        res.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn encode_errors() {
+        let mut state = State::new();
+        let print: Value = state.get_global("print").unwrap();
+        assert_eq!(
+            encode_error(&mut state, print),
+            "cannot encode a function value"
+        );
+        assert_eq!(
+            encode_error(&mut state, Value::Float(f64::NAN)),
+            "cannot encode non-finite number"
+        );
+        assert_eq!(
+            encode_error(&mut state, Value::String(b"\xff\xfe".to_vec().into())),
+            "cannot encode string which is not valid UTF-8"
+        );
+
+        let sparse: HashMap<i64, i64> = vec![(1, 1), (3, 3)].into_iter().collect();
+        assert_eq!(
+            encode_error(&mut state, sparse.into_lua().unwrap()),
+            "cannot encode table key of type number"
+        );
+
+        let t = Value::new_map(HashMap::new());
+        if let Value::Map(m) = &t {
+            let inner = Value::new_map(vec![(Value::Integer(1), t.clone())].into_iter().collect());
+            m.borrow_mut()
+                .insert(Value::String("inner".into()), inner);
+        }
+        assert_eq!(
+            encode_error(&mut state, t),
+            "cannot encode a recursive table"
+        );
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut state = State::new();
+        assert_eq!(
+            decode_error(&mut state, "[1, 2"),
+            "json decode error at position 6: expected ',' or ']'"
+        );
+        assert_eq!(
+            decode_error(&mut state, "{1: 2}"),
+            "json decode error at position 2: expected string key"
+        );
+        assert_eq!(
+            decode_error(&mut state, "01"),
+            "json decode error at position 2: invalid number"
+        );
+        assert_eq!(
+            decode_error(&mut state, "true false"),
+            "json decode error at position 6: trailing characters"
+        );
+        assert_eq!(
+            decode_error(&mut state, "\"\\ud800\""),
+            "json decode error at position 8: missing low surrogate"
+        );
+        let deep = "[".repeat(2000);
+        assert_eq!(
+            decode_error(&mut state, &deep),
+            "json decode error at position 1001: too many nested levels"
+        );
+    }
+}
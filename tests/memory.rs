@@ -0,0 +1,55 @@
+mod memory {
+    use nad::{Options, State, Value};
+
+    const LIMIT: usize = 1 << 20;
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/memory.luac").with_option(Options {
+            memory_limit: Some(LIMIT),
+            ..Options::default()
+        });
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn catch_out_of_memory() {
+        let mut state = new_state();
+        for name in ["grow_string", "grow_table", "grow_json", "decode_json"] {
+            let f: Value = state.get_global(name).unwrap();
+            let (ok, err): (bool, String) = state.call_global("guarded", f).unwrap();
+            assert!(!ok);
+            assert_eq!(err, "not enough memory");
+
+            // state is still usable
+            let n: i64 = state.call_global("small", ()).unwrap();
+            assert_eq!(n, 100);
+        }
+        assert!(state.memory_used() < LIMIT);
+        assert!(state.memory_peak() <= LIMIT);
+        assert!(state.memory_peak() > LIMIT / 2);
+    }
+
+    #[test]
+    fn error_to_host() {
+        let mut state = new_state();
+        let err = state.call_global::<_, ()>("grow_table", ()).unwrap_err();
+        assert_eq!(err.to_string(), "not enough memory");
+    }
+
+    #[test]
+    fn usage_without_limit() {
+        let mut state = State::new();
+        let before = state.memory_used();
+        let t = Value::new_map(Default::default());
+        if let Value::Map(m) = &t {
+            let s = Value::String("x".repeat(10000).into());
+            m.borrow_mut().insert(Value::Integer(1), s);
+        }
+        state.set_global("t", t).unwrap();
+        assert!(state.memory_used() >= before + 10000);
+        state.set_global("t", Value::Nil).unwrap();
+        assert!(state.memory_used() < before + 10000);
+        assert!(state.memory_peak() >= before + 10000);
+    }
+}
@@ -0,0 +1,46 @@
+mod package {
+    use nad::{State, Value};
+    use std::collections::HashMap;
+
+    fn require(state: &mut State, name: &str) -> Result<Value, String> {
+        state
+            .call_global("require", name)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn preload_native() {
+        let mut state = State::new();
+        state
+            .preload("config", |_, name| {
+                let mut m = HashMap::new();
+                m.insert("name".to_string(), name);
+                Ok(m)
+            })
+            .unwrap();
+
+        let first = require(&mut state, "config").unwrap();
+        let again = require(&mut state, "config").unwrap();
+        assert_eq!(first, again);
+
+        let m: HashMap<String, String> = state.call_global("require", "config").unwrap();
+        assert_eq!(m["name"], "config");
+    }
+
+    #[test]
+    fn not_found() {
+        let mut state = State::new();
+        if let Value::Map(m) = state.get_global::<Value>("package").unwrap() {
+            m.borrow_mut().insert(
+                Value::String("path".into()),
+                Value::String("tests/?.x;tests/?/init.x".into()),
+            );
+        }
+        let err = require(&mut state, "a.b").unwrap_err();
+        assert_eq!(
+            err,
+            "module 'a.b' not found:\n\tno field package.preload['a.b']\
+             \n\tno file 'tests/a/b.x'\n\tno file 'tests/a/b/init.x'"
+        );
+    }
+}
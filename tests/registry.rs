@@ -0,0 +1,64 @@
+mod registry {
+    use nad::{Function, State, Value, NOREF, REFNIL};
+
+    #[test]
+    fn ref_unref() {
+        let mut state = State::new();
+        state.push_value(Value::String("a".into()));
+        let a = state.reference();
+        state.push_value(Value::Integer(2));
+        let b = state.reference();
+        assert!(a > 0 && b > 0 && a != b);
+        assert_eq!(state.top(), 0);
+
+        state.push_value(Value::Nil);
+        assert_eq!(state.reference(), REFNIL);
+        state.get_reference(REFNIL);
+        state.get_reference(a);
+        assert_eq!(state.pop_value(), Value::String("a".into()));
+        assert_eq!(state.pop_value(), Value::Nil);
+
+        state.unreference(a);
+        state.unreference(NOREF);
+        state.get_reference(a);
+        assert_eq!(state.pop_value(), Value::Nil);
+
+        // released references are reused
+        state.push_value(Value::Bool(true));
+        assert_eq!(state.reference(), a);
+    }
+
+    #[test]
+    fn registry_key() {
+        let mut state = State::new();
+        let add = state.create_function(|_, (a, b): (i64, i64)| Ok(a + b));
+        let key = state.create_registry_value(add).unwrap();
+        let id = key.id();
+
+        // the function is pinned without any global
+        for i in 0..3 {
+            let f: Function = state.registry_value(&key).unwrap();
+            assert_eq!(f.call::<_, i64>(&mut state, (i, 1)).unwrap(), i + 1);
+        }
+        state.replace_registry_value(&key, "text").unwrap();
+        let s: String = state.registry_value(&key).unwrap();
+        assert_eq!(s, "text");
+
+        drop(key);
+        state.get_reference(id);
+        assert_ne!(state.pop_value(), Value::Nil);
+        let key = state.create_registry_value(1).unwrap();
+        assert_eq!(key.id(), id);
+        state.remove_registry_value(key).unwrap();
+        state.get_reference(id);
+        assert_eq!(state.pop_value(), Value::Nil);
+
+        let key = state.create_registry_value(Value::Nil).unwrap();
+        assert_eq!(key.id(), REFNIL);
+        assert!(state.replace_registry_value(&key, 1).is_err());
+
+        let other = State::new();
+        let err = other.registry_value::<Value>(&key).unwrap_err();
+        assert_eq!(err.to_string(), "RegistryKey used with a different state");
+    }
+}
@@ -15,7 +15,10 @@ mod run_code {
             println!("===========================");
             println!("exec: {}", Green.paint(path.to_str().unwrap()));
 
-            State::from_file(path).with_option(opt.clone()).call(0, 0);
+            State::from_file(path)
+                .with_option(opt.clone())
+                .call(0, 0)
+                .unwrap();
         })
     }
 }
@@ -0,0 +1,106 @@
+mod sandbox {
+    use nad::{Options, Reader, Sandbox, State, Value};
+    use std::path::PathBuf;
+
+    const SCRIPT: &str = "tests/bytecode/sandbox.luac";
+
+    fn new_state(sandbox: Sandbox) -> State {
+        let options = Options {
+            sandbox: Some(sandbox),
+            ..Options::default()
+        };
+        let mut state = State::from_file(SCRIPT).with_option(options);
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn default_sandbox() {
+        let mut state = State::sandboxed();
+        state.push_chunk(Reader::from_file(SCRIPT).into_chunk());
+        state.call(0, 0).unwrap();
+        let ok: bool = state.call_global("sandboxed", ()).unwrap();
+        assert!(ok);
+
+        // modules of host are still loaded into frozen `package.loaded`
+        state.preload("config", |_, name| Ok(name)).unwrap();
+        let name: String = state.call_global("require", "config").unwrap();
+        assert_eq!(name, "config");
+        let name: String = state.call_global("require", "config").unwrap();
+        assert_eq!(name, "config");
+
+        // no file root
+        let (ok, err): (bool, String) = state.call_global("load_file", SCRIPT).unwrap();
+        assert!(!ok);
+        assert_eq!(err, format!("cannot open {}: Permission denied", SCRIPT));
+    }
+
+    #[test]
+    fn file_root() {
+        let mut state = new_state(Sandbox {
+            io: true,
+            file_root: Some(PathBuf::from("tests/textcode")),
+            ..Sandbox::default()
+        });
+        let line: String = state.call_global("read_file", "hook.lua").unwrap();
+        assert!(line.starts_with("local function assert"));
+        let line: String = state
+            .call_global("read_file", "../textcode/hook.lua")
+            .unwrap();
+        assert!(line.starts_with("local function assert"));
+
+        for name in ["../bytecode/hook.luac", "/etc/passwd", "../x"] {
+            let (ok, err): (Value, String) = state.call_global("read_file", name).unwrap();
+            assert_eq!(ok, Value::Nil);
+            assert_eq!(err, format!("{}: Permission denied", name));
+        }
+    }
+
+    #[test]
+    fn binary_chunks() {
+        // binary chunks are only loaded from host by default
+        let mut state = new_state(Sandbox {
+            file_root: Some(PathBuf::from("tests/bytecode")),
+            ..Sandbox::default()
+        });
+        let (ok, err): (bool, String) = state.call_global("load_file", "hook.luac").unwrap();
+        assert!(!ok);
+        assert_eq!(err, "hook.luac: binary chunks are not allowed in sandbox");
+
+        let mut state = new_state(Sandbox {
+            binary_chunks: true,
+            file_root: Some(PathBuf::from("tests/bytecode")),
+            ..Sandbox::default()
+        });
+        let (ok, _): (bool, Value) = state.call_global("load_file", "hook.luac").unwrap();
+        assert!(ok);
+        let name: String = state.call_global("require_name", "mod_counter").unwrap();
+        assert_eq!(name, "mod_counter");
+
+        let (ok, err): (bool, String) = state
+            .call_global("load_file", "../textcode/hook.lua")
+            .unwrap();
+        assert!(!ok);
+        assert!(err.ends_with("Permission denied"));
+    }
+
+    #[test]
+    fn freeze_globals() {
+        let mut state = State::new();
+        state.set_global("config", vec![1, 2, 3]).unwrap();
+        state.freeze_globals();
+
+        let config: Value = state.get_global("config").unwrap();
+        state.push_value(config.clone());
+        state.push_value(Value::Integer(1));
+        state.push_value(Value::Integer(10));
+        let err = state.raw_set(-3).unwrap_err();
+        assert_eq!(err.to_string(), "attempt to modify a read-only table");
+        let first: i64 = state.call_global("rawget", (config, 1)).unwrap();
+        assert_eq!(first, 1);
+
+        // not sandboxed, io is still there
+        let io: Value = state.get_global("io").unwrap();
+        assert!(matches!(io, Value::Map(_)));
+    }
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "serde")]
+
+mod serde_value {
+    use nad::{State, Value};
+    use serde::de::value::BytesDeserializer;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Level {
+        Debug,
+        Limit(u32),
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Config {
+        name: String,
+        ports: Vec<u16>,
+        ratio: f64,
+        level: Level,
+        fallback: Option<String>,
+        tags: HashMap<String, bool>,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: "server".to_string(),
+            ports: vec![80, 443],
+            ratio: 0.5,
+            level: Level::Limit(3),
+            fallback: None,
+            tags: vec![("fast".to_string(), true)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut state = State::new();
+        let val = state.to_value(&config()).unwrap();
+        state.set_global("config", val).unwrap();
+
+        let val: Value = state.get_global("config").unwrap();
+        let back: Config = state.from_value(val).unwrap();
+        assert_eq!(back, config());
+
+        let val = state.to_value(&Level::Debug).unwrap();
+        assert_eq!(val, Value::String("Debug".into()));
+        assert_eq!(state.from_value::<Level>(val).unwrap(), Level::Debug);
+    }
+
+    #[test]
+    fn json_layout() {
+        let state = State::new();
+        let val: Value =
+            serde_json::from_str(r#"{"list": [1, 2.5, "x", null], "t": true}"#).unwrap();
+        let json = serde_json::to_string(&val).unwrap();
+        let back: serde_json::Value = serde_json::from_str(&json).unwrap();
+        // trailing `null` is not kept in the table
+        assert_eq!(back, serde_json::json!({"list": [1, 2.5, "x"], "t": true}));
+
+        // integral floats are accepted as integers
+        let n: u8 = state.from_value(Value::Float(3.0)).unwrap();
+        assert_eq!(n, 3);
+        assert!(state.from_value::<u8>(Value::Float(3.5)).is_err());
+        assert_eq!(serde_json::to_string(&Value::Nil).unwrap(), "null");
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut state = State::new();
+        let print: Value = state.get_global("print").unwrap();
+        let err = serde_json::to_string(&print).unwrap_err();
+        assert_eq!(err.to_string(), "cannot serialize a function value");
+
+        let val = state.to_value(&HashMap::<String, i64>::new()).unwrap();
+        if let Value::Map(m) = &val {
+            m.borrow_mut()
+                .insert(Value::String("self".into()), val.clone());
+        }
+        let err = serde_json::to_string(&val).unwrap_err();
+        assert_eq!(err.to_string(), "cannot serialize a recursive table");
+        let err = state.from_value::<HashMap<String, Value>>(val).unwrap_err();
+        assert_eq!(err.to_string(), "cannot deserialize a recursive table");
+    }
+
+    /// serialized by `serialize_bytes`
+    struct Bytes(&'static [u8]);
+
+    impl Serialize for Bytes {
+        fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_bytes(self.0)
+        }
+    }
+
+    #[test]
+    fn byte_strings() {
+        let state = State::new();
+        let val = state.to_value(&Bytes(b"abc")).unwrap();
+        assert_eq!(val, Value::String("abc".into()));
+        // strings are bytes which are not necessarily utf-8
+        let val = state.to_value(&Bytes(b"\xff\xfe")).unwrap();
+        assert_eq!(val, Value::String(b"\xff\xfe".to_vec().into()));
+
+        type Error = serde::de::value::Error;
+        let de = BytesDeserializer::<Error>::new(b"\xff\xfe");
+        assert_eq!(Value::deserialize(de).unwrap(), val);
+        let de = BytesDeserializer::<Error>::new(b"\xC0\x80");
+        let val = Value::deserialize(de).unwrap();
+        assert_eq!(val, Value::String(b"\xC0\x80".to_vec().into()));
+    }
+}
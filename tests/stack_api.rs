@@ -0,0 +1,136 @@
+mod stack_api {
+    use nad::{ArithOp, LuaType, State, Value};
+
+    fn push_all(state: &mut State, vals: Vec<Value>) {
+        assert!(state.check_stack(vals.len()));
+        vals.into_iter().for_each(|v| state.push_value(v));
+    }
+
+    #[test]
+    fn types_and_conversion() {
+        let mut state = State::new();
+        push_all(
+            &mut state,
+            vec![
+                Value::Nil,
+                Value::Bool(false),
+                Value::Integer(3),
+                Value::Float(2.5),
+                Value::String("0x10".into()),
+                Value::String("abc".into()),
+            ],
+        );
+
+        assert_eq!(state.type_at(1), LuaType::Nil);
+        assert_eq!(state.type_at(-1), LuaType::String);
+        assert_eq!(state.type_at(7), LuaType::None);
+        assert_eq!(state.type_at(7).name(), "no value");
+        assert!(state.is_none_or_nil(1) && state.is_none(10) && !state.is_nil(10));
+        assert!(state.is_boolean(2) && state.is_integer(3) && !state.is_integer(4));
+        assert!(state.is_number(5) && !state.is_number(6) && state.is_string(3));
+
+        assert_eq!(state.to_integerx(3), Some(3));
+        assert_eq!(state.to_integerx(4), None);
+        assert_eq!(state.to_integerx(5), Some(16));
+        assert_eq!(state.to_numberx(-3), Some(2.5));
+        assert_eq!(state.to_numberx(-1), None);
+        assert_eq!(state.to_number(-1), 0.0);
+        assert!(!state.to_boolean(2) && state.to_boolean(3) && !state.to_boolean(8));
+
+        // numbers are converted in place
+        assert_eq!(state.to_lstring(3), Some("3".into()));
+        assert!(state.is_string(3) && !state.is_number(1));
+        assert_eq!(state.type_at(3), LuaType::String);
+        assert_eq!(state.to_lstring(1), None);
+        assert_eq!(state.raw_len(-1), 3);
+        assert_eq!(state.abs_index(-1), 6);
+        assert!(state.check_stack(10));
+        assert!(!state.check_stack(usize::MAX / 2));
+        let err = state.ensure_stack(usize::MAX / 2, "too many values").unwrap_err();
+        assert_eq!(err.to_string(), "stack overflow (too many values)");
+    }
+
+    #[test]
+    fn fields() {
+        let mut state = State::new();
+        state.map_new(0);
+        state.push_value(Value::Integer(1));
+        state.set_field(1, "x").unwrap();
+        state.push_value(Value::String("a".into()));
+        state.set_i(-2, 1).unwrap();
+        state.push_value(Value::String("b".into()));
+        state.set_i(1, 2).unwrap();
+        assert_eq!(state.raw_len(1), 2);
+
+        assert_eq!(state.get_field(1, "x").unwrap(), LuaType::Number);
+        assert_eq!(state.get_i(1, 2).unwrap(), LuaType::String);
+        assert_eq!(state.get_field(1, "y").unwrap(), LuaType::Nil);
+        assert_eq!(state.to_lstring(-2), Some("b".into()));
+        state.set_top(1);
+
+        state.push_value(Value::String("k".into()));
+        state.push_value(Value::Bool(true));
+        state.raw_set(1).unwrap();
+        state.push_value(Value::String("k".into()));
+        assert_eq!(state.raw_get(1).unwrap(), LuaType::Boolean);
+        assert_eq!(state.top(), 2);
+
+        state.push_value(Value::Integer(1));
+        let err = state.raw_get(2).unwrap_err();
+        assert_eq!(err.to_string(), "table expected, got boolean");
+        assert_eq!(state.top(), 3);
+        state.push_value(Value::Integer(2));
+        let err = state.raw_set(2).unwrap_err();
+        assert_eq!(err.to_string(), "table expected, got boolean");
+        assert_eq!(state.top(), 4);
+        state.pop(2);
+        let err = state.get_field(2, "x").unwrap_err();
+        assert_eq!(err.to_string(), "attempt to index a boolean value");
+    }
+
+    #[test]
+    fn arith_and_compare() {
+        let mut state = State::new();
+        push_all(&mut state, vec![Value::Integer(-7), Value::Integer(2)]);
+        state.arith(ArithOp::IDiv).unwrap();
+        assert_eq!(state.pop_value(), Value::Integer(-4));
+
+        push_all(
+            &mut state,
+            vec![Value::Integer(i64::MAX), Value::Integer(1)],
+        );
+        state.arith(ArithOp::Add).unwrap();
+        assert_eq!(state.pop_value(), Value::Integer(i64::MIN));
+
+        push_all(&mut state, vec![Value::Integer(2), Value::Integer(3)]);
+        state.arith(ArithOp::Pow).unwrap();
+        assert_eq!(state.pop_value(), Value::Float(8.0));
+
+        push_all(&mut state, vec![Value::Integer(5)]);
+        state.arith(ArithOp::Unm).unwrap();
+        assert_eq!(state.pop_value(), Value::Integer(-5));
+
+        push_all(&mut state, vec![Value::Integer(1), Value::Integer(0)]);
+        let err = state.arith(ArithOp::Mod).unwrap_err();
+        assert_eq!(err.to_string(), "attempt to perform 'n%0'");
+        push_all(&mut state, vec![Value::Float(1.5), Value::Integer(1)]);
+        let err = state.arith(ArithOp::BOr).unwrap_err();
+        assert_eq!(err.to_string(), "number has no integer representation");
+        push_all(&mut state, vec![Value::Integer(1), Value::Nil]);
+        let err = state.arith(ArithOp::Add).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "attempt to perform arithmetic on a nil value"
+        );
+
+        push_all(
+            &mut state,
+            vec![Value::Integer(1), Value::Float(1.5), Value::Bool(true)],
+        );
+        assert!(state.compare(1, 2, "<").unwrap());
+        assert!(state.compare(2, 1, ">=").unwrap());
+        assert!(!state.compare(1, 5, "==").unwrap());
+        let err = state.compare(1, 3, "<").unwrap_err();
+        assert_eq!(err.to_string(), "attempt to compare number with boolean");
+    }
+}
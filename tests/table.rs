@@ -0,0 +1,54 @@
+mod table {
+    use nad::{State, Value};
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/table.luac");
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn borders() {
+        let mut state = new_state();
+        let n: i64 = state.call_global("borders", ()).unwrap();
+        assert_eq!(n, 11);
+        let n: i64 = state.call_global("removed", (100, 37)).unwrap();
+        assert_eq!(n, 37);
+        let n: i64 = state.call_global("removed", (8, 0)).unwrap();
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    fn constructor_and_traversal() {
+        let mut state = new_state();
+        let n: i64 = state.call_global("constructed", ()).unwrap();
+        assert_eq!(n, 16);
+        // integer keys of both parts and float keys with integral value
+        let n: i64 = state.call_global("traverse", ()).unwrap();
+        assert_eq!(n, 5111);
+        let n: i64 = state.call_global("sum_array", 20000).unwrap();
+        assert_eq!(n, 20000 * 20001);
+        let n: i64 = state.call_global("moved_tail", ()).unwrap();
+        assert_eq!(n, 1);
+        let n: i64 = state.call_global("cleared", 20000).unwrap();
+        assert_eq!(n, 40000);
+    }
+
+    #[test]
+    fn host_table() {
+        let mut state = State::new();
+        state.create_table(4, 1);
+        assert_eq!(state.raw_len(-1), 0);
+        for i in 1..=6 {
+            state.push_value(Value::Integer(i));
+            state.set_i(-2, i).unwrap();
+        }
+        assert_eq!(state.raw_len(-1), 6);
+
+        state.push_value(Value::Nil);
+        state.set_i(-2, 6).unwrap();
+        state.push_value(Value::Integer(8));
+        state.set_i(-2, 8).unwrap();
+        assert_eq!(state.raw_len(-1), 5);
+    }
+}
@@ -0,0 +1,32 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+-- each function is called by tests/arg_check.rs and fails in `insert`
+function by_global()
+    insert({}, nil)
+end
+
+function by_local()
+    local add = insert
+    add({}, 1.5)
+end
+
+function by_field()
+    local t = { insert = insert }
+    t.insert(1)
+end
+
+function by_method()
+    local t = { insert = insert }
+    t:insert("x")
+end
+
+function by_option()
+    insert({}, 1, "middle")
+end
+
+function by_builtin()
+    local c = utf8.char(1, -1)
+    return c
+end
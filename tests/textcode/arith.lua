@@ -0,0 +1,23 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+-- operands are locals so that they are not folded by compiler
+local i7, i2, in7, i3, f7 = 7, 2, -7, 3, 7.5
+
+-- integer and float results
+assert(i7 // i2 == 3 and in7 // i2 == -4)
+assert(f7 // i2 == 3.0 and i7 // (i2 - 2.0) == 1 / 0)
+assert(in7 % i3 == 2 and i7 % -i3 == -2 and f7 % -i2 == -0.5)
+assert(i2 ^ 10 == 1024.0 and i7 / i2 == 3.5)
+local max = 0x7fffffffffffffff
+assert(max + i3 - i2 == -max - 1)
+assert("10" + i3 == 13 and "3" * "4" == 12)
+
+-- bitwise
+assert(i3 << 64 == 0 and i3 << -1 == 1 and -i3 >> 63 == 1)
+assert(i3 & 5 == 1 and i3 | 5 == 7 and i3 ~ 5 == 6 and ~i3 == -4)
+assert("0x10" | i3 == 19 and 2.0 | i3 == 3)
+
+-- comparison
+assert(i3 < f7 and "a" < "b" and "a" <= "a" and not (i3 <= i2))
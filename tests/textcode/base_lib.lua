@@ -0,0 +1,58 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+assert(type(nil) == "nil" and type(true) == "boolean")
+assert(type(1) == "number" and type(1.5) == "number")
+assert(type("s") == "string" and type({}) == "table")
+assert(type(print) == "function" and type(type) == "function")
+assert(type(io.stdout) == "userdata")
+
+assert(tostring(nil) == "nil" and tostring(false) == "false")
+assert(tostring(10) == "10" and tostring(-0.5) == "-0.5")
+assert(tostring(1.0) == "1.0" and tostring(1e100) == "1e+100")
+assert(tostring("x") == "x")
+local t = {}
+assert(tostring(t) == tostring(t) and tostring(t) ~= tostring({}))
+
+assert(tonumber(10) == 10 and tonumber("0x10") == 16)
+assert(tonumber("  1e1  ") == 10.0)
+assert(tonumber("1e") == nil and tonumber("") == nil and tonumber({}) == nil)
+assert(tonumber("ff", 16) == 255 and tonumber("zz", 36) == 1295)
+assert(tonumber(" -101 ", 2) == -5 and tonumber("8", 8) == nil)
+assert(tonumber("7fffffffffffffff", 16) == 9223372036854775807)
+assert(tonumber("1.5", 10) == nil)
+
+assert(select("#") == 0 and select("#", nil, nil) == 2)
+assert(select(2, "a", "b", "c") == "b")
+local x, y = select(-2, "a", "b", "c")
+assert(x == "b" and y == "c")
+assert(select(5, "a") == nil)
+
+t = { 10, 20, 30 }
+assert(rawlen(t) == 3 and rawlen("abcd") == 4)
+assert(rawget(t, 2) == 20 and rawget(t, 4) == nil)
+assert(rawset(t, 4, 40) == t and t[4] == 40)
+t[2.0] = 21
+assert(t[2] == 21 and rawget(t, 2.0) == 21)
+t[4] = nil
+assert(#t == 3)
+assert(rawequal(t, t) and not rawequal(t, {}) and rawequal(1, 1.0))
+assert(rawequal(print, print) and not rawequal("1", 1))
+
+local a, b, c = unpack({ 1, 2, 3 })
+assert(a == 1 and b == 2 and c == 3)
+a, b = unpack({ 1, 2, 3 }, 2)
+assert(a == 2 and b == 3)
+a, b, c = unpack({ 1, 2, 3 }, 2, 4)
+assert(a == 2 and b == 3 and c == nil)
+assert(unpack({}, 1, 0) == nil)
+
+assert(_G.assert(1, "unused") == 1)
+local v1, v2 = _G.assert("ok", "msg")
+assert(v1 == "ok" and v2 == "msg")
+
+assert(_G._G == _G and _G.print == print)
+_G.new_global = 42
+assert(new_global == 42)
+assert(_VERSION == "Lua 5.3")
@@ -17,3 +17,46 @@ local val, index = max(1, 3, 129, 256, 35)
 print(val, index)
 assert(val == 256)
 assert(index == 4)
+
+-- tail calls reuse the frame of caller
+local function count(n, acc)
+    if n == 0 then return acc end
+    return count(n - 1, acc + 1)
+end
+assert(count(300000, 0) == 300000)
+
+local is_odd
+local function is_even(n)
+    if n == 0 then return true end
+    return is_odd(n - 1)
+end
+is_odd = function(n)
+    if n == 0 then return false end
+    return is_even(n - 1)
+end
+assert(is_even(100001) == false)
+
+local function two() return 1, 2 end
+local function tail_two() return two() end
+local a, b = tail_two()
+assert(a == 1 and b == 2)
+
+local function pass(...) return max(...) end
+val, index = pass(4, 8, 2)
+assert(val == 8 and index == 2)
+
+local function to_str(x) return tostring(x) end
+assert(to_str(12) == "12")
+
+local function id(f) return f end
+local function closure()
+    local x = 1
+    return id(function() return x end)
+end
+assert(closure()() == 1)
+
+local ok = pcall(function() local g; return g() end)
+assert(not ok)
+local function boom() error("boom") end
+ok = pcall(function() return boom() end)
+assert(not ok and count(10, 0) == 10)
@@ -0,0 +1,84 @@
+local function assert(v) if not v then fail() end end
+
+-- each iteration has its own variable
+function counters()
+  local fs = {}
+  for i = 1, 3 do
+    fs[i] = function() i = i + 10 return i end
+  end
+  local r = fs[1]() + fs[2]() + fs[3]() + fs[1]()
+  return r
+end
+
+-- closures share open variable with the function declaring it
+function shared()
+  local n = 0
+  local function inc() n = n + 1 end
+  local function get() return n end
+  inc()
+  inc()
+  assert(get() == 2 and n == 2)
+  n = 10
+  assert(get() == 10)
+  return inc, get
+end
+
+function varargs(a, b, ...)
+  local n = select("#", ...)
+  local c, d = ...
+  return a, b, n, c, d
+end
+
+function pass_varargs(...)
+  local a, b, n, c, d = varargs(...)
+  return a, b, n, c, d
+end
+
+function depth(n)
+  if n == 0 then return 0 end
+  local r = depth(n - 1)
+  return r + 1
+end
+
+-- upvalues are closed when frame is left by error
+function escaped()
+  local f
+  local ok = pcall(function()
+    local x = 42
+    f = function() x = x + 1 return x end
+    error("boom")
+  end)
+  assert(not ok)
+  local r = f() + f()
+  return r
+end
+
+function sum_calls(n)
+  local function add(a, b) return a + b end
+  local s = 0
+  for i = 1, n do s = add(s, i) end
+  return s
+end
+
+function overflow()
+  local function f(n) local r = f(n + 1) return r end
+  local ok, err = pcall(f, 1)
+  return ok, err
+end
+
+-- pcall and metamethods nest calls on the host stack
+function nested_pcall()
+  local err
+  local function f()
+    local ok, e = pcall(f)
+    if not ok then err = e end
+  end
+  f()
+  return err
+end
+
+function nested_index()
+  local t = setmetatable({}, {__index = function(t, k) return t[k] end})
+  local ok, err = pcall(function() return t.x end)
+  return ok, err
+end
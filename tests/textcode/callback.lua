@@ -0,0 +1,27 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+function greet(name)
+    return "hello " .. name
+end
+
+function addmul(a, b)
+    return a + b, a * b
+end
+
+function fail_with(msg)
+    _ENV.assert(false, msg)
+end
+
+local n = 0
+function counter()
+    n = n + 1
+    return n
+end
+
+-- `on` is registered by tests/callback.rs
+if on == nil then return end
+
+on("tick", function(k) n = n + k end)
+on("tick", counter)
@@ -0,0 +1,43 @@
+local function assert(v) if not v then fail() end end
+
+function traverse()
+  local a, b = {}, {}
+  local f = function() end
+  local t = {x = 1, [3] = "c", [1] = "a", [2.5] = "f", y = 2, [true] = "t"}
+  t[b] = "B"
+  t[f] = "F"
+  t[a] = "A"
+  local s = ""
+  for _, v in pairs(t) do s = s .. tostring(v) .. "," end
+  return s
+end
+
+function rolls(n)
+  local s = ""
+  for i = 1, n do s = s .. math.random(1000) .. "," end
+  return s .. math.random()
+end
+
+function roll(...)
+  local r = math.random(...)
+  return r
+end
+
+function encoded()
+  local s = json.encode({b = 1, a = 2, c = {z = 1, y = 2}})
+  return s
+end
+
+function now()
+  return os.time(), os.clock(), os.date("%Y-%m-%d %H:%M:%S")
+end
+
+for i = 1, 1000 do
+  local r = math.random(3, 5)
+  assert(r >= 3 and r <= 5)
+  local f = math.random()
+  assert(f >= 0 and f < 1)
+end
+assert(math.random(7, 7) == 7)
+assert(math.random(0) ~= math.random(0))
+assert(not pcall(math.random, 1, 2, 3))
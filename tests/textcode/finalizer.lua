@@ -0,0 +1,61 @@
+local function assert(v) if not v then fail() end end
+
+-- finalizers run in reverse order of marking
+local order = {}
+local mt = { __gc = function(o) order[#order + 1] = o.id end }
+local function make(id)
+  local o = setmetatable({ id = id }, mt)
+  o.self = o
+end
+for i = 1, 3 do make(i) end
+collectgarbage()
+assert(#order == 3 and order[1] == 3 and order[2] == 2 and order[3] == 1)
+
+-- resurrected objects keep what they reference and are finalized once
+local saved, count = nil, 0
+local rmt = { __gc = function(o) saved, count = o, count + 1 end }
+local function make_saved()
+  setmetatable({ data = "kept", child = { 1, 2 } }, rmt)
+end
+make_saved()
+collectgarbage()
+assert(saved.data == "kept" and saved.child[2] == 2 and count == 1)
+saved = nil
+collectgarbage()
+assert(count == 1)
+
+-- resurrected objects are removed from weak values before finalized,
+-- but from weak keys after
+local wk = setmetatable({}, { __mode = "k" })
+local wv = setmetatable({}, { __mode = "v" })
+local seen_k, seen_v
+local function make_weak()
+  local gc = function(o) seen_k, seen_v = wk[o], wv[1] end
+  local o = setmetatable({}, { __gc = gc })
+  wk[o], wv[1] = "key", o
+end
+make_weak()
+collectgarbage()
+assert(seen_k == "key" and seen_v == nil)
+collectgarbage()
+assert(next(wk) == nil)
+
+-- memory in use
+local before = collectgarbage("count")
+local big = {}
+for i = 1, 10000 do big[i] = i end
+assert(collectgarbage("count") > before)
+big = nil
+collectgarbage()
+assert(collectgarbage("count") < before + 1)
+
+-- options
+assert(collectgarbage("isrunning"))
+assert(collectgarbage("stop") == 0 and not collectgarbage("isrunning"))
+assert(collectgarbage("restart") == 0 and collectgarbage("isrunning"))
+assert(collectgarbage("step") == true)
+assert(collectgarbage("setpause", 150) == 200)
+assert(collectgarbage("setpause", 200) == 150)
+assert(not pcall(collectgarbage, "generational"))
+assert(not pcall(collectgarbage, "incremental"))
+assert(not pcall(collectgarbage, "setstepmul", 100))
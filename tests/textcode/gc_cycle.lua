@@ -0,0 +1,26 @@
+local function assert(v) if not v then fail() end end
+
+-- table and closure referencing each other
+function make_cycle(n)
+  local t = { n = n }
+  t.get = function() local n = t.n return n end
+  return t
+end
+
+-- node kept in globals survives collections
+keep = make_cycle(42)
+
+-- many unreachable cycles trigger collections while running
+local sum = 0
+for i = 1, 5000 do
+  local c = make_cycle(i)
+  local function rec(k)
+    if k > 0 then local r = rec(k - 1) return r end
+    local r = c.get()
+    return r
+  end
+  c.self = c
+  sum = sum + rec(1)
+end
+assert(sum == 5000 * 5001 // 2)
+assert(keep.get() == 42)
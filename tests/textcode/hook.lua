@@ -0,0 +1,18 @@
+local function assert(v) if not v then fail() end end
+
+function spin()
+  while true do end
+end
+
+function guarded(f)
+  local ok, err = pcall(f)
+  return ok, err
+end
+
+function sum(n)
+  local s = 0
+  for i = 1, n do s = s + i end
+  return s
+end
+
+assert(sum(100) == 5050)
@@ -0,0 +1,27 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+-- functions are registered by tests/function.rs
+if add == nil then return end
+
+assert(add(1, 2) == 3)
+assert(add(1.5, 2) == 3.5)
+
+local name, n = split("a,b,c")
+assert(name == "a" and n == 3)
+
+assert(count() == 1)
+assert(count() == 2)
+assert(sum() == 0)
+assert(sum(1, 2, 3, 4) == 10)
+
+local p = point(3, 4)
+assert(p.x == 3 and p.y == 4)
+assert(norm(p) == 5)
+
+local nothing = log("message")
+assert(nothing == nil)
+assert(select("#", log("message")) == 0)
+
+result = { add(10, 20), split("x") }
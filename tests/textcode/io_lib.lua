@@ -0,0 +1,94 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+local name = os.tmpname()
+local f = io.open(name, "w")
+assert(io.type(f) == "file")
+assert(f:write("first line\n", 42, " ", 1.5, "\n") == f)
+f:write("0x1F -3e2 rest\n", "last")
+assert(f:close() == true)
+assert(io.type(f) == "closed file")
+assert(io.type(42) == nil)
+
+f = io.open(name)
+assert(f:read() == "first line")
+assert(f:read("n") == 42)
+assert(f:read("*n") == 1.5)
+assert(f:read("L") == "\n")
+assert(f:read("n", "n") == 31)
+assert(f:read(1) == " ")
+assert(f:read(4) == "rest")
+assert(f:read("l") == "")
+assert(f:read(0) == "")
+assert(f:read("a") == "last")
+assert(f:read(0) == nil)
+assert(f:read("l") == nil)
+assert(f:read("a") == "")
+
+assert(f:seek("set", 6) == 6)
+assert(f:read(4) == "line")
+assert(f:seek() == 10)
+assert(f:seek("cur", -4) == 6)
+assert(f:seek("end") == 37)
+f:close()
+
+local lines = {}
+local count = 0
+for line in io.lines(name) do
+    count = count + 1
+    lines[count] = line
+end
+assert(count == 4 and lines[1] == "first line" and lines[4] == "last")
+
+count = 0
+for a, b in io.lines(name, 1, 2) do
+    count = count + 1
+    if count == 1 then assert(a == "f" and b == "ir") end
+end
+assert(count == 13)
+
+f = io.open(name, "a+")
+f:write("\nappended")
+f:seek("set")
+assert(f:read("a") == "first line\n42 1.5\n0x1F -3e2 rest\nlast\nappended")
+f:close()
+
+local old = io.output()
+assert(io.output(name) ~= old)
+io.write("via ", "default")
+io.close()
+io.output(old)
+io.input(name)
+assert(io.read("a") == "via default")
+io.input(io.stdin)
+
+-- reads return the bytes as they are in the file
+f = io.open(name, "wb")
+f:write("h\xc3\xa9llo")
+f:close()
+f = io.open(name, "rb")
+local data, n = "", 0
+local c = f:read(1)
+while c do
+    assert(#c == 1)
+    data, n = data .. c, n + 1
+    c = f:read(1)
+end
+f:close()
+assert(n == 6 and data == "h\xc3\xa9llo")
+
+f = io.open(name, "wb")
+f:write("\xff\xfeAB")
+f:close()
+f = io.open(name, "rb")
+data = f:read("a")
+f:close()
+assert(#data == 4 and data == "\xff\xfeAB")
+
+local ok, msg, code = io.open("/nonexistent/file")
+assert(ok == nil and msg == "/nonexistent/file: No such file or directory" and code == 2)
+ok, msg = io.stdout:close()
+assert(ok == nil and msg == "cannot close standard file")
+
+assert(os.remove(name))
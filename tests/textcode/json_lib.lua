@@ -0,0 +1,39 @@
+local function assert(v) if not v then fail() end end
+
+assert(package.loaded.json == json)
+
+-- scalars
+assert(json.encode(nil) == "null")
+assert(json.encode(true) == "true")
+assert(json.encode(42) == "42")
+assert(json.encode(1.5) == "1.5")
+assert(json.encode(3.0) == "3.0")
+assert(json.encode("a\"b\\c\n\1") == '"a\\"b\\\\c\\n\\u0001"')
+assert(json.encode(json.null) == "null")
+
+-- arrays and objects
+assert(json.encode({1, 2, "x"}) == '[1,2,"x"]')
+assert(json.encode({}) == "{}")
+assert(json.encode({b = 1, a = {true, false}}, {sort_keys = true}) == '{"a":[true,false],"b":1}')
+local pretty = json.encode({b = {1, 2}, a = {}}, {indent = 2, sort_keys = true})
+assert(pretty == '{\n  "a": {},\n  "b": [\n    1,\n    2\n  ]\n}')
+assert(json.encode({1}, {indent = "\t"}) == "[\n\t1\n]")
+assert(json.encode({1}, {indent = 10}) == "[\n          1\n]")
+assert(not pcall(json.encode, {1}, {indent = 11}))
+assert(not pcall(json.encode, {1}, {indent = 0x4000000000000000}))
+
+-- decode
+local t = json.decode(' {"name": "lua", "list": [1, 2.5, -3e2, null], "ok": true, "none": null} ')
+assert(t.name == "lua" and t.ok == true)
+assert(t.list[1] == 1)
+assert(t.list[2] == 2.5 and t.list[3] == -300.0)
+assert(t.list[4] == json.null and #t.list == 4)
+assert(t.none == json.null)
+assert(json.decode('"\\u00e9\\ud83d\\ude00\\/"') == "\u{e9}\u{1F600}/")
+assert(json.decode("123") == 123)
+assert(json.decode("[]")[1] == nil)
+
+-- round trip
+local s = json.encode(t, {sort_keys = true})
+assert(s == '{"list":[1,2.5,-300.0,null],"name":"lua","none":null,"ok":true}')
+assert(json.encode(json.decode(s), {sort_keys = true}) == s)
@@ -0,0 +1,56 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+local path = "tests/bytecode/load_target.luac"
+local f, msg = loadfile(path)
+assert(type(f) == "function" and msg == nil)
+answer = 42
+local a, b = f("arg")
+assert(a == 42 and b == "arg")
+
+f = loadfile(path, "b", { answer = "env" })
+assert(f() == "env")
+f, msg = loadfile(path, "t")
+assert(f == nil and msg == "attempt to load a binary chunk (mode is 't')")
+f, msg = loadfile("tests/bytecode/missing.luac")
+assert(f == nil and msg == "cannot open tests/bytecode/missing.luac: No such file or directory")
+
+-- binary chunks read from file are loaded from strings
+local file = io.open(path, "rb")
+local data = file:read("a")
+file:close()
+
+f = load(data)
+assert(f() == 42)
+f = load(data, "=target", "b", { answer = 1 })
+assert(f() == 1)
+
+local done = false
+f = load(function()
+    if not done then
+        done = true
+        return data
+    end
+end)
+assert(f() == 42)
+
+local pieces = { "\27Lu", "a" }
+local i = 0
+f, msg = load(function()
+    i = i + 1
+    return pieces[i]
+end)
+assert(f == nil and msg == "(load): truncated precompiled chunk")
+
+f, msg = load(function() return {} end)
+assert(f == nil and type(msg) == "string")
+f, msg = load(data, "=target", "t")
+assert(f == nil and msg == "attempt to load a binary chunk (mode is 't')")
+f, msg = load("\27Lua")
+assert(f == nil and msg == "binary string: truncated precompiled chunk")
+f, msg = load("\27Lux", "=bad")
+assert(f == nil and msg == "bad: not a precompiled chunk")
+
+local x, y = dofile(path)
+assert(x == 42 and y == nil)
@@ -0,0 +1,2 @@
+-- chunk loaded at runtime by load_lib.lua
+return answer, ...
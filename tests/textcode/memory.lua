@@ -0,0 +1,45 @@
+local function assert(v) if not v then fail() end end
+
+-- errors are caught by pcall
+local ok, v = pcall(function(a) return a + 1 end, 1)
+assert(ok and v == 2)
+ok, v = pcall(function() local x = nil return x + 1 end)
+assert(not ok and type(v) == "string")
+
+function grow_string()
+  local s = "x"
+  while true do s = s .. s end
+end
+
+function grow_table()
+  local t = {}
+  local i = 1
+  while true do
+    t[i] = { i }
+    i = i + 1
+  end
+end
+
+function grow_json()
+  local t = {}
+  for i = 1, 500 do t = { t } end
+  local s = json.encode(t, { indent = 10 })
+  return s
+end
+
+function decode_json()
+  local s = "[1]"
+  for i = 1, 15 do s = s .. "," .. s end
+  return json.decode("[" .. s .. "]")
+end
+
+function guarded(f)
+  local ok, err = pcall(f)
+  return ok, err
+end
+
+function small()
+  local t = {}
+  for i = 1, 100 do t[i] = tostring(i) end
+  return #t
+end
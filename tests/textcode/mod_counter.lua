@@ -0,0 +1,10 @@
+-- module loaded by require in package_lib.lua
+local name, filename = ...
+local M = { name = name, filename = filename, count = 0 }
+
+function M.incr()
+  M.count = M.count + 1
+  return M.count
+end
+
+return M
@@ -0,0 +1,42 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+local t = os.time({ year = 2021, month = 3, day = 14, hour = 15, min = 9, sec = 26 })
+local d = os.date("*t", t)
+assert(d.year == 2021 and d.month == 3 and d.day == 14)
+assert(d.hour == 15 and d.min == 9 and d.sec == 26)
+assert(d.wday == 1 and d.yday == 73)
+
+-- day overflow is normalized by mktime
+local next_month = os.time({ year = 2021, month = 3, day = 14 + 30, hour = 15, min = 9, sec = 26 })
+assert(os.difftime(next_month, t) == 30 * 24 * 3600)
+assert(os.difftime(-9223372036854775807 - 1, 1) == -9.2233720368547758e18)
+assert(os.date("%Y-%m-%d", next_month) == "2021-04-13")
+assert(os.difftime(t) == t)
+
+-- fields of the table are normalized in place
+local date = { year = 2021, month = 14, day = 0, hour = 25, min = -1, sec = 61 }
+local normalized = os.time(date)
+assert(date.year == 2022 and date.month == 2 and date.day == 1)
+assert(date.hour == 1 and date.min == 0 and date.sec == 1)
+assert(date.wday == 3 and date.yday == 32)
+assert(os.time(date) == normalized)
+
+assert(os.date("!%H:%M:%S", 3600 * 5 + 60 * 4 + 3) == "05:04:03")
+assert(os.date("!%Y-%m-%d %j %%", 0) == "1970-01-01 001 %")
+local u = os.date("!*t", 86400)
+assert(u.year == 1970 and u.day == 2 and u.hour == 0)
+
+assert(os.time() > 0)
+assert(os.clock() >= 0)
+assert(os.getenv("NAD_SURELY_NOT_DEFINED") == nil)
+
+local name = os.tmpname()
+local renamed = name .. ".renamed"
+assert(os.rename(name, renamed))
+local ok, msg, errno = os.remove(name)
+assert(ok == nil and msg == name .. ": No such file or directory" and errno == 2)
+assert(os.remove(renamed))
+
+print(os.date("%Y-%m-%d", t), os.date("!%c", 0))
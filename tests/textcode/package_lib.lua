@@ -0,0 +1,35 @@
+local function assert(v) if not v then fail() end end
+
+assert(type(require) == "function")
+assert(package.loaded._G == _G)
+assert(package.loaded.io == io and package.loaded.package == package)
+assert(package.config == "/\n;\n?\n!\n-\n")
+assert(type(package.searchers[1]) == "function")
+
+package.path = "tests/bytecode/?.luac;tests/bytecode/?/init.luac"
+local counter = require("mod_counter")
+assert(counter.name == "mod_counter")
+assert(counter.filename == "tests/bytecode/mod_counter.luac")
+assert(counter.incr() == 1)
+
+-- modules are loaded only once
+assert(require("mod_counter") == counter)
+assert(package.loaded.mod_counter == counter)
+assert(require("mod_counter").incr() == 2)
+
+-- loader without result
+package.preload.empty = function(name) assert(name == "empty") end
+assert(require("empty") == true)
+assert(package.loaded.empty == true)
+
+-- loader setting package.loaded itself
+package.preload.self_set = function(name) package.loaded[name] = "set" end
+assert(require("self_set") == "set")
+
+local f, msg = package.searchpath("a.b", "tests/?.x;tests/?/init.x")
+assert(f == nil)
+assert(msg == "\n\tno file 'tests/a/b.x'\n\tno file 'tests/a/b/init.x'")
+f = package.searchpath("mod_counter", package.path)
+assert(f == "tests/bytecode/mod_counter.luac")
+f = package.searchpath("mod-counter", "tests/bytecode/?.luac", "-", "_")
+assert(f == "tests/bytecode/mod_counter.luac")
@@ -0,0 +1,57 @@
+local function assert(v) if not v then fail() end end
+
+function sandboxed()
+  -- libraries touching the host are not installed
+  assert(io == nil and package.loaded.io == nil)
+  assert(os.exit == nil and os.getenv == nil and os.remove == nil)
+  assert(os.time ~= nil and os.clock ~= nil)
+  assert(package.path == "./?.luac;./?/init.luac")
+
+  -- only memory in use can be read from the collector
+  assert(collectgarbage("count") > 0)
+  assert(not pcall(collectgarbage))
+  assert(not pcall(collectgarbage, "stop"))
+  assert(not pcall(collectgarbage, "setpause", 1))
+
+  -- builtin globals are read-only
+  assert(not pcall(rawset, os, "time", nil))
+  assert(not pcall(setmetatable, os, {}))
+  assert(getmetatable(_G) == false)
+  assert(not pcall(setmetatable, _G, nil))
+  assert(_G._G == _G)
+
+  -- so are tables reachable from them
+  local evil = function() return "evil" end
+  assert(not pcall(function() package.searchers[1] = evil end))
+  assert(not pcall(rawset, package.loaded, "x", evil))
+  assert(not pcall(rawset, package.preload, "x", evil))
+  assert(not pcall(rawset, json.null, 1, evil))
+  assert(package.searchers[2] ~= nil and package.preload.x == nil)
+
+  -- scripts set their own globals over them
+  local builtin = tostring
+  tostring = 1
+  assert(tostring == 1 and rawget(_G, "tostring") == 1)
+  tostring = nil
+  assert(tostring == builtin)
+  return true
+end
+
+function read_file(name)
+  local f, err = io.open(name)
+  if not f then return nil, err end
+  local s = f:read("l")
+  f:close()
+  return s
+end
+
+function load_file(name)
+  local f, err = loadfile(name)
+  return f ~= nil, err
+end
+
+function require_name(name)
+  return require(name).name
+end
+
+assert(sandboxed ~= nil)
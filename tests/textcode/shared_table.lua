@@ -0,0 +1,34 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+-- tables are shared by reference
+local t = {}
+local u = t
+u.x = 1
+assert(t.x == 1)
+
+local function set(m, k, v)
+    m[k] = v
+end
+set(t, "y", 2)
+assert(u.y == 2)
+
+-- upvalues see the same table
+local function add(k, v)
+    t[k] = v
+end
+add(1, "a")
+add(2, "b")
+assert(u[1] == "a" and u[2] == "b")
+
+-- tables and functions compare and hash by identity
+assert(t == u and t ~= {})
+local f = set
+assert(f == set and f ~= add)
+
+local k1, k2 = {}, {}
+local m = {[k1] = 1, [k2] = 2, [set] = 3}
+assert(m[k1] == 1 and m[k2] == 2 and m[f] == 3)
+k1.x = 1
+assert(m[k1] == 1 and m[{}] == nil)
@@ -0,0 +1,106 @@
+local function assert(v) if not v then fail() end end
+
+-- `n` is a border of `t`
+local function is_border(t, n)
+  return (n == 0 or t[n] ~= nil) and t[n + 1] == nil
+end
+
+function borders()
+  local t = {1, 2, 3, nil, 5}
+  assert(is_border(t, #t))
+
+  t = {}
+  for i = 1, 100 do t[i] = i end
+  t[50] = nil
+  assert(is_border(t, #t))
+  t[100] = nil
+  assert(is_border(t, #t))
+
+  -- non-sequence keys do not count
+  t = {x = 1, y = 2, [0] = 0, [-1] = -1, [1.5] = 1.5}
+  assert(#t == 0)
+  t[1], t[2] = "a", "b"
+  assert(#t == 2 and rawlen(t) == 2)
+
+  -- keys added in reverse order end in the hash part
+  t = {}
+  for i = 10, 1, -1 do t[i] = i end
+  assert(#t == 10)
+  t[11] = 11
+  assert(#t == 11)
+  return #t
+end
+
+function removed(n, k)
+  local t = {}
+  for i = 1, n do t[i] = i end
+  for i = n, k + 1, -1 do t[i] = nil end
+  return #t
+end
+
+function constructed()
+  local t = {1, 2, 3, 4, 5, 6, 7, 8, 9, 10, a = 1, b = 2, c = 3}
+  assert(#t == 10 and t.a == 1 and t.c == 3)
+  local u = {select(1, 1, 2, 3, 4)}
+  assert(#u == 4 and u[4] == 4)
+  local v = {n = 1, select(1, "x", "y")}
+  assert(#v == 2 and v[2] == "y" and v.n == 1)
+  local r = #t + #u + #v
+  return r
+end
+
+function traverse()
+  local t = {10, 20, 30, x = "x"}
+  t[5] = 50
+  t[2.0] = 21
+  local count, sum = 0, 0
+  for k, v in pairs(t) do
+    count = count + 1
+    if type(k) == "number" then sum = sum + v end
+  end
+  assert(t[2] == 21)
+  local r = count * 1000 + sum
+  return r
+end
+
+function sum_array(n)
+  local t = {}
+  for i = 1, n do t[i] = i end
+  for i = 1, #t do t[#t + 1 - i] = t[#t + 1 - i] * 2 end
+  local s = 0
+  for i = 1, #t do s = s + t[i] end
+  return s
+end
+
+-- key at the end of a shrunk array part is set again
+function moved_tail()
+  local t = {}
+  for i = 1, 8 do t[i] = i end
+  t[4], t[6], t[7], t[8] = nil, nil, nil, nil
+  for i = 1, 40 do t["k" .. i] = i end
+  t[5] = "new"
+  local count = 0
+  for k in pairs(t) do
+    if k == 5 then count = count + 1 end
+  end
+  assert(t[5] == "new")
+  t[5] = nil
+  assert(t[5] == nil)
+  return count
+end
+
+-- entries removed during traversal, in both parts
+function cleared(n)
+  local t = {}
+  for i = 1, n do
+    t[i] = i
+    t["k" .. i] = i
+  end
+  local count = 0
+  for k in pairs(t) do
+    t[k] = nil
+    count = count + 1
+  end
+  assert(next(t) == nil)
+  return count
+end
@@ -0,0 +1,34 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+-- `Vector` is registered by tests/userdata.rs
+if Vector == nil then return end
+
+local a = Vector(1, 2)
+local b = Vector(3, 4)
+assert(type(a) == "userdata")
+assert(a.x == 1 and a.y == 2)
+a.x = 5
+assert(a.x == 5)
+assert(a.missing == nil)
+
+assert(b:length() == 5)
+b:scale(2)
+assert(b.x == 6 and b.y == 8)
+
+local c = a + b
+assert(c.x == 11 and c.y == 10)
+local d = -a
+assert(d.x == -5 and d.y == -2)
+
+assert(Vector(1, 1) == Vector(1, 1))
+assert(Vector(1, 1) ~= Vector(1, 2))
+assert(a ~= nil and a == a)
+assert(tostring(Vector(1, 2)) == "Vector(1, 2)")
+
+-- `__le` falls back to `__lt`
+assert(Vector(1, 1) < Vector(3, 0) and not (b < a))
+assert(Vector(1, 1) <= Vector(1, 1) and b >= a)
+
+result = a
@@ -0,0 +1,41 @@
+local function assert(v)
+    if not v then fail() end
+end
+
+local s = "héllo, 世界"
+assert(#s == 14)
+assert(utf8.len(s) == 9)
+assert(utf8.len(s, 4) == 7)
+assert(utf8.len(s, -6) == 2)
+assert(utf8.len("") == 0)
+
+local n, pos = utf8.len(s, 3, 4)
+assert(n == nil and pos == 3)
+
+assert(utf8.char(72, 233, 0x4E16, 0x1F600) == "Hé世😀")
+assert(utf8.char() == "")
+assert(not pcall(utf8.char, 0xD800) and not pcall(utf8.char, 0x110000))
+assert(utf8.codepoint(s) == 104)
+assert(utf8.codepoint(s, 2) == 233)
+local a, b, c = utf8.codepoint(s, -6, -1)
+assert(a == 19990 and b == 30028 and c == nil)
+assert(utf8.codepoint(s, 3, 2) == nil)
+
+assert(utf8.offset(s, 3) == 4)
+assert(utf8.offset(s, -1) == 12)
+assert(utf8.offset(s, -2) == 9)
+assert(utf8.offset(s, 0, 3) == 2)
+assert(utf8.offset(s, 10) == 15)
+assert(utf8.offset(s, 11) == nil)
+assert(utf8.offset(s, -10) == nil)
+
+local count = 0
+local last = 0
+for p, code in utf8.codes(s) do
+    count = count + 1
+    last = code
+    if count == 3 then assert(p == 4 and code == 108) end
+end
+assert(count == 9 and last == 30028)
+
+assert(utf8.charpattern == "[\0-\x7F\xC2-\xF4][\x80-\xBF]*")
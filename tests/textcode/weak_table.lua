@@ -0,0 +1,78 @@
+local function assert(v) if not v then fail() end end
+
+-- metatables of tables
+local base = { x = 1 }
+local obj = setmetatable({}, { __index = base })
+assert(obj.x == 1 and rawget(obj, "x") == nil)
+assert(getmetatable(obj).__index == base)
+local index = setmetatable({}, { __index = function(t, k) return k .. "!" end })
+assert(index.a == "a!")
+
+local log = {}
+local proxy = setmetatable({ b = 2 }, { __newindex = function(t, k, v) log[k] = v end })
+proxy.a, proxy.b = 1, 3
+assert(rawget(proxy, "a") == nil and log.a == 1 and proxy.b == 3)
+
+local eq = { __eq = function(a, b) return a.id == b.id end }
+local a, b = setmetatable({ id = 1 }, eq), setmetatable({ id = 1 }, eq)
+assert(a == b and not rawequal(a, b))
+assert(setmetatable(a, nil) == a and getmetatable(a) == nil)
+
+-- loops of __index and __newindex tables raise errors
+local loop = {}
+setmetatable(loop, { __index = loop, __newindex = loop })
+local ok, err = pcall(function() return loop.x end)
+assert(not ok and err == "'__index' chain too long; possibly a loop")
+ok, err = pcall(function() loop.x = 1 end)
+assert(not ok and err == "'__newindex' chain too long; possibly a loop")
+local chain = setmetatable({}, { __index = setmetatable({}, { __index = base }) })
+assert(chain.x == 1)
+
+local locked = setmetatable({}, { __metatable = "locked" })
+assert(getmetatable(locked) == "locked")
+
+-- traversal
+local t = { 1, 2, 3, x = 4 }
+local sum, n = 0, 0
+for k, v in pairs(t) do
+  sum, n = sum + v, n + 1
+end
+assert(sum == 10 and n == 4)
+for k in pairs(t) do t[k] = nil end
+assert(next(t) == nil)
+
+-- caches with weak keys, weak values and both
+cache_k = setmetatable({}, { __mode = "k" })
+cache_v = setmetatable({}, { __mode = "v" })
+cache_kv = setmetatable({}, { __mode = "kv" })
+keep = {}
+
+function fill()
+  keep.k, keep.v = {}, {}
+  -- unreachable key
+  cache_k[{}] = 1
+  -- value is alive while its key is
+  cache_k[keep.k] = {}
+  -- value referencing its own key does not keep it alive
+  local k = {}
+  cache_k[k] = { k }
+  cache_k.s = {}
+
+  cache_v.dead = {}
+  cache_v.live = keep.v
+  cache_v.n = 1
+
+  cache_kv[{}] = 1
+  cache_kv[1] = {}
+  cache_kv[keep.k] = keep.v
+end
+
+function release()
+  keep.k, keep.v = nil, nil
+end
+
+function count(t)
+  local n = 0
+  for _ in pairs(t) do n = n + 1 end
+  return n
+end
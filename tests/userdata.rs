@@ -0,0 +1,142 @@
+mod userdata {
+    use nad::{LuaError, LuaUserData, State, UserData, UserDataMethods, Value};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Debug, PartialEq)]
+    struct Vector {
+        x: f64,
+        y: f64,
+    }
+
+    impl UserData for Vector {
+        fn add_methods(methods: &mut UserDataMethods<Self>) {
+            methods.add_field_get("x", |_, v| Ok(v.x));
+            methods.add_field_get("y", |_, v| Ok(v.y));
+            methods.add_field_set("x", |_, v, x| {
+                v.x = x;
+                Ok(())
+            });
+            methods.add_method("length", |_, v, ()| Ok((v.x * v.x + v.y * v.y).sqrt()));
+            methods.add_method_mut("scale", |_, v, k: f64| {
+                v.x *= k;
+                v.y *= k;
+                Ok(())
+            });
+            methods.add_meta_method("__tostring", |_, v, ()| {
+                Ok(format!("Vector({}, {})", v.x, v.y))
+            });
+            methods.add_meta_method("__unm", |state, v, _: Value| {
+                Ok(state.create_userdata(Vector { x: -v.x, y: -v.y }))
+            });
+            methods.add_meta_function(
+                "__add",
+                |state, (a, b): (Rc<LuaUserData>, Rc<LuaUserData>)| {
+                    let (a, b) = (a.borrow::<Vector>()?, b.borrow::<Vector>()?);
+                    let v = Vector {
+                        x: a.x + b.x,
+                        y: a.y + b.y,
+                    };
+                    Ok(state.create_userdata(v))
+                },
+            );
+            methods.add_meta_method("__lt", |_, a, b: Rc<LuaUserData>| {
+                let b = b.borrow::<Vector>()?;
+                Ok(a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y)
+            });
+            methods.add_meta_function("__eq", |_, (a, b): (Rc<LuaUserData>, Rc<LuaUserData>)| {
+                Ok(*a.borrow::<Vector>()? == *b.borrow::<Vector>()?)
+            });
+        }
+    }
+
+    struct Handle {
+        closed: Rc<Cell<usize>>,
+    }
+
+    impl UserData for Handle {
+        fn add_methods(methods: &mut UserDataMethods<Self>) {
+            methods.add_meta_method("__gc", |_, h, ()| {
+                h.closed.set(h.closed.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/userdata.luac");
+        let new = state.create_function(|state, (x, y)| Ok(state.create_userdata(Vector { x, y })));
+        state.set_global("Vector", new).unwrap();
+        state
+    }
+
+    #[test]
+    fn methods_and_metamethods() {
+        let mut state = new_state();
+        state.call(0, 0).unwrap();
+
+        let ud: Rc<LuaUserData> = state.get_global("result").unwrap();
+        assert!(ud.is::<Vector>());
+        assert_eq!(*ud.borrow::<Vector>().unwrap(), Vector { x: 5.0, y: 2.0 });
+        ud.borrow_mut::<Vector>().unwrap().y = 3.0;
+        assert_eq!(ud.borrow::<Vector>().unwrap().y, 3.0);
+
+        let err = ud.borrow::<Handle>().map(|_| ()).unwrap_err();
+        assert!(matches!(
+            err,
+            LuaError::FromLua {
+                from: "userdata",
+                ..
+            }
+        ));
+        let err = state.get_global::<Rc<LuaUserData>>("Vector").unwrap_err();
+        assert!(matches!(
+            err,
+            LuaError::FromLua {
+                from: "function",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn unknown_field() {
+        let mut state = new_state();
+        let v = state.create_userdata(Vector { x: 0.0, y: 0.0 });
+        state.push_value(v);
+        state.push_value(Value::String("z".into()));
+        state.push_value(Value::Integer(1));
+        let err = state.map_set_top(-3).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "attempt to set unknown field 'z' of Vector"
+        );
+    }
+
+    #[test]
+    fn finalizer() {
+        let closed = Rc::new(Cell::new(0));
+        let mut state = State::new();
+        let h = state.create_userdata(Handle {
+            closed: closed.clone(),
+        });
+        state.set_global("h", h).unwrap();
+        let h = state.create_userdata(Handle {
+            closed: closed.clone(),
+        });
+        state.set_global("h2", h).unwrap();
+        assert_eq!(closed.get(), 0);
+
+        // finalizer runs when back to host from lua
+        state.set_global("h", Value::Nil).unwrap();
+        let tostring = state.get_global::<Value>("tostring").unwrap();
+        state.push_value(tostring);
+        state.push_value(Value::Nil);
+        state.call(1, 0).unwrap();
+        assert_eq!(closed.get(), 1);
+
+        // the others are finalized when state is closed
+        drop(state);
+        assert_eq!(closed.get(), 2);
+    }
+}
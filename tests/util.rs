@@ -6,7 +6,7 @@ pub fn iter_luac<F: FnMut(PathBuf)>(f: F) {
         .unwrap()
         .filter_map(|path| {
             let path = path.ok()?.path();
-            path.to_str()?.ends_with(".luac").then(move || path)
+            path.to_str()?.ends_with(".luac").then_some(path)
         })
         .for_each(f)
 }
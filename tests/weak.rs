@@ -0,0 +1,76 @@
+mod weak {
+    use nad::{State, Value};
+
+    fn new_state() -> State {
+        let mut state = State::from_file("tests/bytecode/weak_table.luac");
+        state.call(0, 0).unwrap();
+        state
+    }
+
+    fn counts(state: &mut State) -> Vec<i64> {
+        ["cache_k", "cache_v", "cache_kv"]
+            .iter()
+            .map(|name| {
+                let t: Value = state.get_global(name).unwrap();
+                state.call_global("count", t).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clear_unreachable() {
+        let mut state = new_state();
+        state.call_global::<_, ()>("fill", ()).unwrap();
+        assert_eq!(counts(&mut state), [4, 3, 3]);
+
+        state.gc_collect().unwrap();
+        assert_eq!(counts(&mut state), [2, 2, 1]);
+
+        state.call_global::<_, ()>("release", ()).unwrap();
+        state.gc_collect().unwrap();
+        assert_eq!(counts(&mut state), [1, 1, 0]);
+    }
+
+    #[test]
+    fn traverse_while_collecting() {
+        let mut state = new_state();
+        let cache: Value = state.get_global("cache_v").unwrap();
+        let keep: Value = state.get_global("keep").unwrap();
+        let (cache_m, keep_m) = match (&cache, &keep) {
+            (Value::Map(c), Value::Map(k)) => (c.clone(), k.clone()),
+            _ => unreachable!(),
+        };
+        for i in 0..50 {
+            let key = Value::String(format!("dead{}", i).into());
+            let val = Value::new_map(Default::default());
+            cache_m.borrow_mut().insert(key, val);
+        }
+        for i in 0..5 {
+            let key = Value::String(format!("live{}", i).into());
+            let val = Value::new_map(Default::default());
+            keep_m.borrow_mut().insert(Value::Integer(i), val.clone());
+            cache_m.borrow_mut().insert(key, val);
+        }
+        drop((cache_m, keep_m, keep));
+
+        let (mut key, _): (Value, Value) = state
+            .call_global("next", (cache.clone(), Value::Nil))
+            .unwrap();
+        state.gc_collect().unwrap();
+        let mut live = 0;
+        loop {
+            let (k, v): (Value, Value) = state.call_global("next", (cache.clone(), key)).unwrap();
+            match &k {
+                Value::String(s) => assert!(s.starts_with(b"live")),
+                Value::Nil => break,
+                _ => unreachable!(),
+            }
+            assert!(matches!(v, Value::Map(_)));
+            key = k;
+            live += 1;
+        }
+        assert!(live <= 5);
+        let n: i64 = state.call_global("count", cache).unwrap();
+        assert_eq!(n, 5);
+    }
+}